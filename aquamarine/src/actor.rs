@@ -14,20 +14,24 @@
  * limitations under the License.
  */
 
-use crate::awaited_particle::AwaitedParticle;
+use crate::awaited_particle::{AwaitedParticle, EffectsChannel};
 use crate::particle_executor::{Fut, FutResult, ParticleExecutor};
+use crate::recorder::ParticleRecorder;
+use crate::vm_pool::{LostVm, PooledVm};
 
-use aquamarine_vm::AquamarineVM;
+use libp2p::PeerId;
 use particle_protocol::Particle;
 
 use crate::error::AquamarineApiError;
 use crate::AwaitedEffects;
-use futures::FutureExt;
+use futures::{channel::oneshot, future::BoxFuture, FutureExt};
+use humantime::format_duration as pretty;
 use std::ops::Mul;
 use std::{
     collections::VecDeque,
     fmt::Debug,
     task::{Context, Poll, Waker},
+    time::Duration,
 };
 
 #[derive(Debug, Clone)]
@@ -45,6 +49,13 @@ impl Deadline {
         }
     }
 
+    /// Time in ms after which particle is expired, `None` if timestamp + ttl overflowed
+    pub fn expires_at(&self) -> Option<u64> {
+        self.timestamp
+            .checked_mul(1000)?
+            .checked_add(self.ttl as u64)
+    }
+
     pub fn is_expired(&self, now_ms: u64) -> bool {
         self.timestamp
             .mul(1000)
@@ -59,19 +70,48 @@ impl Deadline {
     }
 }
 
+/// Particle being executed on a VM
+struct Execution {
+    future: Fut,
+    /// Resolves once execution took longer than `execution_timeout`
+    timeout: BoxFuture<'static, ()>,
+    /// Generation of the VM executing the particle
+    generation: u64,
+    particle_id: String,
+    out: EffectsChannel,
+}
+
 pub struct Actor {
     /// Particle of that actor is expired after that deadline
     deadline: Deadline,
-    future: Option<Fut>,
+    /// Peer that initiated the particle, used to share interpreters fairly between peers
+    init_peer_id: PeerId,
+    /// Execution is abandoned after that time, and its VM is considered lost
+    execution_timeout: Duration,
+    /// If set, executions of the particle are recorded
+    recorder: Option<ParticleRecorder>,
+    execution: Option<Execution>,
+    /// Set while a timed out execution is still running on a lost VM, so the next particle
+    /// with the same id isn't executed concurrently with it. Resolves once that execution ends
+    lost: Option<oneshot::Receiver<()>>,
     mailbox: VecDeque<AwaitedParticle>,
     waker: Option<Waker>,
 }
 
 impl Actor {
-    pub fn new(deadline: Deadline) -> Self {
+    pub fn new(
+        deadline: Deadline,
+        init_peer_id: PeerId,
+        execution_timeout: Duration,
+        recorder: Option<ParticleRecorder>,
+    ) -> Self {
         Self {
             deadline,
-            future: None,
+            init_peer_id,
+            execution_timeout,
+            recorder,
+            execution: None,
+            lost: None,
             mailbox: <_>::default(),
             waker: <_>::default(),
         }
@@ -81,24 +121,81 @@ impl Actor {
         self.deadline.is_expired(now_ms)
     }
 
+    pub fn init_peer_id(&self) -> &PeerId {
+        &self.init_peer_id
+    }
+
+    /// Number of particles waiting for execution
+    pub fn mailbox_len(&self) -> usize {
+        self.mailbox.len()
+    }
+
+    /// Whether actor has particles to execute and isn't busy executing the previous one
+    pub fn is_ready(&self) -> bool {
+        self.execution.is_none() && self.lost.is_none() && !self.mailbox.is_empty()
+    }
+
     pub fn ingest(&mut self, particle: AwaitedParticle) {
         self.mailbox.push_back(particle);
         self.wake();
     }
 
-    /// Polls actor for result on previously ingested particle
-    pub fn poll_completed(&mut self, cx: &mut Context<'_>) -> Poll<FutResult> {
+    /// Polls actor for result on previously ingested particle.
+    /// If execution timed out, VM is lost, and execution is returned in its place:
+    /// it can't be interrupted, so it keeps running on a blocking thread, and actor
+    /// doesn't execute further particles until it finishes
+    pub fn poll_completed(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<(Result<PooledVm, LostVm>, AwaitedEffects)> {
         self.waker = Some(cx.waker().clone());
 
-        // Poll self.future
-        let future = self.future.take().map(|mut fut| (fut.poll_unpin(cx), fut));
+        // Sender is dropped either when lost execution finishes, or when pool drops it
+        if let Some(lost) = self.lost.as_mut() {
+            if lost.poll_unpin(cx).is_ready() {
+                self.lost = None;
+            }
+        }
+
+        let mut execution = match self.execution.take() {
+            Some(execution) => execution,
+            None => return Poll::Pending,
+        };
 
-        match future {
+        if let Poll::Ready(FutResult { vm, effects }) = execution.future.poll_unpin(cx) {
             // If future is ready, return effects and vm
-            Some((Poll::Ready(r), _)) => Poll::Ready(r),
-            o => {
-                // Either keep pending future or keep it None
-                self.future = o.map(|t| t.1);
+            let effects = AwaitedEffects::ok(effects, execution.out);
+            let generation = execution.generation;
+            return Poll::Ready((Ok(PooledVm { vm, generation }), effects));
+        }
+
+        match execution.timeout.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                let timeout = self.execution_timeout;
+                log::warn!(
+                    "Particle {} execution timed out after {}, interpreter is lost",
+                    execution.particle_id,
+                    pretty(timeout)
+                );
+                let err = AquamarineApiError::ExecutionTimedOut {
+                    particle_id: execution.particle_id,
+                    timeout: pretty(timeout),
+                };
+                let effects = AwaitedEffects::err(err, execution.out);
+                let (finished, lost) = oneshot::channel();
+                self.lost = Some(lost);
+                let execution_future = execution.future.map(move |result| {
+                    finished.send(()).ok();
+                    result
+                });
+                let lost = LostVm {
+                    execution: execution_future.boxed(),
+                    generation: execution.generation,
+                };
+                Poll::Ready((Err(lost), effects))
+            }
+            Poll::Pending => {
+                self.execution = Some(execution);
                 Poll::Pending
             }
         }
@@ -107,20 +204,31 @@ impl Actor {
     /// Provide actor with new `vm` to execute particles, if there are any.
     ///
     /// If actor is in the middle of executing previous particle, vm is returned
+    /// If previous execution timed out but still runs on a lost vm, vm is returned
     /// If actor's mailbox is empty, vm is returned
-    pub fn poll_next(&mut self, vm: AquamarineVM, cx: &mut Context<'_>) -> ActorPoll {
+    pub fn poll_next(&mut self, vm: PooledVm, cx: &mut Context<'_>) -> ActorPoll {
         self.waker = Some(cx.waker().clone());
 
         // Return vm if previous particle is still executing
-        if self.future.is_some() {
+        if self.execution.is_some() || self.lost.is_some() {
             return ActorPoll::Vm(vm);
         }
 
         match self.mailbox.pop_front() {
             Some(p) if !p.is_expired() => {
                 // Take ownership of vm to process particle
-                // TODO: add timeout for execution
-                self.future = vm.execute(p, cx.waker().clone()).into();
+                let (particle, out) = p.into();
+                let particle_id = particle.id.clone();
+                let PooledVm { vm, generation } = vm;
+                let future = vm.execute(particle, cx.waker().clone(), self.recorder.clone());
+                let timeout = async_std::task::sleep(self.execution_timeout).boxed();
+                self.execution = Some(Execution {
+                    future,
+                    timeout,
+                    generation,
+                    particle_id,
+                    out,
+                });
                 ActorPoll::Executing
             }
             Some(p) => {
@@ -144,6 +252,46 @@ impl Actor {
 
 pub enum ActorPoll {
     Executing,
-    Vm(AquamarineVM),
-    Expired(AwaitedEffects, AquamarineVM),
+    Vm(PooledVm),
+    Expired(AwaitedEffects, PooledVm),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Actor, Deadline};
+    use crate::AwaitedParticle;
+
+    use libp2p::PeerId;
+    use particle_protocol::Particle;
+
+    use futures::channel::oneshot;
+    use futures::task::noop_waker_ref;
+    use std::task::Context;
+    use std::time::Duration;
+
+    #[test]
+    fn lost_execution_blocks_mailbox() {
+        let particle = Particle {
+            id: "1".to_string(),
+            ttl: 1000,
+            ..<_>::default()
+        };
+        let (out, _) = oneshot::channel();
+        let particle = AwaitedParticle { particle, out };
+        let deadline = Deadline::from(&particle);
+        let mut actor = Actor::new(deadline, PeerId::random(), Duration::from_secs(1), None);
+        actor.ingest(particle);
+        assert!(actor.is_ready());
+
+        // while timed out execution is running, next particle isn't executed
+        let (finished, lost) = oneshot::channel::<()>();
+        actor.lost = Some(lost);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(actor.poll_completed(&mut cx).is_pending());
+        assert!(!actor.is_ready());
+
+        finished.send(()).unwrap();
+        assert!(actor.poll_completed(&mut cx).is_pending());
+        assert!(actor.is_ready());
+    }
 }
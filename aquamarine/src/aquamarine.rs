@@ -13,8 +13,11 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use crate::actor::Deadline;
 use crate::awaited_particle::EffectsChannel;
 use crate::error::AquamarineApiError;
+use crate::interpreter::InterpreterReloads;
+use crate::sweeper::ParticleDataSweeper;
 use crate::{AwaitedEffects, AwaitedParticle, Plumber, StepperEffects, VmPoolConfig};
 
 use fluence_libp2p::types::{BackPressuredInlet, BackPressuredOutlet};
@@ -28,6 +31,7 @@ use futures::{
     FutureExt, SinkExt, StreamExt,
 };
 use humantime::format_duration as pretty;
+use prometheus::Registry;
 use std::convert::identity;
 use std::task::Poll;
 use std::time::Duration;
@@ -35,14 +39,26 @@ use std::time::Duration;
 pub struct AquamarineBackend {
     inlet: BackPressuredInlet<(Particle, EffectsChannel)>,
     plumber: Plumber,
+    /// Removes interpreter data of expired particles
+    sweeper: ParticleDataSweeper,
 }
 
 impl AquamarineBackend {
-    pub fn new(config: VmPoolConfig, host_closures: ClosureDescriptor) -> (Self, AquamarineApi) {
+    pub fn new(
+        config: VmPoolConfig,
+        host_closures: ClosureDescriptor,
+        reloads: InterpreterReloads,
+        registry: Option<&Registry>,
+    ) -> (Self, AquamarineApi) {
         let (outlet, inlet) = mpsc::channel(100);
         let sender = AquamarineApi::new(outlet, config.execution_timeout);
-        let plumber = Plumber::new(config, host_closures);
-        let this = Self { inlet, plumber };
+        let sweeper = ParticleDataSweeper::new(&config, registry);
+        let plumber = Plumber::new(config, host_closures, reloads, registry);
+        let this = Self {
+            inlet,
+            plumber,
+            sweeper,
+        };
 
         (this, sender)
     }
@@ -53,8 +69,10 @@ impl AquamarineBackend {
         // check if there are new particles
         while let Poll::Ready(Some((particle, out))) = self.inlet.poll_next_unpin(cx) {
             wake = true;
+            let particle = AwaitedParticle { particle, out };
+            self.sweeper.track(&particle.id, Deadline::from(&particle));
             // set new particles to be executed
-            self.plumber.ingest(AwaitedParticle { particle, out });
+            self.plumber.ingest(particle);
         }
 
         // check if there are executed particles
@@ -64,6 +82,8 @@ impl AquamarineBackend {
             out.send(effects).ok();
         }
 
+        self.sweeper.poll(cx);
+
         if wake {
             Poll::Ready(())
         } else {
@@ -30,10 +30,47 @@ pub struct VmPoolConfig {
     pub services_dir: PathBuf,
     /// Dir for stepper to persist particle data to merge it
     pub particles_dir: PathBuf,
-    /// Number of VMs to create
+    /// Max number of VMs, pool grows up to that when particles wait for a VM
     pub pool_size: usize,
+    /// Number of VMs that are always kept in the pool
+    pub min_pool_size: usize,
+    /// VMs above `min_pool_size` are removed after being unused for that long
+    pub vm_idle_timeout: Duration,
     /// Timeout of a particle execution
     pub execution_timeout: Duration,
+    /// Max number of timed out executions whose VMs are replaced while they keep running
+    pub max_lost_vms: usize,
+    /// Max number of particles waiting for execution with the same particle id
+    pub mailbox_size: usize,
+    /// Max number of particles waiting for execution in all mailboxes
+    pub max_queued_particles: usize,
+    /// Max number of accepted particles remembered until their deadline to drop their exact copies
+    pub seen_particles_limit: usize,
+    /// Particle data that wasn't modified for that long is removed, even if particle isn't expired
+    pub particle_data_max_age: Duration,
+    /// Max total size of `particles_dir`, least recently modified data is removed above that
+    pub particle_data_max_size: u64,
+    /// If set, every particle execution is recorded there, so it can be replayed later
+    pub recordings_dir: Option<PathBuf>,
+    /// If set, `air_interpreter` is checked for modifications that often, and reloaded
+    pub interpreter_watch_interval: Option<Duration>,
+}
+
+/// Settings of `VmPoolConfig` that don't depend on node's dirs, see `VmPoolConfig` for docs
+#[derive(Debug, Clone)]
+pub struct VmPoolSettings {
+    pub pool_size: usize,
+    pub min_pool_size: usize,
+    pub vm_idle_timeout: Duration,
+    pub execution_timeout: Duration,
+    pub max_lost_vms: usize,
+    pub mailbox_size: usize,
+    pub max_queued_particles: usize,
+    pub seen_particles_limit: usize,
+    pub particle_data_max_age: Duration,
+    pub particle_data_max_size: u64,
+    pub recordings_dir: Option<PathBuf>,
+    pub interpreter_watch_interval: Option<Duration>,
 }
 
 impl VmPoolConfig {
@@ -41,10 +78,23 @@ impl VmPoolConfig {
         current_peer_id: PeerId,
         base_dir: PathBuf,
         air_interpreter: PathBuf,
-        pool_size: usize,
-        execution_timeout: Duration,
+        settings: VmPoolSettings,
     ) -> Result<Self, std::io::Error> {
         let base_dir = to_abs_path(base_dir);
+        let VmPoolSettings {
+            pool_size,
+            min_pool_size,
+            vm_idle_timeout,
+            execution_timeout,
+            max_lost_vms,
+            mailbox_size,
+            max_queued_particles,
+            seen_particles_limit,
+            particle_data_max_age,
+            particle_data_max_size,
+            recordings_dir,
+            interpreter_watch_interval,
+        } = settings;
 
         let this = Self {
             current_peer_id,
@@ -53,7 +103,17 @@ impl VmPoolConfig {
             particles_dir: config_utils::particles_dir(&base_dir),
             air_interpreter,
             pool_size,
+            min_pool_size: min_pool_size.min(pool_size),
+            vm_idle_timeout,
             execution_timeout,
+            max_lost_vms,
+            mailbox_size,
+            max_queued_particles,
+            seen_particles_limit,
+            particle_data_max_age,
+            particle_data_max_size,
+            recordings_dir: recordings_dir.map(to_abs_path),
+            interpreter_watch_interval,
         };
 
         this.create_dirs()?;
@@ -40,6 +40,18 @@ pub enum AquamarineApiError {
         particle_id: String,
         timeout: FormattedDuration,
     },
+    #[error(
+        "AquamarineApiError::MailboxFull: particle_id = {particle_id}, mailbox is limited to {limit} particles"
+    )]
+    MailboxFull { particle_id: String, limit: usize },
+    #[error(
+        "AquamarineApiError::QueueFull: particle_id = {particle_id}, no more than {limit} particles can wait for execution"
+    )]
+    QueueFull { particle_id: String, limit: usize },
+    #[error(
+        "AquamarineApiError::DuplicateParticle: particle_id = {particle_id}, the same particle was already received"
+    )]
+    DuplicateParticle { particle_id: String },
 }
 
 impl AquamarineApiError {
@@ -49,6 +61,9 @@ impl AquamarineApiError {
             AquamarineApiError::OneshotCancelled { particle_id } => particle_id,
             AquamarineApiError::AquamarineDied { particle_id } => particle_id,
             AquamarineApiError::ExecutionTimedOut { particle_id, .. } => particle_id,
+            AquamarineApiError::MailboxFull { particle_id, .. } => particle_id,
+            AquamarineApiError::QueueFull { particle_id, .. } => particle_id,
+            AquamarineApiError::DuplicateParticle { particle_id } => particle_id,
         }
     }
 }
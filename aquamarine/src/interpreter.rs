@@ -0,0 +1,207 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Interpreter VMs are created from the `air_interpreter` wasm file. It can be replaced
+//! without restart: either on request through `InterpreterApi`, or when the file is modified.
+//! `VmPool` then creates a new generation of VMs, see `VmPool::poll`.
+
+use crate::config::VmPoolConfig;
+
+use config_utils::to_abs_path;
+
+use async_std::stream;
+use futures::{channel::mpsc, StreamExt};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// Interpreter that VMs are currently created from
+#[derive(Debug, Clone, Serialize)]
+pub struct InterpreterInfo {
+    /// Not serialized, so node's filesystem layout isn't exposed to peers
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Version taken from the file name, if it's named like `aquamarine_{version}.wasm`
+    pub version: Option<String>,
+    /// Hex of blake3 hash of the wasm, tells apart interpreters with the same file name
+    pub hash: Option<String>,
+    /// Incremented each time VMs are recreated from a new interpreter
+    pub generation: u64,
+}
+
+impl InterpreterInfo {
+    /// Reads and hashes interpreter wasm, so it's better called on a blocking threadpool
+    pub fn read(path: PathBuf, generation: u64) -> std::io::Result<Self> {
+        let wasm = std::fs::read(&path)?;
+        let hash = blake3::hash(&wasm).to_hex().to_string();
+        Ok(Self {
+            version: version(&path),
+            hash: Some(hash),
+            path,
+            generation,
+        })
+    }
+}
+
+fn version(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    let version = name.strip_prefix("aquamarine_")?.strip_suffix(".wasm")?;
+    Some(version.to_string()).filter(|v| !v.is_empty())
+}
+
+#[derive(Debug, Error)]
+pub enum ReloadError {
+    #[error("Error reading interpreter {path:?}: {err}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Error creating VM from interpreter {path:?}: {err}")]
+    CreateVm {
+        path: PathBuf,
+        err: aquamarine_vm::AquamarineVMError,
+    },
+    #[error("Interpreter can't be reloaded: aquamarine is stopped")]
+    Stopped,
+}
+
+/// Tells which interpreter is in use, and requests to replace it
+#[derive(Debug, Clone)]
+pub struct InterpreterApi {
+    current: Arc<RwLock<InterpreterInfo>>,
+    outlet: mpsc::UnboundedSender<PathBuf>,
+}
+
+impl InterpreterApi {
+    pub fn current(&self) -> InterpreterInfo {
+        self.current.read().clone()
+    }
+
+    /// Requests VMs to be recreated from the interpreter at `path`, or from the current
+    /// interpreter file if `path` isn't specified. Returns absolute path of the interpreter.
+    pub fn reload(&self, path: Option<PathBuf>) -> Result<PathBuf, ReloadError> {
+        let path = match path {
+            Some(path) => to_abs_path(path),
+            None => self.current.read().path.clone(),
+        };
+        if let Err(err) = std::fs::metadata(&path) {
+            return Err(ReloadError::Read { path, err });
+        }
+
+        self.outlet
+            .unbounded_send(path.clone())
+            .map_err(|_| ReloadError::Stopped)?;
+
+        Ok(path)
+    }
+}
+
+/// Receives reload requests, and watches the interpreter file for modifications
+pub struct InterpreterReloads {
+    current: Arc<RwLock<InterpreterInfo>>,
+    inlet: mpsc::UnboundedReceiver<PathBuf>,
+    /// Interpreter file is checked on each tick, if `interpreter_watch_interval` is set
+    watch: Option<stream::Interval>,
+    /// Modification time of the interpreter file, as of the last reload
+    modified: Option<SystemTime>,
+}
+
+impl InterpreterReloads {
+    pub fn new(config: &VmPoolConfig) -> (Self, InterpreterApi) {
+        let path = config.air_interpreter.clone();
+        let current = InterpreterInfo::read(path.clone(), 0).unwrap_or_else(|err| {
+            log::warn!("Unable to read interpreter {:?}: {}", path, err);
+            InterpreterInfo {
+                version: version(&path),
+                hash: None,
+                path,
+                generation: 0,
+            }
+        });
+        let modified = modified(&current.path);
+        let current = Arc::new(RwLock::new(current));
+
+        let (outlet, inlet) = mpsc::unbounded();
+        let api = InterpreterApi {
+            current: current.clone(),
+            outlet,
+        };
+        let this = Self {
+            current,
+            inlet,
+            watch: config.interpreter_watch_interval.map(stream::interval),
+            modified,
+        };
+
+        (this, api)
+    }
+
+    pub fn current(&self) -> InterpreterInfo {
+        self.current.read().clone()
+    }
+
+    /// Makes `info` current, called once VMs are created from it
+    pub fn set_current(&mut self, info: InterpreterInfo) {
+        self.modified = modified(&info.path);
+        *self.current.write() = info;
+    }
+
+    /// Returns path of the interpreter to reload, if it was requested or the file was modified
+    pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<PathBuf> {
+        if let Poll::Ready(Some(path)) = self.inlet.poll_next_unpin(cx) {
+            return Poll::Ready(path);
+        }
+
+        if let Some(watch) = self.watch.as_mut() {
+            if let Poll::Ready(Some(_)) = watch.poll_next_unpin(cx) {
+                let path = self.current.read().path.clone();
+                let modified = modified(&path);
+                if modified.is_some() && modified != self.modified {
+                    log::info!("Interpreter {:?} was modified, reloading", path);
+                    self.modified = modified;
+                    return Poll::Ready(path);
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::version;
+    use std::path::Path;
+
+    #[test]
+    fn version_from_file_name() {
+        let version = |path: &str| version(Path::new(path));
+
+        assert_eq!(version("./aquamarine_0.7.3.wasm").as_deref(), Some("0.7.3"));
+        assert_eq!(version("/tmp/aquamarine_.wasm"), None);
+        assert_eq!(version("/tmp/air.wasm"), None);
+        assert_eq!(version("aquamarine_0.7.3.wat"), None);
+    }
+}
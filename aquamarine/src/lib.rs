@@ -31,14 +31,22 @@ mod aquamarine;
 mod awaited_particle;
 mod config;
 mod error;
+mod interpreter;
 mod invoke;
 mod outcome;
 mod particle_executor;
 mod plumber;
+mod recorder;
+mod seen;
+mod sweeper;
 mod vm_pool;
 
 pub use crate::aquamarine::{AquamarineApi, AquamarineBackend};
 pub use awaited_particle::{AwaitedEffects, AwaitedParticle};
-pub use config::VmPoolConfig;
+pub use config::{VmPoolConfig, VmPoolSettings};
+pub use interpreter::{InterpreterApi, InterpreterInfo, InterpreterReloads, ReloadError};
 pub use outcome::{SendParticle, StepperEffects};
 pub use plumber::Plumber;
+pub use recorder::{
+    load_recording, replay, ExecutionRecord, HostCall, RecordedValue, RecordingError,
+};
@@ -14,9 +14,9 @@
  * limitations under the License.
  */
 
-use crate::awaited_particle::AwaitedParticle;
 use crate::invoke::{parse_outcome, ExecutionError};
-use crate::{AwaitedEffects, SendParticle, StepperEffects};
+use crate::recorder::ParticleRecorder;
+use crate::{SendParticle, StepperEffects};
 use aquamarine_vm::{AquamarineVM, AquamarineVMError, InterpreterOutcome};
 use particle_protocol::Particle;
 
@@ -31,7 +31,12 @@ pub(super) type Fut = BoxFuture<'static, FutResult>;
 pub trait ParticleExecutor {
     type Future;
     type Particle;
-    fn execute(self, p: Self::Particle, waker: Waker) -> Self::Future;
+    fn execute(
+        self,
+        p: Self::Particle,
+        waker: Waker,
+        recorder: Option<ParticleRecorder>,
+    ) -> Self::Future;
 }
 
 /// Result of a particle execution along a VM that has just executed the particle
@@ -39,35 +44,36 @@ pub struct FutResult {
     /// AquamarineVM that just executed a particle
     pub vm: AquamarineVM,
     /// Effects produced by particle execution
-    pub effects: AwaitedEffects,
+    pub effects: StepperEffects,
 }
 
 impl ParticleExecutor for AquamarineVM {
     type Future = Fut;
-    type Particle = AwaitedParticle;
+    type Particle = Particle;
 
-    fn execute(mut self, p: AwaitedParticle, waker: Waker) -> Fut {
+    fn execute(mut self, p: Particle, waker: Waker, recorder: Option<ParticleRecorder>) -> Fut {
         task::spawn_blocking(move || {
             let now = Instant::now();
             log::info!("Executing particle {}", p.id);
 
-            let (p, out) = p.into();
-
+            let prev_data = recorder.as_ref().map(|r| r.prev_data(&p.id));
             let init_peer_id = p.init_peer_id.to_string();
             let result = self.call(init_peer_id, &p.script, p.data.clone(), &p.id);
+            if let (Some(recorder), Some(prev_data)) = (recorder, prev_data) {
+                if let Err(err) = recorder.record(&p, prev_data, &result) {
+                    log::warn!("Failed to record execution of particle {}: {}", p.id, err);
+                }
+            }
             if let Err(err) = &result {
                 log::warn!("Error executing particle {:#?}: {}", p, err)
             } else {
                 log::trace!(target: "network", "Particle {} executed in {}", p.id, pretty(now.elapsed()));
             }
-            let effects = Ok(into_effects(result, p));
+            let effects = into_effects(result, p);
 
             waker.wake();
 
-            FutResult {
-                vm: self,
-                effects: AwaitedEffects { effects, out },
-            }
+            FutResult { vm: self, effects }
         })
         .boxed()
     }
@@ -16,13 +16,20 @@
 
 use crate::actor::{Actor, ActorPoll, Deadline};
 use crate::config::VmPoolConfig;
+use crate::error::AquamarineApiError::{DuplicateParticle, MailboxFull, QueueFull};
+use crate::interpreter::InterpreterReloads;
+use crate::recorder::ParticleRecorder;
+use crate::seen::{SeenKey, SeenParticles};
 
 use host_closure::ClosureDescriptor;
 
 use crate::vm_pool::VmPool;
+use libp2p::PeerId;
+use prometheus::Registry;
 use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
     task::{Context, Poll},
+    time::Duration,
 };
 
 /// Get current time from OS
@@ -38,17 +45,54 @@ use mock_time::now_ms;
 pub struct Plumber {
     events: VecDeque<AwaitedEffects>,
     actors: HashMap<String, Actor>,
+    /// Initiators of the particles in `actors`. Interpreters are given to them in turns,
+    /// so a single peer can't occupy all of them
+    initiators: VecDeque<PeerId>,
     vm_pool: VmPool,
+    /// Max number of particles in a single actor's mailbox
+    mailbox_size: usize,
+    /// Max number of particles in all mailboxes
+    max_queued_particles: usize,
+    /// Max duration of a single particle execution
+    execution_timeout: Duration,
+    /// Records particle executions, if `recordings_dir` is configured
+    recorder: Option<ParticleRecorder>,
+    /// Accepted particles along with their data, kept until their deadline. Exact copies
+    /// are dropped, while revisits carry updated data and go to the actor's mailbox
+    seen: SeenParticles,
     waker: Option<Waker>,
 }
 
 impl Plumber {
-    pub fn new(config: VmPoolConfig, host_closure: ClosureDescriptor) -> Self {
-        let vm_pool = VmPool::new(config, host_closure);
+    pub fn new(
+        config: VmPoolConfig,
+        host_closure: ClosureDescriptor,
+        reloads: InterpreterReloads,
+        registry: Option<&Registry>,
+    ) -> Self {
+        let mailbox_size = config.mailbox_size;
+        let max_queued_particles = config.max_queued_particles;
+        let execution_timeout = config.execution_timeout;
+        let recorder = config.recordings_dir.clone().map(|dir| {
+            let peer_id = config.current_peer_id.to_string();
+            ParticleRecorder::new(dir, config.particles_dir.clone(), peer_id)
+        });
+        let host_closure = match &recorder {
+            Some(recorder) => recorder.wrap(host_closure),
+            None => host_closure,
+        };
+        let seen = SeenParticles::new(config.seen_particles_limit, registry);
+        let vm_pool = VmPool::new(config, host_closure, reloads, registry);
         Self {
             vm_pool,
             events: <_>::default(),
             actors: <_>::default(),
+            initiators: <_>::default(),
+            mailbox_size,
+            max_queued_particles,
+            execution_timeout,
+            recorder,
+            seen,
             waker: <_>::default(),
         }
     }
@@ -64,9 +108,72 @@ impl Plumber {
             return;
         }
 
-        match self.actors.entry(particle.id.clone()) {
-            Entry::Vacant(entry) => entry.insert(Actor::new(deadline)).ingest(particle),
-            Entry::Occupied(mut entry) => entry.get_mut().ingest(particle),
+        let key = SeenKey::new(&particle);
+        if self.seen.is_duplicate(&key) {
+            log::warn!("Particle {} was already received, ignoring", particle.id);
+            let (particle, out) = particle.into();
+            let err = DuplicateParticle {
+                particle_id: particle.id,
+            };
+            self.events.push_back(AwaitedEffects::err(err, out));
+            return;
+        }
+
+        let queued: usize = self.actors.values().map(Actor::mailbox_len).sum();
+        if queued >= self.max_queued_particles {
+            log::warn!(
+                "Particle {} rejected: {} particles are waiting for execution",
+                particle.id,
+                queued
+            );
+            let (particle, out) = particle.into();
+            let limit = self.max_queued_particles;
+            let err = QueueFull {
+                particle_id: particle.id,
+                limit,
+            };
+            self.events.push_back(AwaitedEffects::err(err, out));
+            return;
+        }
+
+        let accepted = match self.actors.entry(particle.id.clone()) {
+            Entry::Vacant(entry) => {
+                let init_peer_id = particle.init_peer_id;
+                if !self.initiators.contains(&init_peer_id) {
+                    self.initiators.push_back(init_peer_id);
+                }
+                entry
+                    .insert(Actor::new(
+                        deadline,
+                        init_peer_id,
+                        self.execution_timeout,
+                        self.recorder.clone(),
+                    ))
+                    .ingest(particle);
+                true
+            }
+            Entry::Occupied(entry) if entry.get().mailbox_len() >= self.mailbox_size => {
+                log::warn!("Particle {} rejected: mailbox is full", particle.id);
+                let (particle, out) = particle.into();
+                let limit = self.mailbox_size;
+                let err = MailboxFull {
+                    particle_id: particle.id,
+                    limit,
+                };
+                self.events.push_back(AwaitedEffects::err(err, out));
+                false
+            }
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().ingest(particle);
+                true
+            }
+        };
+
+        // Only accepted particles are remembered, so rejected ones can be re-sent.
+        // Deadline is known to be valid here, as particle isn't expired
+        if accepted {
+            let expires_at = deadline.expires_at().unwrap_or_default();
+            self.seen.insert(key, expires_at);
         }
     }
 
@@ -79,22 +186,46 @@ impl Plumber {
             return Poll::Ready(event);
         }
 
-        // Remove expired actors
+        // Remove expired actors, and initiators that have no actors left
         let now = now_ms();
         self.actors.retain(|_, actor| !actor.is_expired(now));
+        self.seen.remove_expired(now);
+        let alive: HashSet<_> = self.actors.values().map(Actor::init_peer_id).collect();
+        self.initiators.retain(|peer_id| alive.contains(peer_id));
 
         // Gather effects and put VMs back
         let mut effects = vec![];
         for actor in self.actors.values_mut() {
-            if let Poll::Ready(result) = actor.poll_completed(cx) {
-                effects.push(result.effects);
-                self.vm_pool.put_vm(result.vm);
+            if let Poll::Ready((vm, result)) = actor.poll_completed(cx) {
+                effects.push(result);
+                match vm {
+                    Ok(vm) => self.vm_pool.put_vm(vm),
+                    // execution timed out, its vm is unavailable until execution finishes
+                    Err(lost) => self.vm_pool.recreate_vm(lost, cx),
+                }
             }
         }
 
         // Execute next messages
-        for actor in self.actors.values_mut() {
-            if let Some(vm) = self.vm_pool.get_vm() {
+        let mut ready: HashMap<PeerId, VecDeque<String>> = HashMap::new();
+        for (particle_id, actor) in self.actors.iter() {
+            if actor.is_ready() {
+                let ids = ready.entry(*actor.init_peer_id()).or_default();
+                ids.push_back(particle_id.clone());
+            }
+        }
+        let free_vms = self.vm_pool.free_vms();
+        let ready_count: usize = ready.values().map(VecDeque::len).sum();
+        let scheduled = schedule(&mut self.initiators, ready, free_vms);
+        // Grow the pool for particles that didn't get a VM
+        let waiting = ready_count - scheduled.len();
+        if waiting > 0 {
+            self.vm_pool.grow(waiting, cx);
+        }
+        for particle_id in scheduled {
+            let actor = self.actors.get_mut(&particle_id);
+            let vm = self.vm_pool.get_vm();
+            if let (Some(actor), Some(vm)) = (actor, vm) {
                 match actor.poll_next(vm, cx) {
                     ActorPoll::Vm(vm) => self.vm_pool.put_vm(vm),
                     ActorPoll::Expired(es, vm) => {
@@ -103,8 +234,6 @@ impl Plumber {
                     }
                     ActorPoll::Executing => {}
                 }
-            } else {
-                log::debug!("No more free Aquamarine interpreters");
             }
         }
 
@@ -128,8 +257,36 @@ impl Plumber {
     }
 }
 
+/// Picks up to `free_vms` particles to execute, taking one particle from each initiator in turn.
+/// Initiators that got a particle scheduled are moved to the back of the queue,
+/// so the next round starts with those who didn't.
+fn schedule(
+    initiators: &mut VecDeque<PeerId>,
+    mut ready: HashMap<PeerId, VecDeque<String>>,
+    free_vms: usize,
+) -> Vec<String> {
+    let mut scheduled = vec![];
+    // number of initiators in a row that had nothing to execute
+    let mut idle = 0;
+    while scheduled.len() < free_vms && idle < initiators.len() {
+        let initiator = initiators
+            .pop_front()
+            .expect("initiators can't be empty here");
+        match ready.get_mut(&initiator).and_then(|ids| ids.pop_front()) {
+            Some(particle_id) => {
+                scheduled.push(particle_id);
+                idle = 0;
+            }
+            None => idle += 1,
+        }
+        initiators.push_back(initiator);
+    }
+
+    scheduled
+}
+
 /// Implements `now` by taking number of non-leap seconds from `Utc::now()`
-mod real_time {
+pub(crate) mod real_time {
     #[allow(dead_code)]
     pub fn now_ms() -> u64 {
         (chrono::Utc::now().timestamp() * 1000) as u64
@@ -229,3 +386,177 @@ pub mod mock_time {
         MOCK_TIME.with(|cell| *cell.borrow_mut() = time);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::AquamarineApiError::{self, DuplicateParticle, MailboxFull, QueueFull};
+    use crate::plumber::mock_time::set_mock_time;
+    use crate::plumber::schedule;
+    use crate::{AwaitedParticle, InterpreterReloads, Plumber, VmPoolConfig};
+
+    use libp2p::PeerId;
+    use particle_protocol::Particle;
+
+    use futures::channel::oneshot;
+    use futures::task::noop_waker_ref;
+    use std::collections::{HashMap, VecDeque};
+    use std::task::Context;
+    use std::{sync::Arc, time::Duration};
+
+    fn plumber(mailbox_size: usize, max_queued_particles: usize) -> Plumber {
+        let config = VmPoolConfig {
+            current_peer_id: PeerId::random(),
+            workdir: <_>::default(),
+            air_interpreter: <_>::default(),
+            services_dir: <_>::default(),
+            particles_dir: <_>::default(),
+            pool_size: 0,
+            min_pool_size: 0,
+            vm_idle_timeout: Duration::from_secs(1),
+            execution_timeout: Duration::from_secs(1),
+            max_lost_vms: 0,
+            mailbox_size,
+            max_queued_particles,
+            seen_particles_limit: 100,
+            particle_data_max_age: Duration::from_secs(1),
+            particle_data_max_size: 0,
+            recordings_dir: None,
+            interpreter_watch_interval: None,
+        };
+        let host_closure = Arc::new(|| panic!("no host_closure no no no"));
+        let (reloads, _) = InterpreterReloads::new(&config);
+        Plumber::new(config, host_closure, reloads, None)
+    }
+
+    fn particle(id: &str) -> AwaitedParticle {
+        let particle = Particle {
+            id: id.to_string(),
+            ttl: 1000,
+            ..<_>::default()
+        };
+        let (out, _) = oneshot::channel();
+        AwaitedParticle { particle, out }
+    }
+
+    #[test]
+    fn mailbox_limits() {
+        let mut plumber = plumber(2, 3);
+
+        // revisits of the same particle carry different data
+        let first = particle("1");
+        plumber.ingest(copy(&first, b"a"));
+        plumber.ingest(copy(&first, b"b"));
+        plumber.ingest(copy(&first, b"c"));
+        let rejected = plumber.events.pop_front().map(|e| e.effects);
+        assert!(matches!(rejected, Some(Err(MailboxFull { limit: 2, .. }))));
+
+        plumber.ingest(particle("2"));
+        plumber.ingest(particle("3"));
+        let rejected = plumber.events.pop_front().map(|e| e.effects);
+        assert!(matches!(rejected, Some(Err(QueueFull { limit: 3, .. }))));
+        assert!(plumber.events.is_empty());
+    }
+
+    fn copy(particle: &AwaitedParticle, data: &[u8]) -> AwaitedParticle {
+        let (out, _) = oneshot::channel();
+        let particle = Particle {
+            data: data.to_vec(),
+            ..particle.particle.clone()
+        };
+        AwaitedParticle { particle, out }
+    }
+
+    #[test]
+    fn drop_duplicates() {
+        set_mock_time(0);
+        let mut plumber = plumber(10, 10);
+
+        let original = particle("1");
+        let (replay, revisit) = (copy(&original, b""), copy(&original, b"revisit"));
+        let late_replay = copy(&original, b"");
+        plumber.ingest(original);
+        // copy with the same data is dropped, even while actor is alive
+        plumber.ingest(replay);
+        let rejected = plumber.events.pop_front().map(|e| e.effects);
+        assert!(matches!(rejected, Some(Err(DuplicateParticle { .. }))));
+        // revisits carry updated data
+        plumber.ingest(revisit);
+        assert!(plumber.events.is_empty());
+        assert_eq!(plumber.actors["1"].mailbox_len(), 2);
+
+        // once particle is past its deadline, actor is removed and copies are dropped as expired
+        set_mock_time(2000);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        while plumber.poll(&mut cx).is_ready() {}
+        assert!(plumber.actors.is_empty());
+        plumber.ingest(late_replay);
+        let rejected = plumber.events.pop_front().map(|e| e.effects);
+        assert!(matches!(
+            rejected,
+            Some(Err(AquamarineApiError::ParticleExpired { .. }))
+        ));
+    }
+
+    #[test]
+    fn rejected_particle_can_be_resent() {
+        let mut plumber = plumber(10, 1);
+
+        plumber.ingest(particle("1"));
+        let rejected = particle("2");
+        let resent = copy(&rejected, b"");
+        plumber.ingest(rejected);
+        let rejected = plumber.events.pop_front().map(|e| e.effects);
+        assert!(matches!(rejected, Some(Err(QueueFull { .. }))));
+
+        plumber.actors.clear();
+        plumber.ingest(resent);
+        assert!(plumber.events.is_empty());
+        assert_eq!(plumber.actors.len(), 1);
+    }
+
+    fn ready(particles: &[(PeerId, usize)]) -> HashMap<PeerId, VecDeque<String>> {
+        particles
+            .iter()
+            .map(|(peer_id, n)| {
+                let ids = (0..*n).map(|i| format!("{}-{}", peer_id, i)).collect();
+                (*peer_id, ids)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn noisy_peer_doesnt_starve_others() {
+        let (noisy, quiet1, quiet2) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut initiators: VecDeque<_> = vec![noisy, quiet1, quiet2].into();
+
+        let particles = ready(&[(noisy, 100), (quiet1, 1), (quiet2, 1)]);
+        let scheduled = schedule(&mut initiators, particles, 4);
+        assert_eq!(
+            scheduled,
+            vec![
+                format!("{}-0", noisy),
+                format!("{}-0", quiet1),
+                format!("{}-0", quiet2),
+                format!("{}-1", noisy),
+            ]
+        );
+    }
+
+    #[test]
+    fn turns_carry_over() {
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let mut initiators: VecDeque<_> = vec![first, second].into();
+
+        // only one interpreter is free, so first peer gets it
+        let scheduled = schedule(&mut initiators, ready(&[(first, 2), (second, 2)]), 1);
+        assert_eq!(scheduled, vec![format!("{}-0", first)]);
+
+        // next time it's the second peer's turn
+        let scheduled = schedule(&mut initiators, ready(&[(first, 2), (second, 2)]), 1);
+        assert_eq!(scheduled, vec![format!("{}-0", second)]);
+
+        // nothing is scheduled when there are no free interpreters or nothing to execute
+        assert!(schedule(&mut initiators, ready(&[(first, 2)]), 0).is_empty());
+        assert!(schedule(&mut initiators, ready(&[]), 10).is_empty());
+    }
+}
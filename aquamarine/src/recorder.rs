@@ -0,0 +1,407 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Particle executions can be recorded to disk, and then replayed offline.
+//!
+//! Each execution of a particle is appended to `{recordings_dir}/{particle_id}.jsonl` as a line
+//! of JSON, along with the data interpreter had from previous executions, and every host call
+//! it has made.
+//! Replay runs the interpreter on the same inputs, answering host calls with the recorded results.
+
+use aquamarine_vm::{
+    ne_vec::NEVec, AquamarineVM, AquamarineVMConfig, AquamarineVMError, IValue, InterpreterOutcome,
+    ParticleParameters,
+};
+use host_closure::ClosureDescriptor;
+use json_utils::base64_serde;
+use particle_protocol::Particle;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Serializable counterpart of `IValue`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RecordedValue {
+    S8(i8),
+    S16(i16),
+    S32(i32),
+    S64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    I32(i32),
+    I64(i64),
+    Array(Vec<RecordedValue>),
+    Record(Vec<RecordedValue>),
+}
+
+impl From<IValue> for RecordedValue {
+    fn from(v: IValue) -> Self {
+        use RecordedValue::*;
+
+        match v {
+            IValue::S8(v) => S8(v),
+            IValue::S16(v) => S16(v),
+            IValue::S32(v) => S32(v),
+            IValue::S64(v) => S64(v),
+            IValue::U8(v) => U8(v),
+            IValue::U16(v) => U16(v),
+            IValue::U32(v) => U32(v),
+            IValue::U64(v) => U64(v),
+            IValue::F32(v) => F32(v),
+            IValue::F64(v) => F64(v),
+            IValue::String(v) => String(v),
+            IValue::I32(v) => I32(v),
+            IValue::I64(v) => I64(v),
+            IValue::Array(v) => Array(v.into_iter().map(Into::into).collect()),
+            IValue::Record(v) => Record(v.into_vec().into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl RecordedValue {
+    /// Returns None for an empty record, since `IValue::Record` can't be empty
+    pub fn into_ivalue(self) -> Option<IValue> {
+        use RecordedValue::*;
+
+        let value = match self {
+            S8(v) => IValue::S8(v),
+            S16(v) => IValue::S16(v),
+            S32(v) => IValue::S32(v),
+            S64(v) => IValue::S64(v),
+            U8(v) => IValue::U8(v),
+            U16(v) => IValue::U16(v),
+            U32(v) => IValue::U32(v),
+            U64(v) => IValue::U64(v),
+            F32(v) => IValue::F32(v),
+            F64(v) => IValue::F64(v),
+            String(v) => IValue::String(v),
+            I32(v) => IValue::I32(v),
+            I64(v) => IValue::I64(v),
+            Array(v) => IValue::Array(
+                v.into_iter()
+                    .map(Self::into_ivalue)
+                    .collect::<Option<_>>()?,
+            ),
+            Record(v) => {
+                let fields = v
+                    .into_iter()
+                    .map(Self::into_ivalue)
+                    .collect::<Option<_>>()?;
+                IValue::Record(NEVec::new(fields).ok()?)
+            }
+        };
+
+        Some(value)
+    }
+}
+
+/// Call from the interpreter to a host closure
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HostCall {
+    pub args: Vec<RecordedValue>,
+    pub result: Option<RecordedValue>,
+}
+
+/// Single execution of a particle on this node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExecutionRecord {
+    pub current_peer_id: String,
+    /// Particle as it was received
+    pub particle: Particle,
+    /// Data left by the previous executions of the particle
+    #[serde(with = "base64_serde")]
+    pub prev_data: Vec<u8>,
+    /// Host calls in the order they were made
+    pub host_calls: Vec<HostCall>,
+    /// Interpreter outcome, or an error if interpreter failed
+    pub outcome: Result<InterpreterOutcome, String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("Error reading recording {path:?}: {err}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Error parsing recording {path:?}: {err}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        err: serde_json::Error,
+    },
+    #[error("Error writing recording {path:?}: {err}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Particle id {0:?} can't be used as a file name, execution isn't recorded")]
+    InvalidParticleId(String),
+}
+
+/// Records particle executions to `recordings_dir`
+#[derive(Clone)]
+pub struct ParticleRecorder {
+    recordings_dir: PathBuf,
+    /// Dir where interpreter keeps particle data between executions
+    particles_dir: PathBuf,
+    current_peer_id: String,
+    /// Host calls of particles being executed, by particle id
+    host_calls: Arc<Mutex<HashMap<String, Vec<HostCall>>>>,
+}
+
+impl ParticleRecorder {
+    pub fn new(recordings_dir: PathBuf, particles_dir: PathBuf, current_peer_id: String) -> Self {
+        Self {
+            recordings_dir,
+            particles_dir,
+            current_peer_id,
+            host_calls: <_>::default(),
+        }
+    }
+
+    /// Wraps host closures, so every call they serve is recorded
+    pub fn wrap(&self, host_closure: ClosureDescriptor) -> ClosureDescriptor {
+        let host_calls = self.host_calls.clone();
+        Arc::new(move || {
+            let call_service = host_closure();
+            let host_calls = host_calls.clone();
+            Box::new(move |params: ParticleParameters, args: Vec<IValue>| {
+                let particle_id = params.particle_id.clone();
+                let recorded_args = args.iter().cloned().map(Into::into).collect();
+                let result = call_service(params, args);
+                let call = HostCall {
+                    args: recorded_args,
+                    result: result.clone().map(Into::into),
+                };
+                host_calls.lock().entry(particle_id).or_default().push(call);
+                result
+            })
+        })
+    }
+
+    /// Data interpreter will load for the particle, must be called before execution
+    pub fn prev_data(&self, particle_id: &str) -> Vec<u8> {
+        std::fs::read(self.particles_dir.join(particle_id)).unwrap_or_default()
+    }
+
+    /// Appends execution of the `particle` to its recording
+    pub fn record(
+        &self,
+        particle: &Particle,
+        prev_data: Vec<u8>,
+        outcome: &Result<InterpreterOutcome, AquamarineVMError>,
+    ) -> Result<(), RecordingError> {
+        let host_calls = self.host_calls.lock().remove(&particle.id);
+        let path = recording_path(&self.recordings_dir, &particle.id)?;
+
+        let execution = ExecutionRecord {
+            current_peer_id: self.current_peer_id.clone(),
+            particle: particle.clone(),
+            prev_data,
+            host_calls: host_calls.unwrap_or_default(),
+            outcome: outcome
+                .as_ref()
+                .map(Clone::clone)
+                .map_err(|e| e.to_string()),
+        };
+
+        let write = || -> std::io::Result<()> {
+            let mut line = serde_json::to_vec(&execution)?;
+            line.push(b'\n');
+            std::fs::create_dir_all(&self.recordings_dir)?;
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            // whole line is written at once, so concurrent appends don't interleave
+            file.write_all(&line)
+        };
+        write().map_err(|err| RecordingError::Write { path, err })
+    }
+}
+
+fn recording_path(recordings_dir: &Path, particle_id: &str) -> Result<PathBuf, RecordingError> {
+    let valid = !particle_id.is_empty()
+        && particle_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(RecordingError::InvalidParticleId(particle_id.to_string()));
+    }
+
+    Ok(recordings_dir.join(format!("{}.jsonl", particle_id)))
+}
+
+/// Loads all recorded executions of a particle, in the order they happened
+pub fn load_recording(
+    recordings_dir: &Path,
+    particle_id: &str,
+) -> Result<Vec<ExecutionRecord>, RecordingError> {
+    let path = recording_path(recordings_dir, particle_id)?;
+    let read_err = |err| RecordingError::Read {
+        path: path.clone(),
+        err,
+    };
+    let file = File::open(&path).map_err(read_err)?;
+
+    let mut executions = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(read_err)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let execution = serde_json::from_str(&line).map_err(|err| RecordingError::Parse {
+            path: path.clone(),
+            err,
+        })?;
+        executions.push(execution);
+    }
+
+    Ok(executions)
+}
+
+/// Executes recorded particle again on a fresh interpreter, answering host calls
+/// with the recorded results. Host calls that differ from the recorded ones fail.
+pub fn replay(
+    air_interpreter: PathBuf,
+    particles_dir: PathBuf,
+    record: &ExecutionRecord,
+) -> Result<InterpreterOutcome, AquamarineVMError> {
+    let particle = &record.particle;
+    if let Err(err) = std::fs::write(particles_dir.join(&particle.id), &record.prev_data) {
+        log::warn!(
+            "Unable to write prev_data for replay of {}: {}",
+            particle.id,
+            err
+        );
+    }
+
+    let recorded: VecDeque<HostCall> = record.host_calls.iter().cloned().collect();
+    let recorded = Mutex::new(recorded);
+    let call_service = Box::new(move |_: ParticleParameters, args: Vec<IValue>| {
+        let args: Vec<RecordedValue> = args.into_iter().map(Into::into).collect();
+        match recorded.lock().pop_front() {
+            Some(call) if call.args == args => call.result.and_then(RecordedValue::into_ivalue),
+            Some(call) => {
+                log::warn!("Replay diverged: expected {:?}, got {:?}", call.args, args);
+                ivalue_utils::error(serde_json::json!("replay diverged from the recording"))
+            }
+            None => {
+                log::warn!("Replay diverged: unexpected host call {:?}", args);
+                ivalue_utils::error(serde_json::json!("host call wasn't recorded"))
+            }
+        }
+    });
+
+    let config = AquamarineVMConfig {
+        current_peer_id: record.current_peer_id.clone(),
+        aquamarine_wasm_path: air_interpreter,
+        particle_data_store: particles_dir,
+        call_service,
+        logging_mask: i32::max_value(),
+    };
+    let mut vm = AquamarineVM::new(config)?;
+    let init_peer_id = particle.init_peer_id.to_string();
+    vm.call(
+        init_peer_id,
+        &particle.script,
+        particle.data.clone(),
+        &particle.id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_recording, ParticleRecorder, RecordedValue};
+    use aquamarine_vm::{ne_vec::NEVec, IValue, InterpreterOutcome, ParticleParameters};
+    use particle_protocol::Particle;
+    use std::sync::Arc;
+    use tempdir::TempDir;
+
+    #[test]
+    fn record_host_calls() {
+        let dir = TempDir::new("recordings").unwrap();
+        let recorder = ParticleRecorder::new(dir.path().into(), dir.path().into(), "peer".into());
+
+        let result = IValue::Record(
+            NEVec::new(vec![IValue::U32(0), IValue::String("\"ok\"".into())]).unwrap(),
+        );
+        let returned = result.clone();
+        let host_closure = recorder.wrap(Arc::new(move || {
+            let result = returned.clone();
+            Box::new(move |_, _| Some(result.clone()))
+        }));
+        let call_service = host_closure();
+        let params = ParticleParameters {
+            particle_id: "particle".into(),
+            ..<_>::default()
+        };
+        let args = vec![IValue::String("srv".into()), IValue::Array(vec![])];
+        assert_eq!(call_service(params, args.clone()), Some(result.clone()));
+
+        let particle = Particle {
+            id: "particle".into(),
+            ..<_>::default()
+        };
+        let outcome = InterpreterOutcome {
+            ret_code: 0,
+            error_message: "".into(),
+            data: b"data".to_vec(),
+            next_peer_pks: vec![],
+        };
+        recorder
+            .record(&particle, b"prev".to_vec(), &Ok(outcome.clone()))
+            .unwrap();
+        recorder.record(&particle, vec![], &Ok(outcome)).unwrap();
+
+        let recording = load_recording(dir.path(), "particle").unwrap();
+        assert_eq!(recording.len(), 2);
+        assert_eq!(recording[0].prev_data, b"prev".to_vec());
+        assert_eq!(recording[0].host_calls.len(), 1);
+        let call = recording[0].host_calls[0].clone();
+        let recorded_args: Option<Vec<_>> = call
+            .args
+            .into_iter()
+            .map(RecordedValue::into_ivalue)
+            .collect();
+        assert_eq!(recorded_args, Some(args));
+        assert_eq!(
+            call.result.and_then(RecordedValue::into_ivalue),
+            Some(result)
+        );
+        // host calls are recorded for a single execution
+        assert!(recording[1].host_calls.is_empty());
+
+        // particle id must be a valid file name
+        let particle = Particle {
+            id: "../escape".into(),
+            ..particle
+        };
+        let outcome = recording[1].outcome.clone().unwrap();
+        assert!(recorder.record(&particle, vec![], &Ok(outcome)).is_err());
+    }
+}
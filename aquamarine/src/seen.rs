@@ -0,0 +1,173 @@
+/*
+ * Copyright 2021 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Particles accepted for execution are remembered along with the hash of their data until
+//! their deadline, so an exact copy of a particle isn't executed again, be it re-sent or replayed
+//! by a peer. Legitimate revisits of a particle always carry updated data, so they get through.
+
+use particle_protocol::Particle;
+use prometheus::{IntCounter, Registry};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+/// Identifies a particle along with its data. Data isn't signed, so a copy with changed data
+/// isn't detected, but it isn't a replay either: the interpreter merges it as any other revisit.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeenKey {
+    id: String,
+    init_peer_id: Vec<u8>,
+    signature: Vec<u8>,
+    data: [u8; 32],
+}
+
+impl SeenKey {
+    pub fn new(particle: &Particle) -> Self {
+        Self {
+            id: particle.id.clone(),
+            init_peer_id: particle.init_peer_id.to_bytes(),
+            signature: particle.signature.clone(),
+            data: *blake3::hash(&particle.data).as_bytes(),
+        }
+    }
+}
+
+pub struct SeenParticles {
+    seen: HashSet<SeenKey>,
+    /// Keys of `seen` ordered by deadline, soonest first
+    deadlines: BinaryHeap<Reverse<(u64, SeenKey)>>,
+    /// Max number of remembered particles, ones closest to their deadline are forgotten above that
+    limit: usize,
+    duplicates: Option<IntCounter>,
+}
+
+impl SeenParticles {
+    pub fn new(limit: usize, registry: Option<&Registry>) -> Self {
+        let duplicates = registry.and_then(|registry| {
+            let duplicates = IntCounter::new(
+                "aquamarine_particle_duplicates",
+                "Number of particles dropped as copies of already received ones",
+            )
+            .ok()?;
+            if let Err(err) = registry.register(Box::new(duplicates.clone())) {
+                log::warn!("Failed to register particle duplicates metric: {}", err);
+            }
+            Some(duplicates)
+        });
+
+        Self {
+            seen: <_>::default(),
+            deadlines: <_>::default(),
+            limit,
+            duplicates,
+        }
+    }
+
+    /// Returns true if particle with the same data was already accepted
+    pub fn is_duplicate(&self, key: &SeenKey) -> bool {
+        let duplicate = self.seen.contains(key);
+        if duplicate {
+            if let Some(duplicates) = &self.duplicates {
+                duplicates.inc();
+            }
+        }
+        duplicate
+    }
+
+    /// Remembers accepted particle until `deadline_ms`
+    pub fn insert(&mut self, key: SeenKey, deadline_ms: u64) {
+        if self.limit == 0 || self.seen.contains(&key) {
+            return;
+        }
+
+        while self.seen.len() >= self.limit {
+            match self.deadlines.pop() {
+                Some(Reverse((_, key))) => self.seen.remove(&key),
+                None => break,
+            };
+        }
+        self.seen.insert(key.clone());
+        self.deadlines.push(Reverse((deadline_ms, key)));
+    }
+
+    /// Forgets particles with deadline before `now_ms`, their copies are rejected as expired
+    pub fn remove_expired(&mut self, now_ms: u64) {
+        while let Some(Reverse((deadline, _))) = self.deadlines.peek() {
+            if *deadline >= now_ms {
+                break;
+            }
+            if let Some(Reverse((_, key))) = self.deadlines.pop() {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SeenKey, SeenParticles};
+
+    use libp2p::PeerId;
+    use particle_protocol::Particle;
+
+    thread_local! {
+        static INIT_PEER_ID: PeerId = PeerId::random();
+    }
+
+    fn particle(id: &str, data: &[u8]) -> Particle {
+        Particle {
+            id: id.to_string(),
+            init_peer_id: INIT_PEER_ID.with(|p| *p),
+            data: data.to_vec(),
+            ..<_>::default()
+        }
+    }
+
+    fn key(id: &str, data: &[u8]) -> SeenKey {
+        SeenKey::new(&particle(id, data))
+    }
+
+    #[test]
+    fn duplicates_until_deadline() {
+        let mut seen = SeenParticles::new(10, None);
+
+        assert!(!seen.is_duplicate(&key("1", b"a")));
+        seen.insert(key("1", b"a"), 100);
+        assert!(seen.is_duplicate(&key("1", b"a")));
+        // revisits carry updated data
+        assert!(!seen.is_duplicate(&key("1", b"b")));
+        seen.insert(key("1", b"b"), 100);
+        seen.insert(key("2", b"a"), 200);
+
+        seen.remove_expired(150);
+        assert_eq!(seen.seen.len(), 1);
+        assert!(!seen.is_duplicate(&key("1", b"a")));
+        assert!(seen.is_duplicate(&key("2", b"a")));
+    }
+
+    #[test]
+    fn bounded() {
+        let mut seen = SeenParticles::new(2, None);
+
+        seen.insert(key("1", b""), 300);
+        seen.insert(key("2", b""), 100);
+        seen.insert(key("3", b""), 200);
+        assert_eq!(seen.seen.len(), 2);
+
+        // particle closest to its deadline was forgotten
+        assert!(seen.is_duplicate(&key("1", b"")));
+        assert!(!seen.is_duplicate(&key("2", b"")));
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Interpreter persists data of each particle to `particles_dir`, in a file named by particle id.
+//! That data is useless once particle is expired, so it is removed in background.
+
+use crate::actor::Deadline;
+use crate::config::VmPoolConfig;
+use crate::plumber::real_time::now_ms;
+
+use async_std::{stream, task};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use prometheus::{IntCounter, Registry};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+/// How often `particles_dir` is checked
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct SweeperMetrics {
+    reclaimed_bytes: IntCounter,
+    removed_files: IntCounter,
+}
+
+impl SweeperMetrics {
+    fn new(registry: &Registry) -> Option<Self> {
+        let reclaimed_bytes = IntCounter::new(
+            "aquamarine_particle_data_reclaimed_bytes",
+            "Number of bytes of particle data removed from disk",
+        )
+        .ok()?;
+        let removed_files = IntCounter::new(
+            "aquamarine_particle_data_removed",
+            "Number of particles whose data was removed from disk",
+        )
+        .ok()?;
+
+        for counter in [&reclaimed_bytes, &removed_files].iter() {
+            if let Err(err) = registry.register(Box::new((*counter).clone())) {
+                log::warn!("Failed to register particle data metric: {}", err);
+            }
+        }
+
+        Some(Self {
+            reclaimed_bytes,
+            removed_files,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Swept {
+    files: u64,
+    bytes: u64,
+}
+
+/// Periodically removes data of expired particles from `particles_dir`.
+///
+/// Deadlines are known only for particles seen since start, so data of unknown particles is
+/// removed once it's older than `max_age`. If `particles_dir` is still larger than `max_size`,
+/// the least recently modified data of particles that aren't alive is removed.
+pub(crate) struct ParticleDataSweeper {
+    particles_dir: PathBuf,
+    max_age: Duration,
+    max_size: u64,
+    /// Deadlines of particles that may have data in `particles_dir`
+    deadlines: HashMap<String, Deadline>,
+    interval: stream::Interval,
+    sweeping: Option<BoxFuture<'static, Swept>>,
+    metrics: Option<SweeperMetrics>,
+}
+
+impl ParticleDataSweeper {
+    pub fn new(config: &VmPoolConfig, registry: Option<&Registry>) -> Self {
+        Self {
+            particles_dir: config.particles_dir.clone(),
+            max_age: config.particle_data_max_age,
+            max_size: config.particle_data_max_size,
+            deadlines: <_>::default(),
+            interval: stream::interval(SWEEP_INTERVAL),
+            sweeping: None,
+            metrics: registry.and_then(SweeperMetrics::new),
+        }
+    }
+
+    /// Remembers particle's deadline, so its data is removed once it's expired
+    pub fn track(&mut self, particle_id: &str, deadline: Deadline) {
+        if !self.deadlines.contains_key(particle_id) {
+            self.deadlines.insert(particle_id.to_string(), deadline);
+        }
+    }
+
+    pub fn poll(&mut self, cx: &mut Context<'_>) {
+        if self.sweeping.is_none() {
+            if let Poll::Ready(Some(_)) = self.interval.poll_next_unpin(cx) {
+                self.sweeping = Some(self.start_sweep());
+            }
+        }
+
+        if let Some(sweeping) = self.sweeping.as_mut() {
+            if let Poll::Ready(swept) = sweeping.poll_unpin(cx) {
+                self.sweeping = None;
+                if swept.files > 0 {
+                    #[rustfmt::skip]
+                    log::debug!("Removed data of {} particles, {} bytes", swept.files, swept.bytes);
+                }
+                if let Some(metrics) = &self.metrics {
+                    metrics.reclaimed_bytes.inc_by(swept.bytes as i64);
+                    metrics.removed_files.inc_by(swept.files as i64);
+                }
+            }
+        }
+    }
+
+    /// Forgets expired particles, and removes their data in background
+    fn start_sweep(&mut self) -> BoxFuture<'static, Swept> {
+        let now = now_ms();
+        let expired: HashSet<String> = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| deadline.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired.iter() {
+            self.deadlines.remove(id);
+        }
+        let alive: HashSet<String> = self.deadlines.keys().cloned().collect();
+
+        let dir = self.particles_dir.clone();
+        let (max_age, max_size) = (self.max_age, self.max_size);
+        task::spawn_blocking(move || {
+            sweep(&dir, &expired, &alive, max_age, max_size, SystemTime::now())
+        })
+        .boxed()
+    }
+}
+
+/// Removes data of `expired` particles, data older than `max_age`, and the oldest data
+/// above `max_size`. Data of `alive` particles is never removed.
+fn sweep(
+    dir: &Path,
+    expired: &HashSet<String>,
+    alive: &HashSet<String>,
+    max_age: Duration,
+    max_size: u64,
+    now: SystemTime,
+) -> Swept {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::warn!("Unable to list particle data in {:?}: {}", dir, err);
+            return Swept::default();
+        }
+    };
+
+    let mut swept = Swept::default();
+    let mut remove = |path: &Path, size: u64| match std::fs::remove_file(path) {
+        Ok(_) => {
+            swept.files += 1;
+            swept.bytes += size;
+            true
+        }
+        Err(err) => {
+            log::warn!("Unable to remove particle data {:?}: {}", path, err);
+            false
+        }
+    };
+
+    // (modified, size, path) of data that can be removed if it doesn't fit into max_size
+    let mut kept = vec![];
+    let mut total_size = 0;
+    for entry in entries.flatten() {
+        let meta = match entry.metadata() {
+            Ok(meta) if meta.is_file() => meta,
+            _ => continue,
+        };
+        let size = meta.len();
+        let particle_id = entry.file_name().to_string_lossy().to_string();
+        if alive.contains(&particle_id) {
+            total_size += size;
+            continue;
+        }
+
+        let modified = meta.modified().unwrap_or(now);
+        let age = now.duration_since(modified).unwrap_or_default();
+        if expired.contains(&particle_id) || age > max_age {
+            if !remove(&entry.path(), size) {
+                total_size += size;
+            }
+        } else {
+            total_size += size;
+            kept.push((modified, size, entry.path()));
+        }
+    }
+
+    kept.sort_by_key(|(modified, _, _)| *modified);
+    for (_, size, path) in kept {
+        if total_size <= max_size {
+            break;
+        }
+        if remove(&path, size) {
+            total_size -= size;
+        }
+    }
+
+    if total_size > max_size {
+        #[rustfmt::skip]
+        log::warn!("Particle data takes {} bytes, above the limit of {}, but all of it is in use", total_size, max_size);
+    }
+
+    swept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sweep, Swept};
+    use std::collections::HashSet;
+    use std::time::{Duration, SystemTime};
+    use tempdir::TempDir;
+
+    #[test]
+    fn sweep_particle_data() {
+        let dir = TempDir::new("particles").unwrap();
+        for (id, size) in &[("expired", 1), ("alive", 2), ("unknown", 4), ("old", 8)] {
+            std::fs::write(dir.path().join(id), vec![0u8; *size]).unwrap();
+        }
+        let set = |ids: &[&str]| -> HashSet<String> { ids.iter().map(|s| s.to_string()).collect() };
+        let ids = || {
+            let mut ids: Vec<_> = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        let expired = set(&["expired"]);
+        let alive = set(&["alive"]);
+        let hour = Duration::from_secs(60 * 60);
+        let now = SystemTime::now();
+
+        let swept = sweep(dir.path(), &expired, &alive, hour, 1000, now);
+        assert_eq!(swept, Swept { files: 1, bytes: 1 });
+        assert_eq!(ids(), vec!["alive", "old", "unknown"]);
+
+        // everything that isn't alive is too old
+        let swept = sweep(dir.path(), &expired, &alive, hour, 1000, now + 2 * hour);
+        assert_eq!(
+            swept,
+            Swept {
+                files: 2,
+                bytes: 12
+            }
+        );
+        assert_eq!(ids(), vec!["alive"]);
+
+        // alive particles are kept even above max_size
+        std::fs::write(dir.path().join("unknown"), vec![0u8; 4]).unwrap();
+        let swept = sweep(dir.path(), &expired, &alive, hour, 0, now);
+        assert_eq!(swept, Swept { files: 1, bytes: 4 });
+        assert_eq!(ids(), vec!["alive"]);
+    }
+}
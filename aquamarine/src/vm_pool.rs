@@ -14,18 +14,108 @@
  * limitations under the License.
  */
 
+use crate::interpreter::{InterpreterInfo, InterpreterReloads, ReloadError};
+use crate::particle_executor::{Fut, FutResult};
 use crate::VmPoolConfig;
 
 use aquamarine_vm::{AquamarineVM, AquamarineVMConfig, AquamarineVMError};
 use host_closure::ClosureDescriptor;
 
-use async_std::task;
-use futures::{future::BoxFuture, FutureExt};
+use async_std::{stream, task};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use humantime::format_duration as pretty;
+use prometheus::{IntCounter, IntGauge, Registry};
 use std::{
     collections::VecDeque,
+    path::PathBuf,
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
+type VmFuture = BoxFuture<'static, Result<AquamarineVM, AquamarineVMError>>;
+/// Resolves to VMs created from a new interpreter
+type GenerationFuture =
+    BoxFuture<'static, Result<(InterpreterInfo, Vec<AquamarineVM>), ReloadError>>;
+
+/// How many times VM creation is attempted before giving up
+const CREATE_VM_ATTEMPTS: u32 = 5;
+/// Delay before the second attempt to create VM, doubled on each next attempt
+const CREATE_VM_BACKOFF: Duration = Duration::from_secs(1);
+/// Delay before creating VMs again once all attempts failed, doubled while failures continue
+const REFILL_BACKOFF: Duration = Duration::from_secs(16);
+/// Max delay between creating VMs, so the pool is refilled soon after the cause of failures is gone
+const MAX_REFILL_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// VM along with the generation of the interpreter it was created from
+pub struct PooledVm {
+    pub vm: AquamarineVM,
+    pub generation: u64,
+}
+
+/// VM whose execution timed out. Execution can't be interrupted, so it keeps running
+/// on a blocking thread, and VM is returned once it finishes
+pub struct LostVm {
+    pub execution: Fut,
+    pub generation: u64,
+}
+
+/// Counters of VMs lost to execution timeouts, VMs created to replace them, failures
+/// to create them, and interpreter reloads, along with the current pool size
+struct VmPoolMetrics {
+    lost: IntCounter,
+    recreated: IntCounter,
+    recreate_failed: IntCounter,
+    reloaded: IntCounter,
+    size: IntGauge,
+}
+
+impl VmPoolMetrics {
+    fn new(registry: &Registry) -> Option<Self> {
+        let lost = IntCounter::new(
+            "aquamarine_vm_lost",
+            "Number of interpreters lost because of execution timeout",
+        )
+        .ok()?;
+        let recreated = IntCounter::new(
+            "aquamarine_vm_recreated",
+            "Number of interpreters created to replace the lost ones",
+        )
+        .ok()?;
+        let recreate_failed = IntCounter::new(
+            "aquamarine_vm_recreate_failed",
+            "Number of interpreters that failed to be created in place of the lost ones",
+        )
+        .ok()?;
+        let reloaded = IntCounter::new(
+            "aquamarine_interpreter_reloaded",
+            "Number of times interpreters were recreated from a new interpreter wasm",
+        )
+        .ok()?;
+        let size = IntGauge::new(
+            "aquamarine_vm_pool_size",
+            "Number of interpreters in the pool, both free and busy",
+        )
+        .ok()?;
+
+        for counter in [&lost, &recreated, &recreate_failed, &reloaded].iter() {
+            if let Err(err) = registry.register(Box::new((*counter).clone())) {
+                log::warn!("Failed to register vm pool metric: {}", err);
+            }
+        }
+        if let Err(err) = registry.register(Box::new(size.clone())) {
+            log::warn!("Failed to register vm pool metric: {}", err);
+        }
+
+        Some(Self {
+            lost,
+            recreated,
+            recreate_failed,
+            reloaded,
+            size,
+        })
+    }
+}
+
 /// Pool that owns and manages aquamarine stepper VMs
 /// VMs are created asynchronously after `VmPool` creation
 /// Futures representing background VM creation are stored in `VmPool::creating_vms`
@@ -35,76 +125,380 @@ use std::{
 /// API allows taking VM for execution (via `get_vm`), and then it is expected that VM is
 /// returned back via `put_vm`.
 /// It is also expected that `VmPool::poll` is called periodically.
+///
+/// Pool keeps at least `min_pool_size` VMs, and grows up to `pool_size` via `VmPool::grow`
+/// when there are particles waiting for a VM. VMs above `min_pool_size` that weren't used
+/// for `vm_idle_timeout` are removed.
+///
+/// If VM creation fails after all attempts, no VMs are created for a while, so a broken
+/// interpreter doesn't make the pool retry endlessly. Interpreter reload or a created VM resets that.
+///
+/// If VM is lost because its execution timed out, `VmPool::recreate_vm` creates a new one instead.
+/// Timed out executions keep running on blocking threads. They don't count against `pool_size`,
+/// so a hung execution doesn't shrink the pool, but only up to `max_lost_vms` of them are replaced.
+/// Once a timed out execution finishes, its VM is returned to the pool if there's room for it.
+///
+/// When interpreter reload is requested, a new generation of VMs is created in background.
+/// Once it's ready, it replaces free VMs, and VMs of previous generations are dropped
+/// when they are returned via `put_vm`. So particles being executed finish on the old interpreter.
+/// Busy VMs of previous generations are counted in the pool size until they are returned.
 pub struct VmPool {
-    vms: VecDeque<AquamarineVM>,
-    creating_vms: Option<Vec<BoxFuture<'static, Result<AquamarineVM, AquamarineVMError>>>>,
+    /// Free VMs along with the time they were put to the pool.
+    /// Recently used VMs are at the front, so idle ones gather at the back.
+    vms: VecDeque<(AquamarineVM, Instant)>,
+    /// Number of VMs, both free and busy. Busy VMs of previous generations are counted as well
+    size: usize,
+    creating_vms: Vec<VmFuture>,
+    /// VMs being created to replace lost ones
+    recreating_vms: Vec<VmFuture>,
+    /// Timed out executions that are still running, VMs are returned once they finish
+    lost_vms: Vec<LostVm>,
+    /// Set after VM creation failed, no VMs are created until it resolves
+    refill_delay: Option<BoxFuture<'static, ()>>,
+    /// Next value of `refill_delay`
+    refill_backoff: Duration,
+    /// Idle VMs are removed on each tick
+    idle_check: stream::Interval,
+    /// Generation of the interpreter that VMs in `vms` are created from
+    generation: u64,
+    /// VMs being created from a new interpreter
+    next_generation: Option<GenerationFuture>,
+    reloads: InterpreterReloads,
     host_closure: ClosureDescriptor,
     config: VmPoolConfig,
+    metrics: Option<VmPoolMetrics>,
 }
 
 impl VmPool {
-    /// Creates `VmPool` and starts background tasks creating `config.pool_size` number of VMs
-    pub fn new(config: VmPoolConfig, host_closure: ClosureDescriptor) -> Self {
+    /// Creates `VmPool`, VMs are created in background on the first `poll`
+    pub fn new(
+        config: VmPoolConfig,
+        host_closure: ClosureDescriptor,
+        reloads: InterpreterReloads,
+        registry: Option<&Registry>,
+    ) -> Self {
+        let idle_check = config.vm_idle_timeout.max(Duration::from_secs(1));
         Self {
             vms: <_>::default(),
-            creating_vms: None,
+            size: 0,
+            creating_vms: <_>::default(),
+            recreating_vms: <_>::default(),
+            lost_vms: <_>::default(),
+            refill_delay: None,
+            refill_backoff: REFILL_BACKOFF,
+            idle_check: stream::interval(idle_check),
+            generation: reloads.current().generation,
+            next_generation: None,
+            reloads,
             host_closure,
             config,
+            metrics: registry.and_then(VmPoolMetrics::new),
         }
     }
 
     /// Takes VM from pool
-    pub fn get_vm(&mut self) -> Option<AquamarineVM> {
-        self.vms.pop_front()
+    pub fn get_vm(&mut self) -> Option<PooledVm> {
+        let generation = self.generation;
+        self.vms
+            .pop_front()
+            .map(|(vm, _)| PooledVm { vm, generation })
     }
 
-    /// Puts VM back to the pool
-    pub fn put_vm(&mut self, vm: AquamarineVM) {
-        self.vms.push_front(vm)
+    /// Number of VMs available for execution
+    pub fn free_vms(&self) -> usize {
+        self.vms.len()
     }
 
-    /// Moves created VMs from `creating_vms` to `vms`
-    pub fn poll(&mut self, cx: &mut Context<'_>) {
-        let creating_vms = match &mut self.creating_vms {
-            None => {
-                self.creating_vms = Some(
-                    (0..self.config.pool_size)
-                        .map(|_| {
-                            let config = self.config.clone();
-                            let host_closure = self.host_closure.clone();
-                            let waker = cx.waker().clone();
-
-                            create_vm(config, host_closure, waker)
-                        })
-                        .collect(),
-                );
-                self.creating_vms.as_mut().unwrap()
+    /// Puts VM back to the pool, VMs of previous generations are dropped
+    pub fn put_vm(&mut self, vm: PooledVm) {
+        if vm.generation == self.generation {
+            self.vms.push_front((vm.vm, Instant::now()))
+        } else {
+            log::debug!("Dropped VM of interpreter generation {}", vm.generation);
+            self.size = self.size.saturating_sub(1);
+        }
+    }
+
+    /// Starts creation of a VM in place of the lost one, unless it was of a previous generation,
+    /// or there are more than `max_lost_vms` timed out executions still running
+    pub fn recreate_vm(&mut self, lost: LostVm, cx: &mut Context<'_>) {
+        if let Some(metrics) = &self.metrics {
+            metrics.lost.inc();
+        }
+        let generation = lost.generation;
+        self.lost_vms.push(lost);
+        // lost VM was busy, so it was counted in the pool size whatever its generation
+        self.size = self.size.saturating_sub(1);
+        if generation != self.generation {
+            return;
+        }
+
+        if self.refill_delay.is_some() {
+            // pool will be refilled up to `min_pool_size` after the delay
+            return;
+        }
+        if self.lost_vms.len() > self.config.max_lost_vms {
+            #[rustfmt::skip]
+            log::warn!("Lost VM isn't recreated, {} timed out executions are still running", self.lost_vms.len());
+            return;
+        }
+        let vm = self.create_vm(cx);
+        self.recreating_vms.push(vm);
+    }
+
+    /// Starts creation of VMs for particles waiting for execution, as long as pool size allows
+    pub fn grow(&mut self, waiting: usize, cx: &mut Context<'_>) {
+        if self.refill_delay.is_some() {
+            return;
+        }
+
+        let creating = self.creating_vms.len() + self.recreating_vms.len();
+        let count = growth(waiting, creating, self.size, self.config.pool_size);
+        if count == 0 && waiting > creating {
+            #[rustfmt::skip]
+            log::debug!("No more free Aquamarine interpreters, pool is at its max size {}", self.config.pool_size);
+        }
+
+        for _ in 0..count {
+            let vm = self.create_vm(cx);
+            self.creating_vms.push(vm);
+        }
+    }
+
+    fn create_vm(&self, cx: &mut Context<'_>) -> VmFuture {
+        let config = self.config.clone();
+        let host_closure = self.host_closure.clone();
+        let waker = cx.waker().clone();
+        create_vm(config, host_closure, waker)
+    }
+
+    /// Number of VMs, both existing and being created
+    fn total(&self) -> usize {
+        self.size + self.creating_vms.len() + self.recreating_vms.len()
+    }
+
+    /// Starts creation of VMs up to `min_pool_size`
+    fn fill(&mut self, cx: &mut Context<'_>) {
+        if self.refill_delay.is_some() {
+            return;
+        }
+
+        for _ in self.total()..self.config.min_pool_size {
+            let vm = self.create_vm(cx);
+            self.creating_vms.push(vm);
+        }
+    }
+
+    /// Stops creating VMs for `refill_backoff`, and makes the next delay longer.
+    /// VMs that were being created along with the failed one don't prolong the delay.
+    fn creation_failed(&mut self) {
+        if self.refill_delay.is_some() {
+            return;
+        }
+
+        #[rustfmt::skip]
+        log::warn!("No VMs will be created for {}, as VM creation failed", pretty(self.refill_backoff));
+        self.refill_delay = Some(task::sleep(self.refill_backoff).boxed());
+        self.refill_backoff = (self.refill_backoff * 2).min(MAX_REFILL_BACKOFF);
+    }
+
+    /// VMs can be created again, so the next failure waits for the shortest delay
+    fn creation_succeeded(&mut self) {
+        self.refill_delay = None;
+        self.refill_backoff = REFILL_BACKOFF;
+    }
+
+    /// Removes free VMs that weren't used for `vm_idle_timeout`, keeping `min_pool_size` VMs
+    fn remove_idle(&mut self) {
+        let mut removed = 0;
+        while self.size > self.config.min_pool_size {
+            match self.vms.back() {
+                Some((_, since)) if since.elapsed() >= self.config.vm_idle_timeout => {
+                    self.vms.pop_back();
+                    self.size -= 1;
+                    removed += 1;
+                }
+                _ => break,
             }
-            Some(ref mut vms) => vms,
+        }
+
+        if removed > 0 {
+            #[rustfmt::skip]
+            log::debug!("Removed {} idle VMs, {} VMs left", removed, self.size);
+        }
+    }
+
+    /// Starts creation of a new generation of VMs from interpreter at `path`.
+    /// If there's a generation being created already, it's abandoned.
+    fn create_generation(&mut self, path: PathBuf, cx: &mut Context<'_>) {
+        let generation = self.generation + 1;
+        log::info!(
+            "Reloading interpreter {:?}, generation {}",
+            path,
+            generation
+        );
+
+        let config = VmPoolConfig {
+            air_interpreter: path.clone(),
+            ..self.config.clone()
+        };
+        let host_closure = self.host_closure.clone();
+        let waker = cx.waker().clone();
+        let next_generation = async move {
+            let info = task::spawn_blocking(move || InterpreterInfo::read(path, generation))
+                .await
+                .map_err(|err| ReloadError::Read {
+                    path: config.air_interpreter.clone(),
+                    err,
+                })?;
+
+            // at least one VM is created, so a broken interpreter doesn't replace the working one
+            let vms = (0..config.min_pool_size.max(1))
+                .map(|_| create_vm(config.clone(), host_closure.clone(), waker.clone()));
+            let vms =
+                futures::future::try_join_all(vms)
+                    .await
+                    .map_err(|err| ReloadError::CreateVm {
+                        path: info.path.clone(),
+                        err,
+                    })?;
+
+            Ok((info, vms))
         };
 
+        self.next_generation = Some(next_generation.boxed());
+    }
+
+    /// Replaces VMs with the new generation
+    fn replace_generation(&mut self, info: InterpreterInfo, vms: Vec<AquamarineVM>) {
+        #[rustfmt::skip]
+        log::info!("Interpreter {:?} loaded, {} VMs of generation {} created", info.path, vms.len(), info.generation);
+
+        self.generation = info.generation;
+        self.config.air_interpreter = info.path.clone();
+        // busy VMs of the previous generation are counted until they are returned
+        let busy = self.size.saturating_sub(self.vms.len());
+        let now = Instant::now();
+        self.vms = vms.into_iter().map(|vm| (vm, now)).collect();
+        self.size = self.vms.len() + busy;
+        // VMs being created are from the previous interpreter
+        self.creating_vms.clear();
+        self.recreating_vms.clear();
+        self.creation_succeeded();
+        self.reloads.set_current(info);
+
+        if let Some(metrics) = &self.metrics {
+            metrics.reloaded.inc();
+        }
+    }
+
+    /// Moves created VMs from `creating_vms` and `recreating_vms` to `vms`,
+    /// keeps pool size within limits, and replaces VMs with a new generation once it's created
+    pub fn poll(&mut self, cx: &mut Context<'_>) {
+        if let Some(Poll::Ready(())) = self.refill_delay.as_mut().map(|d| d.poll_unpin(cx)) {
+            self.refill_delay = None;
+        }
+        self.fill(cx);
+
         let mut wake = false;
 
         let mut i = 0;
-        while i < creating_vms.len() {
-            let vms = &mut self.vms;
-            let fut = &mut creating_vms[i];
-            if let Poll::Ready(vm) = fut.poll_unpin(cx) {
-                // Remove completed future
-                creating_vms.remove(i);
-                if creating_vms.is_empty() {
-                    log::info!("All stepper VMs created.")
+        while i < self.creating_vms.len() {
+            if let Poll::Ready(vm) = self.creating_vms[i].poll_unpin(cx) {
+                self.creating_vms.remove(i);
+
+                match vm {
+                    Ok(vm) => {
+                        self.size += 1;
+                        self.vms.push_back((vm, Instant::now()));
+                        self.creation_succeeded();
+                        if self.creating_vms.is_empty() {
+                            log::info!("All stepper VMs created, pool size is {}", self.size)
+                        }
+                    }
+                    Err(err) => {
+                        log::error!("Failed to create vm: {:?}", err);
+                        self.creation_failed();
+                    }
                 }
 
-                // Put created vm to self.vms
+                wake = true;
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut i = 0;
+        while i < self.recreating_vms.len() {
+            if let Poll::Ready(vm) = self.recreating_vms[i].poll_unpin(cx) {
+                self.recreating_vms.remove(i);
+
                 match vm {
-                    Ok(vm) => vms.push_back(vm),
-                    Err(err) => log::error!("Failed to create vm: {:?}", err), // TODO: don't panic
+                    Ok(vm) => {
+                        log::info!("Lost stepper VM was recreated");
+                        if let Some(metrics) = &self.metrics {
+                            metrics.recreated.inc();
+                        }
+                        self.size += 1;
+                        self.vms.push_back((vm, Instant::now()));
+                        self.creation_succeeded();
+                    }
+                    Err(err) => {
+                        log::error!("Failed to recreate vm: {:?}", err);
+                        if let Some(metrics) = &self.metrics {
+                            metrics.recreate_failed.inc();
+                        }
+                        self.creation_failed();
+                    }
+                }
+
+                wake = true;
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut i = 0;
+        while i < self.lost_vms.len() {
+            if let Poll::Ready(FutResult { vm, .. }) = self.lost_vms[i].execution.poll_unpin(cx) {
+                let lost = self.lost_vms.remove(i);
+                // lost VM was likely replaced already, so it's kept only if pool isn't full
+                if lost.generation == self.generation && self.total() < self.config.pool_size {
+                    log::info!("Timed out execution finished, its VM is returned to the pool");
+                    self.size += 1;
+                    self.vms.push_back((vm, Instant::now()));
+                } else {
+                    log::debug!("Timed out execution finished, its VM is dropped");
                 }
 
                 wake = true;
+            } else {
+                i += 1;
             }
-            i += 1;
+        }
+
+        if let Poll::Ready(Some(_)) = self.idle_check.poll_next_unpin(cx) {
+            self.remove_idle();
+        }
+
+        while let Poll::Ready(path) = self.reloads.poll(cx) {
+            self.create_generation(path, cx);
+        }
+
+        if let Some(next_generation) = self.next_generation.as_mut() {
+            if let Poll::Ready(result) = next_generation.poll_unpin(cx) {
+                self.next_generation = None;
+                match result {
+                    Ok((info, vms)) => self.replace_generation(info, vms),
+                    Err(err) => log::error!("Failed to reload interpreter: {}", err),
+                }
+
+                wake = true;
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.size.set(self.size as i64);
         }
 
         if wake {
@@ -113,23 +507,175 @@ impl VmPool {
     }
 }
 
-/// Creates `AquamarineVM` in background (on blocking threadpool)
-fn create_vm(
-    config: VmPoolConfig,
-    host_closure: ClosureDescriptor,
-    waker: Waker,
-) -> BoxFuture<'static, Result<AquamarineVM, AquamarineVMError>> {
-    task::spawn_blocking(move || {
-        let config = AquamarineVMConfig {
-            current_peer_id: config.current_peer_id.to_string(),
-            aquamarine_wasm_path: config.air_interpreter,
-            particle_data_store: config.particles_dir,
-            call_service: host_closure(),
-            logging_mask: i32::max_value(),
-        };
-        let vm = AquamarineVM::new(config);
-        waker.wake();
-        vm
-    })
+/// Number of VMs to create for `waiting` particles, given that `creating` VMs are on the way,
+/// and `size` VMs already exist out of `max_size`
+fn growth(waiting: usize, creating: usize, size: usize, max_size: usize) -> usize {
+    let wanted = waiting.saturating_sub(creating);
+    let allowed = max_size.saturating_sub(size + creating);
+    wanted.min(allowed)
+}
+
+/// Creates `AquamarineVM` in background (on blocking threadpool).
+/// Failed creation is retried with exponential backoff, up to `CREATE_VM_ATTEMPTS` times.
+fn create_vm(config: VmPoolConfig, host_closure: ClosureDescriptor, waker: Waker) -> VmFuture {
+    async move {
+        let mut backoff = CREATE_VM_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let (config, host_closure) = (config.clone(), host_closure.clone());
+            let vm = task::spawn_blocking(move || {
+                let config = AquamarineVMConfig {
+                    current_peer_id: config.current_peer_id.to_string(),
+                    aquamarine_wasm_path: config.air_interpreter,
+                    particle_data_store: config.particles_dir,
+                    call_service: host_closure(),
+                    logging_mask: i32::max_value(),
+                };
+                AquamarineVM::new(config)
+            })
+            .await;
+            match vm {
+                Err(err) if attempt < CREATE_VM_ATTEMPTS => {
+                    #[rustfmt::skip]
+                    log::warn!("Failed to create vm (attempt {}), retrying in {}: {:?}", attempt, pretty(backoff), err);
+                    task::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                vm => {
+                    waker.wake();
+                    return vm;
+                }
+            }
+        }
+    }
     .boxed()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{growth, LostVm, VmPool};
+    use crate::{InterpreterInfo, InterpreterReloads, VmPoolConfig};
+
+    use aquamarine_vm::CallServiceClosure;
+    use host_closure::ClosureDescriptor;
+    use libp2p::PeerId;
+
+    use futures::{future, task::noop_waker_ref, FutureExt};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    #[test]
+    fn growth_is_limited() {
+        // nothing is waiting
+        assert_eq!(growth(0, 0, 1, 10), 0);
+        // one VM per waiting particle
+        assert_eq!(growth(3, 0, 1, 10), 3);
+        // VMs being created are counted as serving waiting particles
+        assert_eq!(growth(3, 2, 1, 10), 1);
+        assert_eq!(growth(3, 5, 1, 10), 0);
+        // pool doesn't grow above max size
+        assert_eq!(growth(10, 0, 8, 10), 2);
+        assert_eq!(growth(10, 1, 9, 10), 0);
+        assert_eq!(growth(10, 0, 12, 10), 0);
+    }
+
+    /// Creates pool of `pool_size` VMs with the real interpreter
+    fn create_pool(dir: &TempDir, pool_size: usize) -> VmPool {
+        let air_interpreter = dir.path().join("aquamarine.wasm");
+        std::fs::write(&air_interpreter, air_interpreter_wasm::INTERPRETER_WASM).unwrap();
+        let config = VmPoolConfig {
+            current_peer_id: PeerId::random(),
+            workdir: dir.path().into(),
+            air_interpreter,
+            services_dir: dir.path().into(),
+            particles_dir: dir.path().into(),
+            pool_size,
+            min_pool_size: pool_size,
+            vm_idle_timeout: Duration::from_secs(60),
+            execution_timeout: Duration::from_secs(1),
+            max_lost_vms: 1,
+            mailbox_size: 1,
+            max_queued_particles: 1,
+            seen_particles_limit: 1,
+            particle_data_max_age: Duration::from_secs(60),
+            particle_data_max_size: 0,
+            recordings_dir: None,
+            interpreter_watch_interval: None,
+        };
+        let (reloads, _) = InterpreterReloads::new(&config);
+        let host_closure: ClosureDescriptor = Arc::new(|| {
+            let call_service: CallServiceClosure = Box::new(|_, _| None);
+            call_service
+        });
+        VmPool::new(config, host_closure, reloads, None)
+    }
+
+    /// Takes a free VM, and loses it to an execution that never finishes
+    fn lose_vm(pool: &mut VmPool) {
+        let vm = pool.get_vm().expect("free VM");
+        let lost = LostVm {
+            execution: future::pending().boxed(),
+            generation: vm.generation,
+        };
+        pool.recreate_vm(lost, &mut Context::from_waker(noop_waker_ref()));
+    }
+
+    /// Polls pool until it has `free` VMs available
+    fn wait_free_vms(pool: &mut VmPool, free: usize) {
+        let wait = future::poll_fn(|cx| {
+            pool.poll(cx);
+            if pool.free_vms() == free {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+        async_std::task::block_on(async_std::future::timeout(Duration::from_secs(60), wait))
+            .expect("wait for free VMs");
+    }
+
+    #[test]
+    fn hung_execution_is_replaced() {
+        let dir = TempDir::new("vm_pool").unwrap();
+        let mut pool = create_pool(&dir, 1);
+        wait_free_vms(&mut pool, 1);
+
+        // lose the only VM to an execution that never finishes, while pool is at its max size
+        lose_vm(&mut pool);
+        assert_eq!(pool.free_vms(), 0);
+        // it's replaced, so pool keeps its capacity
+        wait_free_vms(&mut pool, 1);
+        assert_eq!(pool.size, 1);
+
+        // above `max_lost_vms` hung executions, lost VMs aren't replaced
+        lose_vm(&mut pool);
+        assert!(pool.recreating_vms.is_empty());
+        assert_eq!(pool.size, 0);
+    }
+
+    #[test]
+    fn busy_vms_of_previous_generation_are_counted() {
+        let dir = TempDir::new("vm_pool").unwrap();
+        let mut pool = create_pool(&dir, 1);
+        wait_free_vms(&mut pool, 1);
+
+        let busy = pool.get_vm().expect("free VM");
+        let info = InterpreterInfo {
+            path: dir.path().join("aquamarine.wasm"),
+            version: None,
+            hash: None,
+            generation: busy.generation + 1,
+        };
+        pool.replace_generation(info, vec![]);
+        // VM of the previous generation is still executing a particle
+        assert_eq!(pool.size, 1);
+        assert_eq!(pool.total(), 1);
+
+        pool.put_vm(busy);
+        assert_eq!(pool.size, 0);
+        assert_eq!(pool.free_vms(), 0);
+    }
+}
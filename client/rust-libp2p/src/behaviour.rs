@@ -26,10 +26,10 @@ use libp2p::core::Multiaddr;
 use libp2p::ping::{Ping, PingConfig, PingResult};
 use libp2p::swarm::{
     IntoProtocolsHandler, IntoProtocolsHandlerSelect, NetworkBehaviour, NetworkBehaviourAction,
-    NotifyHandler, OneShotHandler, PollParameters,
+    NotifyHandler, PollParameters,
 };
 use libp2p::PeerId;
-use particle_protocol::{HandlerMessage, Particle, ProtocolConfig};
+use particle_protocol::{HandlerMessage, Particle, ParticleHandler, ProtocolConfig};
 use std::collections::VecDeque;
 use std::error::Error;
 
@@ -56,7 +56,11 @@ impl ClientBehaviour {
     pub fn call(&mut self, peer_id: PeerId, call: Particle) {
         self.events
             .push_back(NetworkBehaviourAction::NotifyHandler {
-                event: EitherOutput::First(HandlerMessage::OutParticle(call, <_>::default())),
+                event: EitherOutput::First(HandlerMessage::OutParticle(
+                    call,
+                    <_>::default(),
+                    <_>::default(),
+                )),
                 handler: NotifyHandler::Any,
                 peer_id,
             });
@@ -72,10 +76,8 @@ impl ClientBehaviour {
 }
 
 impl NetworkBehaviour for ClientBehaviour {
-    type ProtocolsHandler = IntoProtocolsHandlerSelect<
-        <OneShotHandler<ProtocolConfig, HandlerMessage, HandlerMessage> as IntoProtocolsHandler>::Handler,
-        <Ping as NetworkBehaviour>::ProtocolsHandler,
-    >;
+    type ProtocolsHandler =
+        IntoProtocolsHandlerSelect<ParticleHandler, <Ping as NetworkBehaviour>::ProtocolsHandler>;
 
     type OutEvent = ClientEvent;
 
@@ -90,7 +90,11 @@ impl Client {
         }
     }
 
-    pub fn send(&self, particle: Particle, node: PeerId) {
+    /// Sends particle to the node. Particles initiated by this client are signed with its key
+    pub fn send(&self, mut particle: Particle, node: PeerId) {
+        if particle.init_peer_id == self.peer_id {
+            particle.sign(&self.key_pair);
+        }
         if let Err(err) = self.relay_outlet.unbounded_send(Command { node, particle }) {
             let err_msg = format!("{:?}", err);
             let msg = err.into_inner();
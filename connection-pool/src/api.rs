@@ -158,7 +158,7 @@ impl ConnectionPoolT for ConnectionPoolApi {
     }
 
     fn disconnect(&self, contact: Contact) -> BoxFuture<'static, bool> {
-        // TODO: timeout needed? will be clearer when disconnect is implemented
+        // timeout isn't needed because result is returned immediately
         self.execute(|out| Command::Disconnect { contact, out })
     }
 
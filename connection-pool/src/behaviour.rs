@@ -20,7 +20,9 @@ use fluence_libp2p::types::{
     BackPressuredInlet, BackPressuredOutlet, OneshotInlet, OneshotOutlet, Outlet,
 };
 use fluence_libp2p::{generate_swarm_event_type, remote_multiaddr};
-use particle_protocol::{CompletionChannel, Contact, HandlerMessage, Particle, ProtocolConfig};
+use particle_protocol::{
+    CompletionChannel, Contact, HandlerMessage, Particle, ParticleHandler, ProtocolConfig,
+};
 use trust_graph::TrustGraph;
 
 use std::{
@@ -46,11 +48,11 @@ use libp2p::{
     kad::Kademlia,
     swarm::{
         DialPeerCondition, IntoProtocolsHandlerSelect, NetworkBehaviour, NetworkBehaviourAction,
-        NetworkBehaviourEventProcess, NotifyHandler, OneShotHandler, PollParameters,
-        ProtocolsHandler,
+        NetworkBehaviourEventProcess, NotifyHandler, PollParameters, ProtocolsHandler,
     },
     PeerId,
 };
+use prometheus::{IntCounter, Registry};
 use std::error::Error;
 
 type SwarmEventType = generate_swarm_event_type!(ConnectionPoolBehaviour);
@@ -88,11 +90,23 @@ pub struct ConnectionPoolBehaviour {
 
     queue: VecDeque<Particle>,
     contacts: HashMap<PeerId, Peer>,
+    /// Established connections, used to close them on `disconnect`
+    connections: HashMap<PeerId, HashSet<ConnectionId>>,
+    /// Number of particles each connection can still take into its send queue
+    send_capacity: HashMap<ConnectionId, usize>,
+    /// Particles waiting for room in send queues of the peer's connections,
+    /// bounded by `ProtocolConfig::send_queue_size` per peer
+    waiting: HashMap<PeerId, VecDeque<(Particle, OneshotOutlet<bool>)>>,
     dialing: HashMap<Multiaddr, Vec<OneshotOutlet<Option<Contact>>>>,
 
     events: VecDeque<SwarmEventType>,
     waker: Option<Waker>,
     pub(super) protocol_config: ProtocolConfig,
+
+    /// Number of particles dropped because of invalid signature
+    invalid_particles: Option<IntCounter>,
+    /// Number of outbound particles dropped because send queue to the peer was full
+    dropped_particles: Option<IntCounter>,
 }
 
 impl ConnectionPoolBehaviour {
@@ -132,12 +146,25 @@ impl ConnectionPoolBehaviour {
         };
     }
 
-    // TODO: implement
-    pub fn disconnect(&mut self, contact: Contact, _outlet: OneshotOutlet<bool>) {
-        todo!(
-            "this doesn't make sense with OneShotHandler since connections are short-lived {:?}",
-            contact
-        )
+    /// Close all connections to the peer and forget its contact.
+    /// Returns `false` if peer wasn't connected or being dialed
+    pub fn disconnect(&mut self, contact: Contact, outlet: OneshotOutlet<bool>) {
+        let peer_id = contact.peer_id;
+        let connections = self.connections.remove(&peer_id).unwrap_or_default();
+        let known = !connections.is_empty() || self.contacts.contains_key(&peer_id);
+
+        for connection in connections {
+            self.send_capacity.remove(&connection);
+            self.push_event(NetworkBehaviourAction::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event: HandlerMessage::Disconnect,
+            });
+        }
+        self.fail_waiting(&peer_id);
+        self.remove_contact(&peer_id, "disconnect requested");
+
+        outlet.send(known).ok();
     }
 
     /// Returns whether given peer is connected or not
@@ -152,19 +179,43 @@ impl ConnectionPoolBehaviour {
     }
 
     /// Sends a particle to a connected contact. Returns whether sending succeeded or not
-    /// Result is sent to channel inside `upgrade_outbound` in ProtocolHandler
+    /// Result is sent to channel inside `upgrade_outbound` in ProtocolHandler.
+    /// If send queues of all connections to the contact are full, particle waits for room.
+    /// If too many particles are already waiting for that contact, particle is dropped.
     pub fn send(&mut self, to: Contact, particle: Particle, outlet: OneshotOutlet<bool>) {
         if to.peer_id == self.peer_id {
             // If particle is sent to the current node, process it locally
             self.queue.push_back(particle);
             outlet.send(true).ok();
             self.wake();
+        } else if self.connections.contains_key(&to.peer_id) {
+            let limit = self.protocol_config.send_queue_size;
+            let waiting = self.waiting.entry(to.peer_id).or_default();
+            if waiting.len() >= limit {
+                // make room by dropping particles nobody waits for anymore
+                waiting.retain(|(p, out)| !out.is_canceled() && !p.is_expired());
+            }
+            if waiting.len() >= limit {
+                #[rustfmt::skip]
+                log::warn!("Particle {} to {} was dropped, too many particles wait for send queue", particle.id, to.peer_id);
+                if let Some(counter) = &self.dropped_particles {
+                    counter.inc();
+                }
+                outlet.send(false).ok();
+            } else {
+                waiting.push_back((particle, outlet));
+            }
+            self.send_waiting(to.peer_id);
         } else {
-            // Send particle to remote peer
+            // Peer isn't connected, so particle is dropped along with the outlet
             self.push_event(NetworkBehaviourAction::NotifyHandler {
                 peer_id: to.peer_id,
                 handler: NotifyHandler::Any,
-                event: HandlerMessage::OutParticle(particle, CompletionChannel::Oneshot(outlet)),
+                event: HandlerMessage::OutParticle(
+                    particle,
+                    CompletionChannel::Oneshot(outlet),
+                    self.protocol_config.wire_format,
+                ),
             });
         }
     }
@@ -185,8 +236,25 @@ impl ConnectionPoolBehaviour {
         buffer: usize,
         protocol_config: ProtocolConfig,
         peer_id: PeerId,
+        registry: Option<&Registry>,
     ) -> (Self, BackPressuredInlet<Particle>) {
         let (outlet, inlet) = mpsc::channel(buffer);
+        let counter = |name: &str, help: &str| {
+            let registry = registry?;
+            let counter = IntCounter::new(name, help).ok()?;
+            if let Err(err) = registry.register(Box::new(counter.clone())) {
+                log::warn!("Failed to register {} metric: {}", name, err);
+            }
+            Some(counter)
+        };
+        let invalid_particles = counter(
+            "connection_pool_invalid_particles",
+            "Number of particles dropped because of invalid signature",
+        );
+        let dropped_particles = counter(
+            "connection_pool_dropped_particles",
+            "Number of outbound particles dropped because send queue to the peer was full",
+        );
 
         let this = Self {
             peer_id,
@@ -194,10 +262,15 @@ impl ConnectionPoolBehaviour {
             subscribers: <_>::default(),
             queue: <_>::default(),
             contacts: <_>::default(),
+            connections: <_>::default(),
+            send_capacity: <_>::default(),
+            waiting: <_>::default(),
             dialing: <_>::default(),
             events: <_>::default(),
             waker: None,
             protocol_config,
+            invalid_particles,
+            dropped_particles,
         };
 
         (this, inlet)
@@ -280,6 +353,80 @@ impl ConnectionPoolBehaviour {
         }
     }
 
+    /// Sends waiting particles to connections of `peer_id` that have room in their send queues
+    fn send_waiting(&mut self, peer_id: PeerId) {
+        loop {
+            let capacity = &self.send_capacity;
+            let connection = self
+                .connections
+                .get(&peer_id)
+                .into_iter()
+                .flatten()
+                .find(|id| capacity.get(id).map_or(false, |c| *c > 0))
+                .copied();
+            let connection = match connection {
+                Some(connection) => connection,
+                None => break,
+            };
+            let (particle, outlet) = match self.waiting.get_mut(&peer_id) {
+                Some(waiting) => match waiting.pop_front() {
+                    Some(next) => next,
+                    None => break,
+                },
+                None => break,
+            };
+            // sender gave up waiting, e.g. ConnectionPoolApi::send timed out
+            if outlet.is_canceled() {
+                continue;
+            }
+            if particle.is_expired() {
+                log::debug!(
+                    "Particle {} to {} expired while waiting",
+                    particle.id,
+                    peer_id
+                );
+                outlet.send(false).ok();
+                continue;
+            }
+
+            if let Some(capacity) = self.send_capacity.get_mut(&connection) {
+                *capacity -= 1;
+            }
+            self.push_event(NetworkBehaviourAction::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event: HandlerMessage::OutParticle(
+                    particle,
+                    CompletionChannel::Oneshot(outlet),
+                    self.protocol_config.wire_format,
+                ),
+            });
+        }
+
+        if self.waiting.get(&peer_id).map_or(false, |w| w.is_empty()) {
+            self.waiting.remove(&peer_id);
+        }
+    }
+
+    /// Notifies senders of particles waiting for `peer_id` that sending failed
+    fn fail_waiting(&mut self, peer_id: &PeerId) {
+        for (particle, outlet) in self.waiting.remove(peer_id).into_iter().flatten() {
+            log::debug!(
+                "Particle {} to {} wasn't sent, peer is disconnected",
+                particle.id,
+                peer_id
+            );
+            outlet.send(false).ok();
+        }
+    }
+
+    fn send_queue_ready(&mut self, peer_id: PeerId, connection: ConnectionId, n: usize) {
+        if let Some(capacity) = self.send_capacity.get_mut(&connection) {
+            *capacity += n;
+            self.send_waiting(peer_id);
+        }
+    }
+
     fn get_contact_impl(&self, peer_id: PeerId) -> Option<Contact> {
         match self.contacts.get(&peer_id) {
             Some(Peer::Connected(addrs)) => {
@@ -291,7 +438,7 @@ impl ConnectionPoolBehaviour {
 }
 
 impl NetworkBehaviour for ConnectionPoolBehaviour {
-    type ProtocolsHandler = OneShotHandler<ProtocolConfig, HandlerMessage, HandlerMessage>;
+    type ProtocolsHandler = ParticleHandler;
     type OutEvent = ();
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
@@ -322,11 +469,16 @@ impl NetworkBehaviour for ConnectionPoolBehaviour {
     fn inject_connection_established(
         &mut self,
         peer_id: &PeerId,
-        _: &ConnectionId,
+        id: &ConnectionId,
         cp: &ConnectedPoint,
     ) {
         let multiaddr = remote_multiaddr(cp).clone();
 
+        self.connections.entry(*peer_id).or_default().insert(*id);
+        let capacity = self.protocol_config.send_queue_size;
+        self.send_capacity.insert(*id, capacity);
+        self.send_waiting(*peer_id);
+
         self.add_address(*peer_id, multiaddr.clone());
 
         self.lifecycle_event(LifecycleEvent::Connected(Contact::new(
@@ -335,6 +487,24 @@ impl NetworkBehaviour for ConnectionPoolBehaviour {
         )))
     }
 
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        id: &ConnectionId,
+        _: &ConnectedPoint,
+    ) {
+        self.send_capacity.remove(id);
+        if let Entry::Occupied(mut entry) = self.connections.entry(*peer_id) {
+            entry.get_mut().remove(id);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+        if !self.connections.contains_key(peer_id) {
+            self.fail_waiting(peer_id);
+        }
+    }
+
     fn inject_addr_reach_failure(
         &mut self,
         peer_id: Option<&PeerId>,
@@ -381,18 +551,37 @@ impl NetworkBehaviour for ConnectionPoolBehaviour {
     fn inject_event(
         &mut self,
         from: PeerId,
-        _: ConnectionId,
+        connection: ConnectionId,
         event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
         match event {
             HandlerMessage::InParticle(particle) => {
+                if let Err(err) = particle.verify() {
+                    log::warn!("Dropping particle {} from {}: {}", particle.id, from, err);
+                    if let Some(counter) = &self.invalid_particles {
+                        counter.inc();
+                    }
+                    return;
+                }
                 log::trace!(target: "network", "received particle {} from {}; queue {}", particle.id, from, self.queue.len());
                 self.queue.push_back(particle);
                 self.wake();
             }
+            HandlerMessage::SendQueueFull(particle_id) => {
+                // sender was notified through its completion channel
+                #[rustfmt::skip]
+                log::warn!("Particle {} to {} was dropped, send queue is full", particle_id, from);
+                if let Some(counter) = &self.dropped_particles {
+                    counter.inc();
+                }
+                // dropped particle never took its place in the queue
+                self.send_queue_ready(from, connection, 1);
+            }
+            HandlerMessage::SendQueueReady(n) => self.send_queue_ready(from, connection, n),
             HandlerMessage::InboundUpgradeError(err) => log::warn!("UpgradeError: {:?}", err),
             HandlerMessage::Upgrade => {}
             HandlerMessage::OutParticle(..) => unreachable!("can't receive OutParticle"),
+            HandlerMessage::Disconnect => unreachable!("can't receive Disconnect"),
         }
     }
 
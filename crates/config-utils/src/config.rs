@@ -34,6 +34,9 @@ pub fn modules_dir(path: &Path) -> PathBuf {
 pub fn services_dir(path: &Path) -> PathBuf {
     path.join("services")
 }
+pub fn scripts_dir(path: &Path) -> PathBuf {
+    path.join("scripts")
+}
 pub fn particles_dir(path: &Path) -> PathBuf {
     path.join("particles")
 }
@@ -31,6 +31,7 @@ pub use config::blueprint_dir;
 pub use config::create_dirs;
 pub use config::modules_dir;
 pub use config::particles_dir;
+pub use config::scripts_dir;
 pub use config::services_dir;
 pub use config::to_abs_path;
 pub use config::to_peer_id;
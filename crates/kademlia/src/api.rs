@@ -15,7 +15,7 @@
  */
 
 use crate::error::{KademliaError, Result};
-use crate::Kademlia;
+use crate::{BannedPeer, Kademlia, ProviderRecord, Quorum, RoutingPeer};
 
 use fluence_libp2p::generate_swarm_event_type;
 use fluence_libp2p::types::{Inlet, OneshotOutlet, Outlet};
@@ -29,6 +29,7 @@ use futures::{
 use libp2p::{core::Multiaddr, identity::ed25519, swarm::NetworkBehaviourEventProcess, PeerId};
 use multihash::Multihash;
 use std::convert::identity;
+use std::time::Duration;
 
 type Future<T> = BoxFuture<'static, T>;
 
@@ -38,6 +39,30 @@ pub trait KademliaApiT {
     fn local_lookup(&self, peer: PeerId) -> Future<Result<Vec<Multiaddr>>>;
     fn discover_peer(&self, peer: PeerId) -> Future<Result<Vec<Multiaddr>>>;
     fn neighborhood(&self, key: Multihash) -> Future<Result<Vec<PeerId>>>;
+    fn add_provider(
+        &self,
+        key: String,
+        service_id: Option<String>,
+        ttl: Option<Duration>,
+    ) -> Future<Result<()>>;
+    fn get_providers(&self, key: String) -> Future<Result<Vec<ProviderRecord>>>;
+    fn put_value(
+        &self,
+        publisher: PeerId,
+        key: String,
+        value: Vec<u8>,
+        quorum: Quorum,
+    ) -> Future<Result<()>>;
+    fn get_value(
+        &self,
+        publisher: PeerId,
+        key: String,
+        quorum: Quorum,
+    ) -> Future<Result<Vec<Vec<u8>>>>;
+    fn remove_value(&self, publisher: PeerId, key: String, quorum: Quorum) -> Future<Result<()>>;
+    fn routing_table(&self) -> Future<Result<Vec<RoutingPeer>>>;
+    fn banned(&self) -> Future<Result<Vec<BannedPeer>>>;
+    fn unban(&self, peer: PeerId) -> Future<Result<bool>>;
 }
 
 #[derive(Debug)]
@@ -60,6 +85,45 @@ enum Command {
         key: Multihash,
         out: OneshotOutlet<Result<Vec<PeerId>>>,
     },
+    AddProvider {
+        key: String,
+        service_id: Option<String>,
+        ttl: Option<Duration>,
+        out: OneshotOutlet<Result<()>>,
+    },
+    GetProviders {
+        key: String,
+        out: OneshotOutlet<Result<Vec<ProviderRecord>>>,
+    },
+    PutValue {
+        publisher: PeerId,
+        key: String,
+        value: Vec<u8>,
+        quorum: Quorum,
+        out: OneshotOutlet<Result<()>>,
+    },
+    GetValue {
+        publisher: PeerId,
+        key: String,
+        quorum: Quorum,
+        out: OneshotOutlet<Result<Vec<Vec<u8>>>>,
+    },
+    RemoveValue {
+        publisher: PeerId,
+        key: String,
+        quorum: Quorum,
+        out: OneshotOutlet<Result<()>>,
+    },
+    RoutingTable {
+        out: OneshotOutlet<Result<Vec<RoutingPeer>>>,
+    },
+    Banned {
+        out: OneshotOutlet<Result<Vec<BannedPeer>>>,
+    },
+    Unban {
+        peer: PeerId,
+        out: OneshotOutlet<Result<bool>>,
+    },
 }
 
 pub type SwarmEventType = generate_swarm_event_type!(KademliaApiInlet);
@@ -95,6 +159,35 @@ impl KademliaApiInlet {
             Command::LocalLookup { peer, out } => self.kademlia.local_lookup(&peer, out),
             Command::DiscoverPeer { peer, out } => self.kademlia.discover_peer(peer, out),
             Command::Neighborhood { key, out } => self.kademlia.neighborhood(key, out),
+            Command::AddProvider {
+                key,
+                service_id,
+                ttl,
+                out,
+            } => self.kademlia.add_provider(key, service_id, ttl, out),
+            Command::GetProviders { key, out } => self.kademlia.get_providers(key, out),
+            Command::PutValue {
+                publisher,
+                key,
+                value,
+                quorum,
+                out,
+            } => self.kademlia.put_value(publisher, key, value, quorum, out),
+            Command::GetValue {
+                publisher,
+                key,
+                quorum,
+                out,
+            } => self.kademlia.get_value(publisher, key, quorum, out),
+            Command::RemoveValue {
+                publisher,
+                key,
+                quorum,
+                out,
+            } => self.kademlia.remove_value(publisher, key, quorum, out),
+            Command::RoutingTable { out } => self.kademlia.routing_table(out),
+            Command::Banned { out } => self.kademlia.banned(out),
+            Command::Unban { peer, out } => self.kademlia.unban(peer, out),
         }
     }
 
@@ -180,4 +273,73 @@ impl KademliaApiT for KademliaApi {
     fn neighborhood(&self, key: Multihash) -> Future<Result<Vec<PeerId>>> {
         self.execute(|out| Command::Neighborhood { key, out })
     }
+
+    fn add_provider(
+        &self,
+        key: String,
+        service_id: Option<String>,
+        ttl: Option<Duration>,
+    ) -> Future<Result<()>> {
+        self.execute(|out| Command::AddProvider {
+            key,
+            service_id,
+            ttl,
+            out,
+        })
+    }
+
+    fn get_providers(&self, key: String) -> Future<Result<Vec<ProviderRecord>>> {
+        self.execute(|out| Command::GetProviders { key, out })
+    }
+
+    fn put_value(
+        &self,
+        publisher: PeerId,
+        key: String,
+        value: Vec<u8>,
+        quorum: Quorum,
+    ) -> Future<Result<()>> {
+        self.execute(|out| Command::PutValue {
+            publisher,
+            key,
+            value,
+            quorum,
+            out,
+        })
+    }
+
+    fn get_value(
+        &self,
+        publisher: PeerId,
+        key: String,
+        quorum: Quorum,
+    ) -> Future<Result<Vec<Vec<u8>>>> {
+        self.execute(|out| Command::GetValue {
+            publisher,
+            key,
+            quorum,
+            out,
+        })
+    }
+
+    fn remove_value(&self, publisher: PeerId, key: String, quorum: Quorum) -> Future<Result<()>> {
+        self.execute(|out| Command::RemoveValue {
+            publisher,
+            key,
+            quorum,
+            out,
+        })
+    }
+
+    fn routing_table(&self) -> Future<Result<Vec<RoutingPeer>>> {
+        self.execute(|out| Command::RoutingTable { out })
+    }
+
+    fn banned(&self) -> Future<Result<Vec<BannedPeer>>> {
+        self.execute(|out| Command::Banned { out })
+    }
+
+    fn unban(&self, peer: PeerId) -> Future<Result<bool>> {
+        self.execute(|out| Command::Unban { peer, out })
+    }
 }
@@ -15,6 +15,10 @@
  */
 
 use crate::error::{KademliaError, Result};
+use crate::metrics::KademliaMetrics;
+use crate::persistence::{self, StoredPeer, StoredRecord};
+use crate::providers::{self, ProviderRecord};
+use crate::store::{value_key, Store};
 
 use control_macro::get_return;
 use fluence_libp2p::generate_swarm_event_type;
@@ -22,32 +26,43 @@ use fluence_libp2p::types::OneshotOutlet;
 use particle_protocol::Contact;
 use trust_graph::TrustGraph;
 
+use async_std::task;
+use futures::{future::BoxFuture, FutureExt};
 use libp2p::identity::PublicKey;
+use libp2p::kad::store::RecordStore;
 use libp2p::{
     core::Multiaddr,
     identity::{ed25519, ed25519::Keypair},
     kad::{
-        self, store::MemoryStore, BootstrapError, BootstrapOk, BootstrapResult,
-        GetClosestPeersError, GetClosestPeersOk, GetClosestPeersResult, KademliaEvent, QueryId,
-        QueryResult,
+        self, kbucket::NodeStatus, BootstrapError, BootstrapOk, BootstrapResult,
+        GetClosestPeersError, GetClosestPeersOk, GetClosestPeersResult, GetRecordError,
+        GetRecordOk, GetRecordResult, KademliaEvent, PutRecordError, PutRecordOk, PutRecordResult,
+        QueryId, QueryResult, Quorum, Record,
     },
     swarm::{NetworkBehaviour, NetworkBehaviourEventProcess},
     PeerId,
 };
 use multihash::Multihash;
+use now_millis::now_sec;
 use prometheus::Registry;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::task::Waker;
 use std::{
     collections::HashMap,
     time::{Duration, Instant},
 };
 
+/// How long an empty record left by `remove_value` replaces the removed value on replicas
+const TOMBSTONE_TTL: Duration = Duration::from_secs(10 * 60);
+
 pub struct KademliaConfig {
     pub peer_id: PeerId,
     pub keypair: Keypair,
     // TODO: wonderful name clashing. I guess it is better to rename one of the KademliaConfig's to something else. You'll figure it out.
     pub kad_config: server_config::KademliaConfig,
+    /// Where to keep routing table and records between restarts. Nothing is persisted if None
+    pub persistence_dir: Option<PathBuf>,
 }
 
 impl Deref for KademliaConfig {
@@ -63,6 +78,8 @@ pub enum PendingQuery {
     Peer(PeerId),
     Neighborhood(OneshotOutlet<Result<Vec<PeerId>>>),
     Unit(OneshotOutlet<Result<()>>),
+    Providers(OneshotOutlet<Result<Vec<ProviderRecord>>>),
+    Values(OneshotOutlet<Result<Vec<Vec<u8>>>>),
 }
 
 #[derive(Debug)]
@@ -92,6 +109,32 @@ impl FailedPeer {
     pub fn increment(&mut self) {
         self.count += 1;
     }
+
+    /// Time left until the ban is lifted, None if peer isn't banned
+    pub fn unban_in(&self, cooldown: Duration) -> Option<Duration> {
+        let ban = self.ban?;
+        cooldown
+            .checked_sub(ban.elapsed())
+            .filter(|d| *d > Duration::default())
+    }
+}
+
+/// Peer from the local routing table
+#[derive(Debug, Clone)]
+pub struct RoutingPeer {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+    /// Index of the k-bucket, i.e. log2 of XOR distance to the local peer
+    pub bucket: usize,
+    pub connected: bool,
+}
+
+/// Peer that failed to be discovered too many times, and isn't looked up until unbanned
+#[derive(Debug, Clone)]
+pub struct BannedPeer {
+    pub peer_id: PeerId,
+    pub failures: usize,
+    pub unban_in: Duration,
 }
 
 type SwarmEventType = generate_swarm_event_type!(Kademlia);
@@ -99,7 +142,7 @@ type SwarmEventType = generate_swarm_event_type!(Kademlia);
 #[derive(::libp2p::NetworkBehaviour)]
 #[behaviour(poll_method = "custom_poll")]
 pub struct Kademlia {
-    kademlia: kad::Kademlia<MemoryStore>,
+    kademlia: kad::Kademlia<Store>,
 
     #[behaviour(ignore)]
     queries: HashMap<QueryId, PendingQuery>,
@@ -111,6 +154,13 @@ pub struct Kademlia {
     config: KademliaConfig,
     #[behaviour(ignore)]
     waker: Option<Waker>,
+    #[behaviour(ignore)]
+    persist_timer: Option<wasm_timer::Delay>,
+    #[behaviour(ignore)]
+    /// Periodic save of routing table and records, written to disk on a blocking task
+    persisting: Option<BoxFuture<'static, ()>>,
+    #[behaviour(ignore)]
+    metrics: Option<KademliaMetrics>,
 }
 
 impl Kademlia {
@@ -119,7 +169,19 @@ impl Kademlia {
         trust_graph: TrustGraph,
         registry: Option<&Registry>,
     ) -> Self {
-        let store = MemoryStore::new(config.peer_id);
+        let mut store = Store::new(config.peer_id, config.as_store_config());
+        let mut peers = vec![];
+        if let Some(dir) = &config.persistence_dir {
+            let now = now_sec();
+            let records = persistence::load_records(dir);
+            let records = records.into_iter().filter_map(|r| r.into_record(now));
+            for record in records {
+                if let Err(err) = store.put(record) {
+                    log::warn!("unable to restore kademlia record: {:?}", err);
+                }
+            }
+            peers = persistence::load_peers(dir);
+        }
 
         let mut kademlia = kad::Kademlia::with_config(
             config.keypair.clone(),
@@ -129,10 +191,24 @@ impl Kademlia {
             trust_graph,
         );
 
+        let metrics = registry.and_then(KademliaMetrics::new);
         if let Some(registry) = registry {
             kademlia.enable_metrics(registry);
         }
 
+        for peer in peers {
+            if let Some(public_key) = peer.public_key() {
+                for addr in peer.addresses {
+                    kademlia.add_address(&peer.peer_id, addr, public_key.clone());
+                }
+            }
+        }
+
+        let persist_timer = config
+            .persistence_dir
+            .as_ref()
+            .map(|_| wasm_timer::Delay::new(config.persist_interval));
+
         Self {
             kademlia,
             queries: <_>::default(),
@@ -140,7 +216,56 @@ impl Kademlia {
             failed_peers: <_>::default(),
             config,
             waker: None,
+            persist_timer,
+            persisting: None,
+            metrics,
+        }
+    }
+
+    /// Saves routing table and non-expired records to `persistence_dir`, blocking until saved.
+    /// Periodic saves don't block the swarm, see `start_persist`
+    pub fn persist(&mut self) {
+        let dir = get_return!(self.config.persistence_dir.clone());
+        // wait for periodic save, so it doesn't overwrite the latest state
+        if let Some(persisting) = self.persisting.take() {
+            task::block_on(persisting);
+        }
+
+        let (peers, records) = self.snapshot();
+        save_state(&dir, &peers, &records);
+    }
+
+    /// Takes snapshot of routing table and records, and saves it to `persistence_dir` on a
+    /// blocking task. Returns None if persistence is disabled
+    fn start_persist(&mut self) -> Option<BoxFuture<'static, ()>> {
+        let dir = self.config.persistence_dir.clone()?;
+        let (peers, records) = self.snapshot();
+
+        Some(task::spawn_blocking(move || save_state(&dir, &peers, &records)).boxed())
+    }
+
+    fn snapshot(&mut self) -> (Vec<StoredPeer>, Vec<StoredRecord>) {
+        let mut peers = vec![];
+        for bucket in self.kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                let contact = entry.node.value;
+                let addresses = contact.addresses.iter().cloned().collect();
+                let peer_id = entry.node.key.preimage();
+                peers.push(StoredPeer::new(*peer_id, &contact.public_key, addresses));
+            }
         }
+
+        let now = now_sec();
+        let instant = Instant::now();
+        let records: Vec<_> = self
+            .kademlia
+            .store_mut()
+            .records()
+            .filter(|r| !r.is_expired(instant))
+            .map(|r| StoredRecord::new(&r, now))
+            .collect();
+
+        (peers, records)
     }
 
     pub fn add_kad_node(
@@ -237,6 +362,146 @@ impl Kademlia {
             .insert(query_id, PendingQuery::Neighborhood(outlet));
         self.wake();
     }
+
+    /// Announces local peer (or its service) as a provider of `key` on the K closest peers.
+    /// Record is signed by the local peer and expires after `ttl`, or configured `provider_ttl`
+    pub fn add_provider(
+        &mut self,
+        key: String,
+        service_id: Option<String>,
+        ttl: Option<Duration>,
+        outlet: OneshotOutlet<Result<()>>,
+    ) {
+        let now = now_sec();
+        let ttl = ttl.unwrap_or(self.config.provider_ttl);
+        let provider = ProviderRecord::signed(key, service_id, ttl, now, &self.config.keypair);
+        let mut record = Record::new(
+            providers::providers_key(&provider.key),
+            providers::encode(&[provider]),
+        );
+        record.expires = Some(Instant::now() + ttl);
+
+        match self.kademlia.put_record(record, Quorum::One) {
+            Ok(query_id) => {
+                self.queries.insert(query_id, PendingQuery::Unit(outlet));
+                self.wake();
+            }
+            Err(err) => {
+                outlet.send(Err(KademliaError::StoreError(err))).ok();
+            }
+        }
+    }
+
+    /// Collects providers of `key` from the K closest peers
+    pub fn get_providers(
+        &mut self,
+        key: String,
+        outlet: OneshotOutlet<Result<Vec<ProviderRecord>>>,
+    ) {
+        let query_id = self
+            .kademlia
+            .get_record(&providers::providers_key(&key), Quorum::All);
+        self.queries
+            .insert(query_id, PendingQuery::Providers(outlet));
+        self.wake();
+    }
+
+    pub fn routing_table(&mut self, outlet: OneshotOutlet<Result<Vec<RoutingPeer>>>) {
+        let mut peers = vec![];
+        for bucket in self.kademlia.kbuckets() {
+            for entry in bucket.iter() {
+                peers.push(RoutingPeer {
+                    peer_id: *entry.node.key.preimage(),
+                    addresses: entry.node.value.addresses.iter().cloned().collect(),
+                    bucket: bucket.index.get(),
+                    connected: entry.status == NodeStatus::Connected,
+                });
+            }
+        }
+        outlet.send(Ok(peers)).ok();
+    }
+
+    pub fn banned(&mut self, outlet: OneshotOutlet<Result<Vec<BannedPeer>>>) {
+        let cooldown = self.config.ban_cooldown;
+        let banned = self.failed_peers.iter().filter_map(|(peer_id, failed)| {
+            Some(BannedPeer {
+                peer_id: *peer_id,
+                failures: failed.count,
+                unban_in: failed.unban_in(cooldown)?,
+            })
+        });
+        outlet.send(Ok(banned.collect())).ok();
+    }
+
+    /// Forgets failures of the peer, so it can be discovered again. Returns whether peer was banned
+    pub fn unban(&mut self, peer: PeerId, outlet: OneshotOutlet<Result<bool>>) {
+        let was_banned = match self.failed_peers.remove(&peer) {
+            Some(failed) => failed.unban_in(self.config.ban_cooldown).is_some(),
+            None => false,
+        };
+        self.update_ban_metrics(0);
+        outlet.send(Ok(was_banned)).ok();
+    }
+
+    /// Stores `value` locally and on the K closest peers to `key` in `publisher`'s namespace,
+    /// succeeds when at least `quorum` peers have stored it
+    pub fn put_value(
+        &mut self,
+        publisher: PeerId,
+        key: String,
+        value: Vec<u8>,
+        quorum: Quorum,
+        outlet: OneshotOutlet<Result<()>>,
+    ) {
+        let record = Record::new(value_key(&publisher, &key), value);
+        match self.kademlia.put_record(record, quorum) {
+            Ok(query_id) => {
+                self.queries.insert(query_id, PendingQuery::Unit(outlet));
+                self.wake();
+            }
+            Err(err) => {
+                outlet.send(Err(KademliaError::StoreError(err))).ok();
+            }
+        }
+    }
+
+    /// Looks up values stored by `publisher` under `key`, waiting for at least `quorum` of them.
+    /// Replicas may diverge, so all distinct values are returned
+    pub fn get_value(
+        &mut self,
+        publisher: PeerId,
+        key: String,
+        quorum: Quorum,
+        outlet: OneshotOutlet<Result<Vec<Vec<u8>>>>,
+    ) {
+        let key = value_key(&publisher, &key);
+        let query_id = self.kademlia.get_record(&key, quorum);
+        self.queries.insert(query_id, PendingQuery::Values(outlet));
+        self.wake();
+    }
+
+    /// Replaces value `publisher` stored under `key` with an empty record that expires after
+    /// `TOMBSTONE_TTL`, both locally and on the K closest peers. Succeeds when at least `quorum`
+    /// peers have stored it. Empty values are never returned by `get_value`
+    pub fn remove_value(
+        &mut self,
+        publisher: PeerId,
+        key: String,
+        quorum: Quorum,
+        outlet: OneshotOutlet<Result<()>>,
+    ) {
+        let mut tombstone = Record::new(value_key(&publisher, &key), vec![]);
+        tombstone.expires = Some(Instant::now() + TOMBSTONE_TTL);
+        match self.kademlia.put_record(tombstone, quorum) {
+            Ok(query_id) => {
+                self.queries.insert(query_id, PendingQuery::Unit(outlet));
+                self.wake();
+            }
+            Err(err) => {
+                outlet.send(Err(KademliaError::StoreError(err))).ok();
+            }
+        }
+    }
 }
 
 impl Kademlia {
@@ -282,6 +547,70 @@ impl Kademlia {
             PendingQuery::Unit(outlet) => {
                 outlet.send(Ok(())).ok();
             }
+            PendingQuery::Providers(outlet) => {
+                outlet.send(Err(KademliaError::NoPeersFound)).ok();
+            }
+            PendingQuery::Values(outlet) => {
+                outlet.send(Err(KademliaError::NoPeersFound)).ok();
+            }
+        }
+    }
+
+    fn put_finished(&mut self, id: QueryId, result: PutRecordResult) {
+        use PutRecordError::{QuorumFailed, Timeout};
+
+        if let Some(PendingQuery::Unit(outlet)) = self.queries.remove(&id) {
+            let result = match result {
+                Ok(PutRecordOk { .. }) => Ok(()),
+                Err(Timeout {
+                    success, quorum, ..
+                }) if success.len() >= quorum.get() => Ok(()),
+                Err(Timeout { .. }) => Err(KademliaError::Timeout),
+                Err(QuorumFailed { .. }) => Err(KademliaError::QuorumFailed),
+            };
+            outlet.send(result).ok();
+        }
+    }
+
+    fn get_finished(&mut self, id: QueryId, result: GetRecordResult) {
+        use GetRecordError::{NotFound, QuorumFailed, Timeout};
+
+        match get_return!(self.queries.remove(&id)) {
+            PendingQuery::Providers(outlet) => {
+                // quorum is never expected to be reached, so take whatever was found
+                let records = match result {
+                    Ok(GetRecordOk { records }) => records,
+                    Err(QuorumFailed { records, .. }) | Err(Timeout { records, .. }) => records,
+                    Err(NotFound { .. }) => vec![],
+                };
+                let now = now_sec();
+                let found = records
+                    .into_iter()
+                    .flat_map(|r| providers::decode(&r.record.key, &r.record.value, now));
+                outlet.send(Ok(providers::merge(found))).ok();
+            }
+            PendingQuery::Values(outlet) => {
+                let result = match result {
+                    Ok(GetRecordOk { records }) => {
+                        // empty value is a tombstone left by remove_value
+                        let mut values: Vec<_> = records
+                            .into_iter()
+                            .map(|r| r.record.value)
+                            .filter(|v| !v.is_empty())
+                            .collect();
+                        values.sort();
+                        values.dedup();
+                        Ok(values)
+                    }
+                    Err(NotFound { .. }) => Ok(vec![]),
+                    Err(QuorumFailed { .. }) => Err(KademliaError::QuorumFailed),
+                    Err(Timeout { .. }) => Err(KademliaError::Timeout),
+                };
+                outlet.send(result).ok();
+            }
+            other => {
+                log::warn!("unexpected GetRecord result for query {:?}", other);
+            }
         }
     }
 
@@ -311,6 +640,20 @@ impl Kademlia {
 
         self.waker = Some(cx.waker().clone());
 
+        if let Some(Poll::Ready(_)) = self.persist_timer.as_mut().map(|t| t.poll_unpin(cx)) {
+            // previous save is still being written, skip this one
+            if self.persisting.is_none() {
+                self.persisting = self.start_persist();
+            }
+            if let Some(timer) = self.persist_timer.as_mut() {
+                timer.reset(self.config.persist_interval);
+            }
+        }
+
+        if let Some(Poll::Ready(_)) = self.persisting.as_mut().map(|p| p.poll_unpin(cx)) {
+            self.persisting = None;
+        }
+
         // Exit early to avoid Instant::now calculation
         if self.pending_peers.is_empty() {
             return Poll::Pending;
@@ -339,21 +682,22 @@ impl Kademlia {
         });
 
         let config = self.config.deref();
+        let mut new_bans = 0;
         self.failed_peers.retain(|_, failed| {
             if let Some(ban) = failed.ban {
                 if now.duration_since(ban) >= config.ban_cooldown {
                     // unban (remove) a peer if cooldown has passed
                     return false;
                 }
-            }
-
-            // ban peers with too many failures
-            if failed.count >= config.peer_fail_threshold {
+            } else if failed.count >= config.peer_fail_threshold {
+                // ban peers with too many failures
                 failed.ban = Some(now);
+                new_bans += 1;
             }
 
             true
         });
+        self.update_ban_metrics(new_bans);
 
         // NOTE: task will not be awaken until something happens;
         //       that implies that timeouts are of low resolution
@@ -367,9 +711,38 @@ impl Kademlia {
     }
 
     fn is_banned(&self, peer: &PeerId) -> bool {
+        let cooldown = self.config.ban_cooldown;
         self.failed_peers
             .get(peer)
-            .map_or(false, |f| f.ban.is_some())
+            .map_or(false, |f| f.unban_in(cooldown).is_some())
+    }
+
+    fn update_ban_metrics(&self, new_bans: usize) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            let banned = self.failed_peers.values().filter(|f| f.ban.is_some());
+            metrics.banned(banned.count(), new_bans);
+        }
+    }
+
+    fn update_routing_metrics(&mut self) {
+        if let Some(metrics) = self.metrics.as_ref() {
+            let buckets = self.kademlia.kbuckets();
+            metrics.routing_table(buckets.map(|b| (b.index.get(), b.num_entries())));
+        }
+    }
+}
+
+fn save_state(dir: &Path, peers: &[StoredPeer], records: &[StoredRecord]) {
+    let saved =
+        persistence::save_peers(dir, peers).and_then(|_| persistence::save_records(dir, records));
+    if let Err(err) = saved {
+        log::warn!("unable to persist kademlia state to {:?}: {}", dir, err);
+    }
+}
+
+impl Drop for Kademlia {
+    fn drop(&mut self) {
+        self.persist()
     }
 }
 
@@ -379,12 +752,17 @@ impl NetworkBehaviourEventProcess<KademliaEvent> for Kademlia {
             KademliaEvent::QueryResult { id, result, .. } => match result {
                 QueryResult::GetClosestPeers(result) => self.closest_finished(id, result),
                 QueryResult::Bootstrap(result) => self.bootstrap_finished(id, result),
+                QueryResult::PutRecord(result) => self.put_finished(id, result),
+                QueryResult::GetRecord(result) => self.get_finished(id, result),
                 _ => {}
             },
             KademliaEvent::UnroutablePeer { .. } => {}
             KademliaEvent::RoutingUpdated {
                 peer, addresses, ..
-            } => self.peer_discovered(peer, addresses.into_vec()),
+            } => {
+                self.peer_discovered(peer, addresses.into_vec());
+                self.update_routing_metrics();
+            }
             KademliaEvent::RoutablePeer { peer, address }
             | KademliaEvent::PendingRoutablePeer { peer, address } => {
                 self.peer_discovered(peer, vec![address])
@@ -425,6 +803,7 @@ mod tests {
                 ban_cooldown: Duration::from_secs(1),
                 ..Default::default()
             },
+            persistence_dir: None,
         }
     }
 
@@ -524,4 +903,31 @@ mod tests {
             .unwrap();
         assert!(matches!(banned, Err(KademliaError::PeerBanned)));
     }
+
+    #[test]
+    fn unban() {
+        use async_std::future::timeout;
+
+        let (mut node, _, _) = make_node();
+        let peer = RandomPeerId::random();
+
+        node.discover_peer(peer, oneshot::channel().0);
+        task::block_on(timeout(Duration::from_millis(200), node.select_next_some())).ok();
+
+        let (out, inlet) = oneshot::channel();
+        node.banned(out);
+        let banned = task::block_on(inlet).unwrap().unwrap();
+        assert_eq!(banned.len(), 1);
+        assert_eq!(banned[0].peer_id, peer);
+        assert!(banned[0].unban_in <= Duration::from_secs(1));
+
+        let (out, inlet) = oneshot::channel();
+        node.unban(peer, out);
+        assert!(task::block_on(inlet).unwrap().unwrap());
+        assert!(!node.is_banned(&peer));
+
+        let (out, inlet) = oneshot::channel();
+        node.unban(peer, out);
+        assert!(!task::block_on(inlet).unwrap().unwrap());
+    }
 }
@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 
+use libp2p::kad::store;
 use thiserror::Error;
 
 pub(crate) type Result<T> = std::result::Result<T, KademliaError>;
@@ -30,4 +31,8 @@ pub enum KademliaError {
     NoKnownPeers,
     #[error("KademliaError::PeerBanned")]
     PeerBanned,
+    #[error("KademliaError::StoreError: {0:?}")]
+    StoreError(store::Error),
+    #[error("KademliaError::QuorumFailed")]
+    QuorumFailed,
 }
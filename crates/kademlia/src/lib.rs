@@ -18,9 +18,18 @@
 mod api;
 mod behaviour;
 mod error;
+mod metrics;
+mod persistence;
+mod providers;
+mod store;
 
 pub use api::KademliaApiT;
 pub use api::{KademliaApi, KademliaApiInlet};
 pub use behaviour::Kademlia;
 pub use behaviour::KademliaConfig;
+pub use behaviour::{BannedPeer, RoutingPeer};
 pub use error::KademliaError;
+pub use providers::{providers_key, ProviderRecord};
+pub use store::value_key;
+
+pub use libp2p::kad::Quorum;
@@ -0,0 +1,87 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use prometheus::{IntCounter, IntGauge, IntGaugeVec, Opts, Registry};
+
+/// Routing table occupancy and peer bans
+pub(crate) struct KademliaMetrics {
+    /// Number of peers in each non-empty bucket, labeled by bucket index
+    bucket_entries: IntGaugeVec,
+    /// Number of peers in the routing table
+    routing_table_size: IntGauge,
+    /// Number of currently banned peers
+    banned: IntGauge,
+    /// Number of bans since start
+    bans: IntCounter,
+}
+
+impl KademliaMetrics {
+    pub fn new(registry: &Registry) -> Option<Self> {
+        let bucket_entries = IntGaugeVec::new(
+            Opts::new(
+                "kademlia_bucket_entries",
+                "Number of peers in a routing table bucket",
+            ),
+            &["bucket"],
+        )
+        .ok()?;
+        let routing_table_size = IntGauge::new(
+            "kademlia_routing_table_size",
+            "Number of peers in the routing table",
+        )
+        .ok()?;
+        let banned = IntGauge::new(
+            "kademlia_banned_peers",
+            "Number of peers currently banned for failed discoveries",
+        )
+        .ok()?;
+        let bans = IntCounter::new("kademlia_bans", "Number of peer bans since start").ok()?;
+
+        let registered = registry
+            .register(Box::new(bucket_entries.clone()))
+            .and_then(|_| registry.register(Box::new(routing_table_size.clone())))
+            .and_then(|_| registry.register(Box::new(banned.clone())))
+            .and_then(|_| registry.register(Box::new(bans.clone())));
+        if let Err(err) = registered {
+            log::warn!("Failed to register kademlia metrics: {}", err);
+        }
+
+        Some(Self {
+            bucket_entries,
+            routing_table_size,
+            banned,
+            bans,
+        })
+    }
+
+    /// `buckets` are (index, number of entries) of all non-empty buckets
+    pub fn routing_table(&self, buckets: impl Iterator<Item = (usize, usize)>) {
+        self.bucket_entries.reset();
+        let mut size = 0;
+        for (index, entries) in buckets {
+            size += entries;
+            self.bucket_entries
+                .with_label_values(&[&index.to_string()])
+                .set(entries as i64);
+        }
+        self.routing_table_size.set(size as i64);
+    }
+
+    pub fn banned(&self, banned: usize, new_bans: usize) {
+        self.banned.set(banned as i64);
+        self.bans.inc_by(new_bans as i64);
+    }
+}
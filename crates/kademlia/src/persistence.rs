@@ -0,0 +1,185 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Routing table and records are saved to disk, so a restarted node
+//! can rejoin the network even if none of the bootstrap nodes are available
+
+use fluence_libp2p::peerid_serializer;
+
+use libp2p::{core::Multiaddr, identity::ed25519::PublicKey, kad::Record, PeerId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const PEERS_FILE: &str = "peers.json";
+const RECORDS_FILE: &str = "records.json";
+
+/// Peer from the routing table
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StoredPeer {
+    #[serde(with = "peerid_serializer")]
+    pub peer_id: PeerId,
+    /// base58-encoded ed25519 public key
+    pub public_key: String,
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl StoredPeer {
+    pub fn new(peer_id: PeerId, public_key: &PublicKey, addresses: Vec<Multiaddr>) -> Self {
+        Self {
+            peer_id,
+            public_key: bs58::encode(public_key.encode()).into_string(),
+            addresses,
+        }
+    }
+
+    pub fn public_key(&self) -> Option<PublicKey> {
+        let bytes = bs58::decode(&self.public_key).into_vec().ok()?;
+        PublicKey::decode(&bytes).ok()
+    }
+}
+
+/// Record from the record store, binary fields are base64-encoded
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StoredRecord {
+    pub key: String,
+    pub value: String,
+    pub publisher: Option<String>,
+    /// UNIX timestamp in seconds
+    pub expires_at: Option<u64>,
+}
+
+impl StoredRecord {
+    pub fn new(record: &Record, now_sec: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            key: base64::encode(record.key.as_ref()),
+            value: base64::encode(&record.value),
+            publisher: record.publisher.as_ref().map(|p| p.to_string()),
+            expires_at: record
+                .expires
+                .map(|e| now_sec + e.saturating_duration_since(now).as_secs()),
+        }
+    }
+
+    /// Returns None if record is expired or malformed
+    pub fn into_record(self, now_sec: u64) -> Option<Record> {
+        let expires = match self.expires_at {
+            Some(expires_at) if expires_at <= now_sec => return None,
+            Some(expires_at) => Some(Instant::now() + Duration::from_secs(expires_at - now_sec)),
+            None => None,
+        };
+        let publisher = match self.publisher {
+            Some(p) => Some(p.parse().ok()?),
+            None => None,
+        };
+        let key = base64::decode(&self.key).ok()?;
+
+        Some(Record {
+            key: key.into(),
+            value: base64::decode(&self.value).ok()?,
+            publisher,
+            expires,
+        })
+    }
+}
+
+pub fn load_peers(dir: &Path) -> Vec<StoredPeer> {
+    load(&dir.join(PEERS_FILE))
+}
+
+pub fn load_records(dir: &Path) -> Vec<StoredRecord> {
+    load(&dir.join(RECORDS_FILE))
+}
+
+pub fn save_peers(dir: &Path, peers: &[StoredPeer]) -> std::io::Result<()> {
+    save(dir, PEERS_FILE, peers)
+}
+
+pub fn save_records(dir: &Path, records: &[StoredRecord]) -> std::io::Result<()> {
+    save(dir, RECORDS_FILE, records)
+}
+
+/// Missing or corrupted file is treated as empty, node will just bootstrap from scratch
+fn load<T: DeserializeOwned>(path: &Path) -> Vec<T> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return vec![],
+        Err(err) => {
+            log::warn!("unable to read {:?}: {}", path, err);
+            return vec![];
+        }
+    };
+
+    serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+        log::warn!("unable to parse {:?}: {}", path, err);
+        vec![]
+    })
+}
+
+/// Writes to a temporary file first, so a crash never leaves a truncated file behind
+fn save<T: Serialize>(dir: &Path, name: &str, items: &[T]) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!("{}.tmp", name));
+    fs::write(&tmp, serde_json::to_vec(items)?)?;
+    fs::rename(tmp, dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_peers, load_records, save_peers, save_records, StoredPeer, StoredRecord};
+    use libp2p::identity::{ed25519::Keypair, PublicKey};
+    use libp2p::kad::Record;
+    use libp2p::PeerId;
+    use std::time::{Duration, Instant};
+    use tempdir::TempDir;
+
+    #[test]
+    fn roundtrip() {
+        let tmp = TempDir::new("kad-persistence").unwrap();
+        let dir = tmp.path().join("kademlia");
+        assert!(load_peers(&dir).is_empty());
+
+        let kp = Keypair::generate();
+        let peer_id = PeerId::from(PublicKey::Ed25519(kp.public()));
+        let addr = "/ip4/127.0.0.1/tcp/7777".parse().unwrap();
+        let peer = StoredPeer::new(peer_id, &kp.public(), vec![addr]);
+        save_peers(&dir, &[peer.clone()]).expect("save peers");
+        let peers = load_peers(&dir);
+        assert_eq!(peers, vec![peer]);
+        assert_eq!(peers[0].public_key(), Some(kp.public()));
+
+        let mut record = Record::new(b"key".to_vec(), b"value".to_vec());
+        record.publisher = Some(peer_id);
+        record.expires = Some(Instant::now() + Duration::from_secs(100));
+        let expired = StoredRecord {
+            expires_at: Some(1),
+            ..StoredRecord::new(&record, 1)
+        };
+        save_records(&dir, &[StoredRecord::new(&record, 1000), expired]).expect("save records");
+
+        let records: Vec<_> = load_records(&dir)
+            .into_iter()
+            .filter_map(|r| r.into_record(1000))
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, record.key);
+        assert_eq!(records[0].value, record.value);
+        assert_eq!(records[0].publisher, record.publisher);
+        assert!(records[0].expires.is_some());
+    }
+}
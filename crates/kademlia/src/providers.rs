@@ -0,0 +1,180 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use fluence_libp2p::peerid_serializer;
+
+use libp2p::{identity::ed25519::Keypair, identity::PublicKey, kad::record, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// All provider records are stored in the DHT under keys with that prefix
+pub const PROVIDERS_PREFIX: &[u8] = b"/providers/";
+
+/// Announcement that `peer` (or a service on it) provides `key` until `expires_at`.
+/// Signed by the `peer`, so it can be verified by any node storing or resolving it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProviderRecord {
+    pub key: String,
+    #[serde(with = "peerid_serializer")]
+    pub peer: PeerId,
+    /// If defined, an app service is considered to be a provider, otherwise the whole `peer`
+    pub service_id: Option<String>,
+    /// UNIX timestamp in seconds
+    pub expires_at: u64,
+    pub signature: Vec<u8>,
+}
+
+impl ProviderRecord {
+    /// Creates a record announcing that `keypair`'s peer provides `key` for the next `ttl`
+    pub fn signed(
+        key: String,
+        service_id: Option<String>,
+        ttl: Duration,
+        now_sec: u64,
+        keypair: &Keypair,
+    ) -> Self {
+        let peer = PeerId::from(PublicKey::Ed25519(keypair.public()));
+        let mut record = Self {
+            key,
+            peer,
+            service_id,
+            expires_at: now_sec.saturating_add(ttl.as_secs()),
+            signature: vec![],
+        };
+        record.signature = keypair.sign(&record.signed_bytes());
+        record
+    }
+
+    /// Checks that record was signed by the `peer`
+    pub fn verify(&self) -> bool {
+        match self.peer.as_public_key() {
+            Some(pk) => pk.verify(&self.signed_bytes(), &self.signature),
+            None => false,
+        }
+    }
+
+    pub fn is_expired(&self, now_sec: u64) -> bool {
+        self.expires_at <= now_sec
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        fn push(buf: &mut Vec<u8>, bytes: &[u8]) {
+            buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            buf.extend_from_slice(bytes);
+        }
+
+        let mut buf = PROVIDERS_PREFIX.to_vec();
+        push(&mut buf, self.key.as_bytes());
+        push(&mut buf, &self.peer.to_bytes());
+        push(
+            &mut buf,
+            self.service_id.as_deref().unwrap_or_default().as_bytes(),
+        );
+        buf.push(self.service_id.is_some() as u8);
+        buf.extend_from_slice(&self.expires_at.to_le_bytes());
+        buf
+    }
+}
+
+/// DHT key under which providers of `key` are stored
+pub fn providers_key(key: &str) -> record::Key {
+    let mut bytes = PROVIDERS_PREFIX.to_vec();
+    bytes.extend_from_slice(key.as_bytes());
+    record::Key::new(&bytes)
+}
+
+pub(crate) fn is_providers_key(key: &record::Key) -> bool {
+    key.as_ref().starts_with(PROVIDERS_PREFIX)
+}
+
+pub(crate) fn encode(records: &[ProviderRecord]) -> Vec<u8> {
+    serde_json::to_vec(records).expect("provider records are always serializable")
+}
+
+/// Decodes provider records stored under `key`, skipping expired, forged or misplaced ones
+pub(crate) fn decode(key: &record::Key, bytes: &[u8], now_sec: u64) -> Vec<ProviderRecord> {
+    let records: Vec<ProviderRecord> = match serde_json::from_slice(bytes) {
+        Ok(records) => records,
+        Err(err) => {
+            log::debug!("invalid provider records under {:?}: {}", key, err);
+            return vec![];
+        }
+    };
+
+    records
+        .into_iter()
+        .filter(|r| providers_key(&r.key) == *key && !r.is_expired(now_sec) && r.verify())
+        .collect()
+}
+
+/// Unites records, keeping the latest announcement for each (peer, service_id) pair
+pub(crate) fn merge(records: impl IntoIterator<Item = ProviderRecord>) -> Vec<ProviderRecord> {
+    let mut merged: HashMap<(PeerId, Option<String>), ProviderRecord> = HashMap::new();
+    for record in records {
+        let id = (record.peer, record.service_id.clone());
+        match merged.get(&id) {
+            Some(existing) if existing.expires_at >= record.expires_at => {}
+            _ => {
+                merged.insert(id, record);
+            }
+        }
+    }
+
+    merged.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Local expiration instant of a DHT record holding `records`
+pub(crate) fn expires(records: &[ProviderRecord], now_sec: u64) -> Option<Instant> {
+    let expires_at = records.iter().map(|r| r.expires_at).max()?;
+    Some(Instant::now() + Duration::from_secs(expires_at.saturating_sub(now_sec)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, merge, providers_key, ProviderRecord};
+    use libp2p::identity::ed25519::Keypair;
+    use std::time::Duration;
+
+    #[test]
+    fn sign_and_merge() {
+        let kp = Keypair::generate();
+        let ttl = Duration::from_secs(10);
+        let record = ProviderRecord::signed("key".into(), Some("srv".into()), ttl, 100, &kp);
+        assert!(record.verify());
+
+        let mut forged = record.clone();
+        forged.expires_at += 1000;
+        assert!(!forged.verify());
+
+        let newer = ProviderRecord::signed("key".into(), Some("srv".into()), ttl, 105, &kp);
+        let node = ProviderRecord::signed("key".into(), None, ttl, 100, &kp);
+        let other = ProviderRecord::signed("other".into(), None, ttl, 100, &kp);
+        let records = vec![record, forged, newer.clone(), node.clone(), other];
+
+        let key = providers_key("key");
+        let decoded = decode(&key, &encode(&records), 100);
+        // forged and misplaced records are dropped
+        assert_eq!(decoded.len(), 3);
+
+        let mut merged = merge(decoded);
+        merged.sort_by_key(|r| r.service_id.is_some());
+        assert_eq!(merged, vec![node, newer]);
+
+        // everything is expired
+        assert!(decode(&key, &encode(&records), 115).is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::providers;
+
+use libp2p::{
+    kad::{
+        record::Key,
+        store::{MemoryStore, MemoryStoreConfig, RecordStore, Result},
+        ProviderRecord, Record,
+    },
+    PeerId,
+};
+use now_millis::now_sec;
+use std::borrow::Cow;
+
+/// Values stored via `put_value` live under keys with that prefix,
+/// so they never clash with provider records
+pub const VALUES_PREFIX: &[u8] = b"/values/";
+
+/// DHT key under which `publisher` stores value for `key`. Keys are namespaced by publisher,
+/// so `kad` builtins called by one peer never touch values of another. Records aren't signed
+/// though, so a DHT node can still store anything under any key
+pub fn value_key(publisher: &PeerId, key: &str) -> Key {
+    let mut bytes = VALUES_PREFIX.to_vec();
+    bytes.extend_from_slice(publisher.to_base58().as_bytes());
+    bytes.push(b'/');
+    bytes.extend_from_slice(key.as_bytes());
+    Key::new(&bytes)
+}
+
+/// `MemoryStore` that merges provider records instead of overwriting them.
+///
+/// Kademlia keeps a single value per key, while there are usually many providers for a key.
+/// So every put under a providers key is united with what's already stored, and records
+/// that are expired or aren't signed by their provider are dropped.
+pub struct Store {
+    inner: MemoryStore,
+}
+
+impl Store {
+    pub fn new(local_id: PeerId, config: MemoryStoreConfig) -> Self {
+        Self {
+            inner: MemoryStore::with_config(local_id, config),
+        }
+    }
+}
+
+impl<'a> RecordStore<'a> for Store {
+    type RecordsIter = <MemoryStore as RecordStore<'a>>::RecordsIter;
+    type ProvidedIter = <MemoryStore as RecordStore<'a>>::ProvidedIter;
+
+    fn get(&'a self, k: &Key) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&'a mut self, mut r: Record) -> Result<()> {
+        if !providers::is_providers_key(&r.key) {
+            return self.inner.put(r);
+        }
+
+        let now = now_sec();
+        let incoming = providers::decode(&r.key, &r.value, now);
+        if incoming.is_empty() {
+            log::debug!("no valid provider records in {:?}, ignoring", r.key);
+            return Ok(());
+        }
+
+        let stored = self.inner.get(&r.key);
+        let stored = stored.map_or(vec![], |s| providers::decode(&s.key, &s.value, now));
+        let merged = providers::merge(stored.into_iter().chain(incoming));
+
+        r.value = providers::encode(&merged);
+        r.expires = providers::expires(&merged, now);
+        self.inner.put(r)
+    }
+
+    fn remove(&'a mut self, k: &Key) {
+        self.inner.remove(k)
+    }
+
+    fn records(&'a self) -> Self::RecordsIter {
+        self.inner.records()
+    }
+
+    fn add_provider(&'a mut self, record: ProviderRecord) -> Result<()> {
+        self.inner.add_provider(record)
+    }
+
+    fn providers(&'a self, key: &Key) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&'a self) -> Self::ProvidedIter {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&'a mut self, k: &Key, p: &PeerId) {
+        self.inner.remove_provider(k, p)
+    }
+}
@@ -0,0 +1,275 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::codec::{ModuleExchangeCodec, ModuleExchangeProtocol, ModuleRequest, ModuleResponse};
+use crate::error::{ModuleExchangeError, Result};
+
+use fluence_libp2p::generate_swarm_event_type;
+use fluence_libp2p::types::{Inlet, OneshotOutlet, Outlet};
+use particle_modules::load_module_with_name;
+
+use async_std::task;
+use futures::{
+    channel::{mpsc::unbounded, oneshot},
+    future::BoxFuture,
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
+use libp2p::{
+    core::Multiaddr,
+    request_response::{
+        ProtocolSupport, RequestId, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+        RequestResponseMessage, ResponseChannel,
+    },
+    swarm::NetworkBehaviourEventProcess,
+    PeerId,
+};
+use std::collections::HashMap;
+use std::convert::identity;
+use std::iter;
+use std::path::PathBuf;
+use std::task::Waker;
+use std::time::Duration;
+
+/// Module bytes and name, as received from a remote peer. Hash isn't checked yet.
+#[derive(Debug, Clone)]
+pub struct FetchedModule {
+    pub module: Vec<u8>,
+    pub name: String,
+}
+
+/// Module loaded from disk, ready to be sent to the peer that requested it
+struct Served {
+    peer: PeerId,
+    hash: String,
+    channel: ResponseChannel<ModuleResponse>,
+    response: ModuleResponse,
+}
+
+#[derive(Debug)]
+enum Command {
+    Fetch {
+        peer: PeerId,
+        addresses: Vec<Multiaddr>,
+        hash: String,
+        out: OneshotOutlet<Result<FetchedModule>>,
+    },
+}
+
+pub type SwarmEventType = generate_swarm_event_type!(ModuleExchange);
+
+/// Serves modules from `modules_dir` to other peers, and fetches modules from them
+#[derive(::libp2p::NetworkBehaviour)]
+#[behaviour(poll_method = "custom_poll")]
+pub struct ModuleExchange {
+    request_response: RequestResponse<ModuleExchangeCodec>,
+    #[behaviour(ignore)]
+    inlet: Inlet<Command>,
+    #[behaviour(ignore)]
+    modules_dir: PathBuf,
+    #[behaviour(ignore)]
+    /// Outbound requests waiting for a response, along with the requested hash
+    pending: HashMap<RequestId, (String, OneshotOutlet<Result<FetchedModule>>)>,
+    #[behaviour(ignore)]
+    /// Inbound requests whose modules are being loaded from disk
+    serving: FuturesUnordered<BoxFuture<'static, Served>>,
+    #[behaviour(ignore)]
+    waker: Option<Waker>,
+}
+
+impl ModuleExchange {
+    pub fn new(modules_dir: PathBuf, request_timeout: Duration) -> (ModuleExchangeApi, Self) {
+        let mut config = RequestResponseConfig::default();
+        config.set_request_timeout(request_timeout);
+        let protocols = iter::once((ModuleExchangeProtocol, ProtocolSupport::Full));
+        let request_response = RequestResponse::new(ModuleExchangeCodec, protocols, config);
+
+        let (outlet, inlet) = unbounded();
+        let api = ModuleExchangeApi { outlet };
+        let this = Self {
+            request_response,
+            inlet,
+            modules_dir,
+            pending: <_>::default(),
+            serving: <_>::default(),
+            waker: None,
+        };
+
+        (api, this)
+    }
+
+    fn execute(&mut self, cmd: Command) {
+        match cmd {
+            Command::Fetch {
+                peer,
+                addresses,
+                hash,
+                out,
+            } => {
+                for addr in addresses {
+                    self.request_response.add_address(&peer, addr);
+                }
+                let request = ModuleRequest { hash: hash.clone() };
+                let id = self.request_response.send_request(&peer, request);
+                self.pending.insert(id, (hash, out));
+            }
+        }
+    }
+
+    /// Loads module on a blocking task, response is sent from `custom_poll` once it's loaded
+    fn serve(&mut self, peer: PeerId, hash: String, channel: ResponseChannel<ModuleResponse>) {
+        let modules_dir = self.modules_dir.clone();
+        let load = async move {
+            let response = task::spawn_blocking({
+                let hash = hash.clone();
+                move || match load_module_with_name(&modules_dir, &hash) {
+                    Ok((module, name)) => ModuleResponse::Found { module, name },
+                    Err(err) => {
+                        log::debug!("Unable to serve module {}: {}", hash, err);
+                        ModuleResponse::NotFound
+                    }
+                }
+            })
+            .await;
+
+            Served {
+                peer,
+                hash,
+                channel,
+                response,
+            }
+        };
+        self.serving.push(load.boxed());
+        self.wake();
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = &self.waker {
+            waker.wake_by_ref()
+        }
+    }
+
+    fn custom_poll(
+        &mut self,
+        cx: &mut std::task::Context,
+        _: &mut impl libp2p::swarm::PollParameters,
+    ) -> std::task::Poll<SwarmEventType> {
+        use std::task::Poll;
+
+        self.waker = Some(cx.waker().clone());
+
+        while let Poll::Ready(Some(cmd)) = self.inlet.poll_next_unpin(cx) {
+            self.execute(cmd)
+        }
+
+        while let Poll::Ready(Some(served)) = self.serving.poll_next_unpin(cx) {
+            let Served {
+                peer,
+                hash,
+                channel,
+                response,
+            } = served;
+            if self
+                .request_response
+                .send_response(channel, response)
+                .is_err()
+            {
+                log::warn!("Module {} wasn't sent to {}", hash, peer);
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<ModuleRequest, ModuleResponse>>
+    for ModuleExchange
+{
+    fn inject_event(&mut self, event: RequestResponseEvent<ModuleRequest, ModuleResponse>) {
+        match event {
+            RequestResponseEvent::Message { peer, message } => match message {
+                RequestResponseMessage::Request {
+                    request, channel, ..
+                } => {
+                    log::debug!("Peer {} requested module {}", peer, request.hash);
+                    self.serve(peer, request.hash, channel);
+                }
+                RequestResponseMessage::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some((hash, out)) = self.pending.remove(&request_id) {
+                        let result = match response {
+                            ModuleResponse::Found { module, name } => {
+                                Ok(FetchedModule { module, name })
+                            }
+                            ModuleResponse::NotFound => {
+                                Err(ModuleExchangeError::NotFound { peer, hash })
+                            }
+                        };
+                        out.send(result).ok();
+                    }
+                }
+            },
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                if let Some((hash, out)) = self.pending.remove(&request_id) {
+                    let error = ModuleExchangeError::Outbound { peer, hash, error };
+                    out.send(Err(error)).ok();
+                }
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                log::debug!("Failed to serve module to {}: {:?}", peer, error);
+            }
+            RequestResponseEvent::ResponseSent { .. } => {}
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ModuleExchangeApi {
+    outlet: Outlet<Command>,
+}
+
+impl ModuleExchangeApi {
+    /// Requests module by hash from `peer`. Given `addresses` are used to dial the peer if needed.
+    pub fn fetch(
+        &self,
+        peer: PeerId,
+        addresses: Vec<Multiaddr>,
+        hash: String,
+    ) -> BoxFuture<'static, Result<FetchedModule>> {
+        let (out, inlet) = oneshot::channel();
+        let cmd = Command::Fetch {
+            peer,
+            addresses,
+            hash,
+            out,
+        };
+        if self.outlet.unbounded_send(cmd).is_err() {
+            return futures::future::err(ModuleExchangeError::Cancelled).boxed();
+        }
+        inlet
+            .map(|r| {
+                r.map_err(|_| ModuleExchangeError::Cancelled)
+                    .and_then(identity)
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::core::upgrade::{read_one, write_with_len_prefix};
+use libp2p::request_response::{ProtocolName, RequestResponseCodec};
+use std::io;
+
+pub const PROTOCOL_NAME: &[u8] = b"/fluence/modules/1.0.0";
+
+/// Hex-encoded blake3 hash is 64 bytes
+const MAX_HASH_SIZE: usize = 128;
+const MAX_NAME_SIZE: usize = 1024;
+const MAX_MODULE_SIZE: usize = 100 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ModuleExchangeProtocol;
+
+impl ProtocolName for ModuleExchangeProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL_NAME
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleRequest {
+    /// Hex-encoded blake3 hash of the module
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleResponse {
+    /// Module bytes and its name. The rest of the config (envs, mapped dirs, binaries) stays local
+    Found {
+        module: Vec<u8>,
+        name: String,
+    },
+    NotFound,
+}
+
+/// Request is a length-prefixed hash.
+/// Response is a single byte flag, followed by length-prefixed name and module if flag is 1.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleExchangeCodec;
+
+#[async_trait]
+impl RequestResponseCodec for ModuleExchangeCodec {
+    type Protocol = ModuleExchangeProtocol;
+    type Request = ModuleRequest;
+    type Response = ModuleResponse;
+
+    async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<ModuleRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let hash = read_one(io, MAX_HASH_SIZE).await.map_err(invalid_data)?;
+        let hash = String::from_utf8(hash).map_err(invalid_data)?;
+
+        Ok(ModuleRequest { hash })
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<ModuleResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut found = [0u8];
+        io.read_exact(&mut found).await?;
+        if found[0] == 0 {
+            return Ok(ModuleResponse::NotFound);
+        }
+
+        let name = read_one(io, MAX_NAME_SIZE).await.map_err(invalid_data)?;
+        let name = String::from_utf8(name).map_err(invalid_data)?;
+        let module = read_one(io, MAX_MODULE_SIZE).await.map_err(invalid_data)?;
+
+        Ok(ModuleResponse::Found { module, name })
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: ModuleRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_with_len_prefix(io, request.hash.as_bytes()).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: ModuleResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        match response {
+            ModuleResponse::Found { module, name } => {
+                io.write_all(&[1]).await?;
+                write_with_len_prefix(io, name.as_bytes()).await?;
+                write_with_len_prefix(io, module).await?;
+            }
+            ModuleResponse::NotFound => io.write_all(&[0]).await?,
+        }
+        io.close().await
+    }
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModuleExchangeCodec, ModuleExchangeProtocol, ModuleRequest, ModuleResponse};
+    use async_std::task;
+    use futures::io::Cursor;
+    use libp2p::request_response::RequestResponseCodec;
+
+    #[test]
+    fn roundtrip() {
+        task::block_on(async {
+            let mut codec = ModuleExchangeCodec;
+            let protocol = ModuleExchangeProtocol;
+
+            let request = ModuleRequest {
+                hash: "abcdef".to_string(),
+            };
+            let mut io = Cursor::new(vec![]);
+            codec
+                .write_request(&protocol, &mut io, request.clone())
+                .await
+                .unwrap();
+            io.set_position(0);
+            let read = codec.read_request(&protocol, &mut io).await.unwrap();
+            assert_eq!(read, request);
+
+            let responses = vec![
+                ModuleResponse::Found {
+                    module: vec![1, 2, 3],
+                    name: "module".to_string(),
+                },
+                ModuleResponse::NotFound,
+            ];
+            for response in responses {
+                let mut io = Cursor::new(vec![]);
+                codec
+                    .write_response(&protocol, &mut io, response.clone())
+                    .await
+                    .unwrap();
+                io.set_position(0);
+                let read = codec.read_response(&protocol, &mut io).await.unwrap();
+                assert_eq!(read, response);
+            }
+        })
+    }
+}
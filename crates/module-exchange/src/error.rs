@@ -0,0 +1,35 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use libp2p::request_response::OutboundFailure;
+use libp2p::PeerId;
+use thiserror::Error;
+
+pub(crate) type Result<T> = std::result::Result<T, ModuleExchangeError>;
+
+#[derive(Debug, Error)]
+pub enum ModuleExchangeError {
+    #[error("Module {hash} wasn't found on {peer}")]
+    NotFound { peer: PeerId, hash: String },
+    #[error("Error fetching module {hash} from {peer}: {error:?}")]
+    Outbound {
+        peer: PeerId,
+        hash: String,
+        error: OutboundFailure,
+    },
+    #[error("ModuleExchangeError::Cancelled")]
+    Cancelled,
+}
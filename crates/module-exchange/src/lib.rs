@@ -0,0 +1,25 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Peers exchange wasm modules by their blake3 hash, so module bytes
+//! don't have to be sent inside particles to every node
+
+mod behaviour;
+mod codec;
+mod error;
+
+pub use behaviour::{FetchedModule, ModuleExchange, ModuleExchangeApi};
+pub use error::ModuleExchangeError;
@@ -26,6 +26,7 @@ pub const DEFAULT_KEY_DIR: &str = ".fluence/secret_key";
 pub const DEFAULT_CONFIG_FILE: &str = ".fluence/Config.toml";
 pub const DEFAULT_SERVICES_BASE_DIR: &str = ".fluence/services";
 pub const DEFAULT_STEPPER_BASE_DIR: &str = ".fluence/stepper";
+pub const DEFAULT_KADEMLIA_DIR: &str = ".fluence/kademlia";
 
 pub fn default_tcp_port() -> u16 {
     7777
@@ -57,6 +58,10 @@ pub fn default_stepper_basedir() -> PathBuf {
     DEFAULT_STEPPER_BASE_DIR.into()
 }
 
+pub fn default_kademlia_dir() -> PathBuf {
+    DEFAULT_KADEMLIA_DIR.into()
+}
+
 pub fn default_air_interpreter_path() -> PathBuf {
     use air_interpreter_wasm as interpreter;
 
@@ -67,6 +72,35 @@ pub fn default_stepper_pool_size() -> usize {
     num_cpus::get() * 2
 }
 
+pub fn default_stepper_vm_idle_timeout() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+pub fn default_stepper_max_lost_vms() -> usize {
+    num_cpus::get() * 2
+}
+
+pub fn default_actor_mailbox_size() -> usize {
+    128
+}
+
+pub fn default_max_queued_particles() -> usize {
+    10_000
+}
+
+pub fn default_seen_particles_limit() -> usize {
+    100_000
+}
+
+pub fn default_particle_data_max_age() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+pub fn default_particle_data_max_size() -> u64 {
+    // 1 GiB
+    1 << 30
+}
+
 pub fn default_particle_queue_buffer_size() -> usize {
     100
 }
@@ -87,6 +121,30 @@ pub fn default_script_storage_particle_ttl() -> Duration {
     Duration::from_secs(120)
 }
 
+pub fn default_peer_fail_threshold() -> usize {
+    3
+}
+
+pub fn default_ban_cooldown() -> Duration {
+    Duration::from_secs(60)
+}
+
+pub fn default_provider_ttl() -> Duration {
+    Duration::from_secs(3600)
+}
+
+pub fn default_kad_max_records() -> usize {
+    1024
+}
+
+pub fn default_kad_max_value_bytes() -> usize {
+    65 * 1024
+}
+
+pub fn default_kad_persist_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
 pub fn default_bootstrap_frequency() -> usize {
     3
 }
@@ -99,6 +157,10 @@ pub fn default_processing_timeout() -> Duration {
     Duration::from_secs(120)
 }
 
+pub fn default_module_fetch_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
 pub fn default_management_peer_id() -> PeerId {
     let kp = Keypair::generate();
     let secret = kp.secret();
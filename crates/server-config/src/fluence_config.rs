@@ -16,7 +16,9 @@
 
 use super::defaults::*;
 use super::keys::{decode_key_pair, load_or_create_key_pair};
-use crate::{BootstrapConfig, KademliaConfig, ListenConfig};
+use crate::{
+    BootstrapConfig, KademliaConfig, ListenConfig, PermissionsConfig, ScopedEnvs, ServiceLimits,
+};
 
 use trust_graph::{KeyPair, PublicKeyHashable};
 
@@ -117,6 +119,10 @@ pub struct NodeConfig {
     #[serde(deserialize_with = "parse_envs")]
     pub services_envs: HashMap<Vec<u8>, Vec<u8>>,
 
+    /// Envs passed only to specific modules or blueprints, unlike `services_envs`
+    #[serde(default)]
+    pub services_scoped_envs: ScopedEnvs,
+
     /// Base directory for resources needed by application services
     #[serde(default = "default_stepper_basedir")]
     pub stepper_base_dir: PathBuf,
@@ -124,10 +130,20 @@ pub struct NodeConfig {
     #[serde(default)]
     pub protocol_config: ProtocolConfig,
 
-    /// Number of stepper VMs to create. By default, `num_cpus::get() * 2` is used
+    /// Max number of stepper VMs. By default, `num_cpus::get() * 2` is used
     #[serde(default = "default_stepper_pool_size")]
     pub stepper_pool_size: usize,
 
+    /// Number of stepper VMs that are always kept. Pool grows from that up to `stepper_pool_size`
+    /// when particles wait for a VM. If not set, pool always has `stepper_pool_size` VMs.
+    #[serde(default)]
+    pub stepper_pool_min_size: Option<usize>,
+
+    /// Stepper VMs above `stepper_pool_min_size` are removed after being unused for that long
+    #[serde(default = "default_stepper_vm_idle_timeout")]
+    #[serde(with = "humantime_serde")]
+    pub stepper_vm_idle_timeout: Duration,
+
     /// Path to AIR interpreter .wasm file (aquamarine.wasm)
     #[serde(default = "default_air_interpreter_path")]
     pub air_interpreter_path: PathBuf,
@@ -135,6 +151,45 @@ pub struct NodeConfig {
     #[serde(default)]
     pub kademlia: KademliaConfig,
 
+    /// Directory where Kademlia routing table and records are kept between restarts
+    #[serde(default = "default_kademlia_dir")]
+    pub kademlia_dir: PathBuf,
+
+    /// Max number of particles with the same id waiting for an interpreter, excess is rejected
+    #[serde(default = "default_actor_mailbox_size")]
+    pub actor_mailbox_size: usize,
+
+    /// Max number of particles waiting for an interpreter in total, excess is rejected
+    #[serde(default = "default_max_queued_particles")]
+    pub max_queued_particles: usize,
+
+    /// Max number of received particles remembered until their deadline,
+    /// so their re-sent or replayed copies aren't executed again
+    #[serde(default = "default_seen_particles_limit")]
+    pub seen_particles_limit: usize,
+
+    /// Interpreter data of particles that weren't seen for that long is removed,
+    /// even if their deadline isn't known, e.g. after restart
+    #[serde(default = "default_particle_data_max_age")]
+    #[serde(with = "humantime_serde")]
+    pub particle_data_max_age: Duration,
+
+    /// Max total size of interpreter data of all particles, in bytes.
+    /// When exceeded, data of the least recently executed particles is removed.
+    #[serde(default = "default_particle_data_max_size")]
+    pub particle_data_max_size: u64,
+
+    /// If set, each particle execution is recorded there, along with host calls it made.
+    /// Recordings can be replayed offline, see `aquamarine::replay`.
+    #[serde(default)]
+    pub particle_recordings_dir: Option<PathBuf>,
+
+    /// If set, `air_interpreter_path` is checked for modifications that often. Once modified,
+    /// interpreters are recreated from it, without dropping particles being executed.
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub air_interpreter_watch_interval: Option<Duration>,
+
     #[serde(default = "default_particle_queue_buffer_size")]
     pub particle_queue_buffer: usize,
     #[serde(default = "default_particle_processor_parallelism")]
@@ -160,13 +215,31 @@ pub struct NodeConfig {
     #[serde(with = "humantime_serde")]
     pub particle_execution_timeout: Duration,
 
+    /// Max number of timed out particle executions whose stepper VMs are replaced.
+    /// Timed out executions can't be interrupted, so each of them keeps a VM until it finishes
+    #[serde(default = "default_stepper_max_lost_vms")]
+    pub stepper_max_lost_vms: usize,
+
     #[serde(default = "default_processing_timeout")]
     #[serde(with = "humantime_serde")]
     pub particle_processing_timeout: Duration,
 
+    /// Timeout for fetching a module from another peer
+    #[serde(default = "default_module_fetch_timeout")]
+    #[serde(with = "humantime_serde")]
+    pub module_fetch_timeout: Duration,
+
     #[serde(deserialize_with = "parse_management_peer_id")]
     #[serde(default = "default_management_peer_id")]
     pub management_peer_id: PeerId,
+
+    /// Restricts which peers are allowed to call host builtins
+    #[serde(default)]
+    pub permissions: PermissionsConfig,
+
+    /// Resources every service is allowed to consume
+    #[serde(default)]
+    pub service_limits: ServiceLimits,
 }
 
 impl NodeConfig {
@@ -14,9 +14,14 @@
  * limitations under the License.
  */
 
+use crate::defaults::{
+    default_ban_cooldown, default_kad_max_records, default_kad_max_value_bytes,
+    default_kad_persist_interval, default_peer_fail_threshold, default_provider_ttl,
+};
+
 use std::time::Duration;
 
-use libp2p::kad::KademliaConfig as LibP2PKadConfig;
+use libp2p::kad::{store::MemoryStoreConfig, KademliaConfig as LibP2PKadConfig};
 use serde::Deserialize;
 
 /// see `libp2p_kad::KademliaConfig`
@@ -29,10 +34,23 @@ pub struct KademliaConfig {
     #[serde(with = "humantime_serde")]
     pub connection_idle_timeout: Option<Duration>,
     /// Number of times peer is failed to be discovered before it is banned
+    #[serde(default = "default_peer_fail_threshold")]
     pub peer_fail_threshold: usize,
     /// Period after which peer ban is lifted
-    #[serde(with = "humantime_serde")]
+    #[serde(with = "humantime_serde", default = "default_ban_cooldown")]
     pub ban_cooldown: Duration,
+    /// How long provider records stay in the DHT unless re-announced
+    #[serde(with = "humantime_serde", default = "default_provider_ttl")]
+    pub provider_ttl: Duration,
+    /// Max number of records stored on the node, providers included
+    #[serde(default = "default_kad_max_records")]
+    pub max_records: usize,
+    /// Max size of a single record value
+    #[serde(default = "default_kad_max_value_bytes")]
+    pub max_value_bytes: usize,
+    /// How often routing table and records are saved to disk
+    #[serde(with = "humantime_serde", default = "default_kad_persist_interval")]
+    pub persist_interval: Duration,
 }
 
 impl Default for KademliaConfig {
@@ -42,8 +60,12 @@ impl Default for KademliaConfig {
             query_timeout: Duration::from_secs(3),
             replication_factor: None,
             connection_idle_timeout: Some(Duration::from_secs(2_628_000_000)), // ~month
-            peer_fail_threshold: 3,
-            ban_cooldown: Duration::from_secs(60),
+            peer_fail_threshold: default_peer_fail_threshold(),
+            ban_cooldown: default_ban_cooldown(),
+            provider_ttl: default_provider_ttl(),
+            max_records: default_kad_max_records(),
+            max_value_bytes: default_kad_max_value_bytes(),
+            persist_interval: default_kad_persist_interval(),
         }
     }
 }
@@ -75,4 +97,12 @@ impl KademliaConfig {
 
         cfg
     }
+
+    pub fn as_store_config(&self) -> MemoryStoreConfig {
+        MemoryStoreConfig {
+            max_records: self.max_records,
+            max_value_bytes: self.max_value_bytes,
+            ..<_>::default()
+        }
+    }
 }
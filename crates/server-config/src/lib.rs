@@ -34,6 +34,7 @@ mod kademlia_config;
 mod keys;
 mod listen_config;
 mod network_config;
+mod permissions_config;
 mod services_config;
 
 pub use defaults::default_air_interpreter_path;
@@ -46,7 +47,8 @@ pub use fluence_config::NodeConfig;
 pub use kademlia_config::KademliaConfig;
 pub use listen_config::ListenConfig;
 pub use network_config::NetworkConfig;
-pub use services_config::ServicesConfig;
+pub use permissions_config::{PermissionRule, PermissionsConfig};
+pub use services_config::{ScopedEnvs, ServiceLimits, ServicesConfig};
 
 pub mod config_keys {
     pub use crate::fluence_config::{
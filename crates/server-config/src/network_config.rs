@@ -19,11 +19,12 @@ use crate::{BootstrapConfig, KademliaConfig};
 
 use particle_protocol::ProtocolConfig;
 
-use config_utils::to_peer_id;
+use config_utils::{modules_dir, to_abs_path, to_peer_id};
 use trust_graph::TrustGraph;
 
 use libp2p::{core::Multiaddr, identity::ed25519, PeerId};
 use prometheus::Registry;
+use std::path::PathBuf;
 use std::time::Duration;
 
 pub struct NetworkConfig {
@@ -35,11 +36,15 @@ pub struct NetworkConfig {
     pub registry: Option<Registry>,
     pub protocol_config: ProtocolConfig,
     pub kademlia_config: KademliaConfig,
+    pub kademlia_dir: Option<PathBuf>,
     pub particle_queue_buffer: usize,
     pub particle_parallelism: usize,
     pub bootstrap_frequency: usize,
     pub allow_local_addresses: bool,
     pub particle_timeout: Duration,
+    /// Modules from that dir are served to other peers by hash
+    pub modules_dir: PathBuf,
+    pub module_fetch_timeout: Duration,
 }
 
 impl NetworkConfig {
@@ -58,11 +63,14 @@ impl NetworkConfig {
             bootstrap: config.bootstrap_config.clone(),
             protocol_config: config.protocol_config.clone(),
             kademlia_config: config.kademlia.clone(),
+            kademlia_dir: Some(to_abs_path(config.kademlia_dir.clone())),
             particle_queue_buffer: config.particle_queue_buffer,
             particle_parallelism: config.particle_processor_parallelism,
             bootstrap_frequency: config.bootstrap_frequency,
             allow_local_addresses: config.allow_local_addresses,
             particle_timeout: config.particle_processing_timeout,
+            modules_dir: modules_dir(&to_abs_path(config.services_base_dir.clone())),
+            module_fetch_timeout: config.module_fetch_timeout,
         }
     }
 }
@@ -0,0 +1,182 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use trust_graph::{certificate_serde, current_time, Certificate, PublicKeyHashable};
+
+use libp2p::core::identity::PublicKey;
+use libp2p::PeerId;
+use serde::Deserialize;
+use std::str::FromStr;
+
+pub const ANY_FUNCTION: &str = "*";
+
+/// Restricts access to host builtins.
+/// Builtins that aren't matched by any rule are open to everyone.
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct PermissionsConfig {
+    #[serde(default)]
+    pub rules: Vec<PermissionRule>,
+    /// Certificates used to check `trusted_roots` of the rules
+    #[serde(default)]
+    #[serde(with = "certificate_serde::vec")]
+    pub certificates: Vec<Certificate>,
+}
+
+/// Allows listed peers to call matching builtins
+#[derive(Clone, Deserialize, Debug)]
+pub struct PermissionRule {
+    pub service_id: String,
+    /// Function name, or "*" to match any function of the service
+    #[serde(default = "any_function")]
+    pub function_name: String,
+    #[serde(default)]
+    #[serde(deserialize_with = "parse_peer_ids")]
+    pub peers: Vec<PeerId>,
+    /// Peers holding a valid certificate issued by one of these keys are allowed as well
+    #[serde(default)]
+    pub trusted_roots: Vec<PublicKeyHashable>,
+}
+
+impl PermissionRule {
+    pub fn matches(&self, service_id: &str, function_name: &str) -> bool {
+        self.service_id == service_id
+            && (self.function_name == ANY_FUNCTION || self.function_name == function_name)
+    }
+}
+
+impl PermissionsConfig {
+    /// Checks whether there are any rules for `function_name` of the `service_id` builtin
+    pub fn is_restricted(&self, service_id: &str, function_name: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|r| r.matches(service_id, function_name))
+    }
+
+    /// Checks whether `peer_id` is allowed to call `function_name` of the `service_id` builtin
+    pub fn is_allowed(&self, peer_id: &PeerId, service_id: &str, function_name: &str) -> bool {
+        if !self.is_restricted(service_id, function_name) {
+            return true;
+        }
+
+        self.rules
+            .iter()
+            .filter(|r| r.matches(service_id, function_name))
+            .any(|r| r.peers.contains(peer_id) || self.is_certified(peer_id, &r.trusted_roots))
+    }
+
+    /// Checks whether there's a valid certificate for `peer_id` issued by one of the `roots`
+    fn is_certified(&self, peer_id: &PeerId, roots: &[PublicKeyHashable]) -> bool {
+        if roots.is_empty() {
+            return false;
+        }
+
+        let public_key = match peer_id.as_public_key() {
+            Some(PublicKey::Ed25519(pk)) => pk,
+            _ => return false,
+        };
+        let roots: Vec<_> = roots.iter().map(|r| r.as_ref().clone()).collect();
+        let now = current_time();
+
+        self.certificates.iter().any(|cert| {
+            let issued_for = cert.chain.last().map(|t| &t.issued_for);
+            issued_for == Some(&public_key) && Certificate::verify(cert, &roots, now).is_ok()
+        })
+    }
+}
+
+fn any_function() -> String {
+    ANY_FUNCTION.to_string()
+}
+
+fn parse_peer_ids<'de, D>(deserializer: D) -> Result<Vec<PeerId>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let peer_ids = Vec::<String>::deserialize(deserializer)?;
+    peer_ids
+        .into_iter()
+        .map(|peer_id| {
+            PeerId::from_str(&peer_id).map_err(|err| {
+                serde::de::Error::custom(format!(
+                    "Failed to deserialize peer id {}: {}",
+                    peer_id, err
+                ))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PermissionsConfig;
+    use libp2p::core::identity::{ed25519::Keypair, PublicKey};
+    use libp2p::PeerId;
+    use std::time::Duration;
+    use trust_graph::{current_time, Certificate, KeyPair};
+
+    #[test]
+    fn rules() {
+        let admin = PeerId::random();
+        let config = format!(
+            r#"
+            [[rules]]
+            service_id = "dist"
+            function_name = "add_module"
+            peers = ["{}"]
+
+            [[rules]]
+            service_id = "srv"
+        "#,
+            admin
+        );
+        let config: PermissionsConfig = toml::from_str(&config).expect("deserialize config");
+        let stranger = PeerId::random();
+
+        assert!(config.is_allowed(&admin, "dist", "add_module"));
+        assert!(!config.is_allowed(&stranger, "dist", "add_module"));
+        assert!(config.is_allowed(&stranger, "dist", "add_blueprint"));
+        assert!(!config.is_allowed(&admin, "srv", "create"));
+        assert!(!config.is_allowed(&admin, "srv", "list"));
+    }
+
+    #[test]
+    fn certificates() {
+        let root = KeyPair::generate();
+        let trusted = Keypair::generate();
+        let trusted_peer = PeerId::from(PublicKey::Ed25519(trusted.public()));
+
+        let now = current_time();
+        let expires_at = now + Duration::from_secs(60);
+        let cert = Certificate::issue_root(&root, trusted.public(), expires_at, now);
+
+        let config = format!(
+            r#"
+            certificates = ["""{}"""]
+
+            [[rules]]
+            service_id = "script"
+            function_name = "add"
+            trusted_roots = ["{}"]
+        "#,
+            cert,
+            bs58::encode(root.public_key().encode()).into_string()
+        );
+        let config: PermissionsConfig = toml::from_str(&config).expect("deserialize config");
+
+        assert!(config.is_allowed(&trusted_peer, "script", "add"));
+        assert!(!config.is_allowed(&PeerId::random(), "script", "add"));
+    }
+}
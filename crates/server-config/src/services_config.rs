@@ -14,10 +14,15 @@
  * limitations under the License.
  */
 
+use crate::PermissionsConfig;
+
 use config_utils::{create_dirs, to_abs_path};
 use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct ServicesConfig {
@@ -25,17 +30,24 @@ pub struct ServicesConfig {
     pub local_peer_id: PeerId,
     /// Path of the blueprint directory containing blueprints and wasm modules
     pub blueprint_dir: PathBuf,
-    /// Opaque environment variables to be passed on each service creation
-    /// TODO: isolate envs of different modules (i.e., module A shouldn't access envs of module B)
+    /// Opaque environment variables to be passed to every module of every service
     pub envs: HashMap<Vec<u8>, Vec<u8>>,
+    /// Environment variables passed only to specific modules or blueprints, e.g. secrets
+    pub scoped_envs: ScopedEnvs,
     /// Working dir for services
     pub workdir: PathBuf,
     /// Dir to store .wasm modules and their configs
     pub modules_dir: PathBuf,
     /// Dir to persist info about running services
     pub services_dir: PathBuf,
+    /// Dir to persist scripts added to the script storage
+    pub scripts_dir: PathBuf,
     /// key that could manage services
     pub management_peer_id: PeerId,
+    /// Restricts which peers are allowed to call host builtins
+    pub permissions: PermissionsConfig,
+    /// Resources every service is allowed to consume, blueprints may narrow them further
+    pub limits: ServiceLimits,
 }
 
 impl ServicesConfig {
@@ -43,7 +55,10 @@ impl ServicesConfig {
         local_peer_id: PeerId,
         base_dir: PathBuf,
         envs: HashMap<Vec<u8>, Vec<u8>>,
+        scoped_envs: ScopedEnvs,
         management_peer_id: PeerId,
+        permissions: PermissionsConfig,
+        limits: ServiceLimits,
     ) -> Result<Self, std::io::Error> {
         let base_dir = to_abs_path(base_dir);
 
@@ -53,8 +68,12 @@ impl ServicesConfig {
             workdir: config_utils::workdir(&base_dir),
             modules_dir: config_utils::modules_dir(&base_dir),
             services_dir: config_utils::services_dir(&base_dir),
+            scripts_dir: config_utils::scripts_dir(&base_dir),
             envs,
+            scoped_envs,
             management_peer_id,
+            permissions,
+            limits,
         };
 
         create_dirs(&[
@@ -62,8 +81,154 @@ impl ServicesConfig {
             &this.workdir,
             &this.modules_dir,
             &this.services_dir,
+            &this.scripts_dir,
         ])?;
 
         Ok(this)
     }
 }
+
+/// Resources a service is allowed to consume. Limits that aren't set aren't enforced.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceLimits {
+    /// Max memory size of each module, in Wasm pages (64 Kb)
+    #[serde(default)]
+    pub max_memory_pages: Option<u32>,
+    /// Max time a call waits for the previous calls to the same service to finish
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub lock_timeout: Option<Duration>,
+    /// Max duration of a single call. The engine can't interrupt a running wasm call,
+    /// so a call that takes longer is abandoned, and the service is unavailable until it finishes
+    // Not serialized when unset, so ids of blueprints that don't set it stay the same
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_timeout: Option<Duration>,
+    /// Max size of the service workdir, in bytes. It is checked before calls,
+    /// so a single call can write past it, but no calls are accepted after that
+    #[serde(default)]
+    pub max_disk_bytes: Option<u64>,
+}
+
+impl ServiceLimits {
+    /// Combines two sets of limits, taking the strictest of each
+    pub fn narrow(&self, other: &ServiceLimits) -> ServiceLimits {
+        fn strictest<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(min(a, b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        ServiceLimits {
+            max_memory_pages: strictest(self.max_memory_pages, other.max_memory_pages),
+            lock_timeout: strictest(self.lock_timeout, other.lock_timeout),
+            call_timeout: strictest(self.call_timeout, other.call_timeout),
+            max_disk_bytes: strictest(self.max_disk_bytes, other.max_disk_bytes),
+        }
+    }
+}
+
+/// Environment variables that are visible only to the modules they are granted to.
+/// Modules are identified by hash rather than name: names are chosen by whoever uploads
+/// a module, so any peer could upload a module named the same as one holding a secret.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopedEnvs {
+    /// Envs for modules with a given hash
+    #[serde(default)]
+    pub modules: HashMap<String, HashMap<String, String>>,
+    /// Envs for all modules of services created from a given blueprint id
+    #[serde(default)]
+    pub blueprints: HashMap<String, HashMap<String, String>>,
+}
+
+impl ScopedEnvs {
+    /// Envs granted to module with `hash`, which is a part of `blueprint_id`.
+    /// Envs granted to the module take precedence over the blueprint ones.
+    pub fn module_envs(&self, blueprint_id: &str, hash: Option<&str>) -> HashMap<Vec<u8>, Vec<u8>> {
+        let scopes = [
+            self.blueprints.get(blueprint_id),
+            hash.and_then(|hash| self.modules.get(hash)),
+        ];
+
+        scopes
+            .iter()
+            .flatten()
+            .flat_map(|envs| envs.iter())
+            .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScopedEnvs, ServiceLimits};
+    use std::time::Duration;
+
+    #[test]
+    fn scoped_envs() {
+        let envs: ScopedEnvs = toml::from_str(
+            r#"
+            [modules.storage_hash]
+            DB_PASSWORD = "secret"
+            LEVEL = "module"
+
+            [blueprints.bp]
+            LEVEL = "blueprint"
+            REGION = "eu"
+            "#,
+        )
+        .expect("deserialize envs");
+
+        let get = |bp, hash| {
+            let mut envs: Vec<_> = envs
+                .module_envs(bp, hash)
+                .into_iter()
+                .map(|(k, v)| (String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap()))
+                .collect();
+            envs.sort();
+            envs
+        };
+
+        assert_eq!(
+            get("bp", Some("storage_hash")),
+            vec![
+                ("DB_PASSWORD".into(), "secret".into()),
+                ("LEVEL".into(), "module".into()),
+                ("REGION".into(), "eu".into()),
+            ]
+        );
+        // secrets aren't passed to other modules, even if they are named the same
+        assert_eq!(get("bp", Some("other_hash")), get("bp", None));
+        assert!(get("bp", Some("storage"))
+            .iter()
+            .all(|(k, _)| k != "DB_PASSWORD"));
+        assert!(get("other", Some("other_hash")).is_empty());
+    }
+
+    #[test]
+    fn narrow_limits() {
+        let node: ServiceLimits = toml::from_str(
+            r#"
+            max_memory_pages = 100
+            lock_timeout = "10s"
+            call_timeout = "30s"
+            "#,
+        )
+        .expect("deserialize limits");
+        let blueprint = ServiceLimits {
+            max_memory_pages: Some(200),
+            lock_timeout: Some(Duration::from_secs(1)),
+            call_timeout: None,
+            max_disk_bytes: Some(1024),
+        };
+
+        let limits = node.narrow(&blueprint);
+        assert_eq!(limits.max_memory_pages, Some(100));
+        assert_eq!(limits.lock_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(limits.call_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(limits.max_disk_bytes, Some(1024));
+        assert_eq!(node.narrow(&<_>::default()), node);
+    }
+}
@@ -171,7 +171,7 @@ impl ConnectedClient {
             .map(|(key, value)| (key.to_string(), value))
             .collect();
         let particle = make_particle(
-            self.peer_id,
+            &self.client.key_pair,
             self.call_service_in.clone(),
             script.into(),
             self.node,
@@ -19,13 +19,17 @@ use crate::{make_tmp_dir, now_ms, put_aquamarine, uuid};
 use host_closure::Args;
 use particle_protocol::Particle;
 
-use aquamarine_vm::{AquamarineVM, AquamarineVMConfig, CallServiceClosure, InterpreterOutcome};
+use aquamarine::ExecutionRecord;
+use aquamarine_vm::{
+    AquamarineVM, AquamarineVMConfig, AquamarineVMError, CallServiceClosure, InterpreterOutcome,
+};
 
 use fstrings::f;
+use libp2p::identity::{ed25519::Keypair, PublicKey};
 use libp2p::PeerId;
 use parking_lot::Mutex;
 use serde_json::Value as JValue;
-use std::{collections::HashMap, ops::Deref, sync::Arc};
+use std::{collections::HashMap, ops::Deref, path::Path, sync::Arc};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Instruction {
@@ -124,13 +128,39 @@ pub fn make_vm(peer_id: &PeerId, call_service: CallServiceClosure) -> Aquamarine
         .expect("vm should be created")
 }
 
+/// Replays every recorded execution of the particle on a fresh interpreter.
+/// Returns recorded executions along with outcomes of their replays.
+pub fn replay_particle(
+    recordings_dir: &Path,
+    particle_id: &str,
+) -> Vec<(
+    ExecutionRecord,
+    Result<InterpreterOutcome, AquamarineVMError>,
+)> {
+    let records = aquamarine::load_recording(recordings_dir, particle_id).expect("load recording");
+
+    let tmp = make_tmp_dir();
+    let interpreter = put_aquamarine(tmp.join("modules"));
+    let particles_dir = tmp.join("particles");
+    std::fs::create_dir_all(&particles_dir).expect("create particles dir");
+
+    records
+        .into_iter()
+        .map(|record| {
+            let outcome = aquamarine::replay(interpreter.clone(), particles_dir.clone(), &record);
+            (record, outcome)
+        })
+        .collect()
+}
+
 pub fn make_particle(
-    peer_id: PeerId,
+    key_pair: &Keypair,
     service_in: Arc<Mutex<HashMap<String, JValue>>>,
     script: String,
     relay: impl Into<Option<PeerId>>,
     local_vm: &mut AquamarineVM,
 ) -> Particle {
+    let peer_id = PeerId::from(PublicKey::Ed25519(key_pair.public()));
     let load_variables = service_in
         .lock()
         .keys()
@@ -180,7 +210,7 @@ pub fn make_particle(
 
     log::info!("Made a particle {}", id);
 
-    Particle {
+    let mut particle = Particle {
         id,
         init_peer_id: peer_id,
         timestamp: now_ms() as u64,
@@ -188,7 +218,10 @@ pub fn make_particle(
         script,
         signature: vec![],
         data,
-    }
+    };
+    particle.sign(key_pair);
+
+    particle
 }
 
 pub fn read_args(
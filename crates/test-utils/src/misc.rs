@@ -20,10 +20,10 @@ use config_utils::{modules_dir, to_abs_path};
 use fluence_client::Transport;
 use fluence_libp2p::types::OneshotOutlet;
 use fluence_libp2p::{build_memory_transport, build_transport};
-use server_config::{BootstrapConfig, NetworkConfig, ServicesConfig};
+use server_config::{BootstrapConfig, NetworkConfig, PermissionsConfig, ServicesConfig};
 use trust_graph::{Certificate, TrustGraph};
 
-use aquamarine::VmPoolConfig;
+use aquamarine::{VmPoolConfig, VmPoolSettings};
 use async_std::task;
 use connection_pool::{ConnectionPoolApi, ConnectionPoolT};
 use eyre::WrapErr;
@@ -33,6 +33,7 @@ use libp2p::{
     identity::ed25519::{Keypair, PublicKey},
     PeerId,
 };
+use prometheus::Registry;
 use rand::Rng;
 use script_storage::ScriptStorageConfig;
 use serde_json::{json, Value as JValue};
@@ -225,6 +226,14 @@ pub struct SwarmConfig {
     pub transport: Transport,
     pub tmp_dir: Option<PathBuf>,
     pub pool_size: Option<usize>,
+    /// VMs kept while idle, all `pool_size` VMs are kept if not set
+    pub min_pool_size: Option<usize>,
+    pub vm_idle_timeout: Option<Duration>,
+    /// If set, node metrics are registered there
+    pub registry: Option<Registry>,
+    pub permissions: PermissionsConfig,
+    /// If set, particle executions are recorded there, see `replay_particle`
+    pub recordings_dir: Option<PathBuf>,
 }
 
 impl Default for SwarmConfig {
@@ -236,6 +245,11 @@ impl Default for SwarmConfig {
             transport: Transport::Memory,
             tmp_dir: <_>::default(),
             pool_size: <_>::default(),
+            min_pool_size: <_>::default(),
+            vm_idle_timeout: <_>::default(),
+            registry: <_>::default(),
+            permissions: <_>::default(),
+            recordings_dir: <_>::default(),
         }
     }
 }
@@ -261,7 +275,7 @@ pub fn create_swarm(config: SwarmConfig) -> (PeerId, Box<Node>, PathBuf, Keypair
     use libp2p::identity;
 
     #[rustfmt::skip]
-    let SwarmConfig { bootstraps, listen_on, trust, transport, pool_size, .. } = config;
+    let SwarmConfig { bootstraps, listen_on, trust, transport, pool_size, min_pool_size, vm_idle_timeout, registry, permissions, .. } = config;
 
     let kp = Keypair::generate();
     let public_key = libp2p::identity::PublicKey::Ed25519(kp.public());
@@ -287,17 +301,34 @@ pub fn create_swarm(config: SwarmConfig) -> (PeerId, Box<Node>, PathBuf, Keypair
     // execution timeout
     let execution_timeout = Duration::from_secs(5);
     let pool_size = pool_size.unwrap_or(1);
-    let pool_config = VmPoolConfig::new(
-        peer_id,
-        stepper_base_dir,
-        air_interpreter,
+    let pool_settings = VmPoolSettings {
         pool_size,
+        min_pool_size: min_pool_size.unwrap_or(pool_size),
+        vm_idle_timeout: vm_idle_timeout.unwrap_or(Duration::from_secs(60)),
         execution_timeout,
-    )
-    .expect("create vm pool config");
+        max_lost_vms: pool_size,
+        mailbox_size: 100,
+        max_queued_particles: 1000,
+        seen_particles_limit: 10_000,
+        particle_data_max_age: Duration::from_secs(60 * 60),
+        particle_data_max_size: 1 << 30,
+        recordings_dir: config.recordings_dir,
+        interpreter_watch_interval: None,
+    };
+    let pool_config = VmPoolConfig::new(peer_id, stepper_base_dir, air_interpreter, pool_settings)
+        .expect("create vm pool config");
 
-    let services_config = ServicesConfig::new(peer_id, tmp.join("services"), <_>::default(), m_id)
-        .expect("create services config");
+    let services_dir = tmp.join("services");
+    let services_config = ServicesConfig::new(
+        peer_id,
+        services_dir,
+        <_>::default(),
+        <_>::default(),
+        m_id,
+        permissions,
+        <_>::default(),
+    )
+    .expect("create services config");
 
     let network_config = NetworkConfig {
         key_pair: kp.clone(),
@@ -308,17 +339,14 @@ pub fn create_swarm(config: SwarmConfig) -> (PeerId, Box<Node>, PathBuf, Keypair
         registry: None,
         protocol_config: Default::default(),
         kademlia_config: Default::default(),
+        kademlia_dir: Some(tmp.join("kademlia")),
         particle_queue_buffer: 100,
         particle_parallelism: 16,
         bootstrap_frequency: 1,
         allow_local_addresses: true,
         particle_timeout: Duration::from_secs(5),
-    };
-
-    use identity::Keypair::Ed25519;
-    let transport = match transport {
-        Transport::Memory => build_memory_transport(Ed25519(kp)),
-        Transport::Network => build_transport(Ed25519(kp), Duration::from_secs(10)),
+        modules_dir: services_config.modules_dir.clone(),
+        module_fetch_timeout: Duration::from_secs(5),
     };
 
     let script_storage_config = ScriptStorageConfig {
@@ -326,6 +354,14 @@ pub fn create_swarm(config: SwarmConfig) -> (PeerId, Box<Node>, PathBuf, Keypair
         max_failures: 1,
         particle_ttl: Duration::from_secs(5),
         peer_id,
+        key_pair: kp.clone(),
+        scripts_dir: services_config.scripts_dir.clone(),
+    };
+
+    use identity::Keypair::Ed25519;
+    let transport = match transport {
+        Transport::Memory => build_memory_transport(Ed25519(kp)),
+        Transport::Network => build_transport(Ed25519(kp), Duration::from_secs(10)),
     };
 
     let mut node = Node::with(
@@ -335,7 +371,7 @@ pub fn create_swarm(config: SwarmConfig) -> (PeerId, Box<Node>, PathBuf, Keypair
         pool_config,
         network_config,
         vec![listen_on.clone()],
-        None,
+        registry,
         "0.0.0.0:0".parse().unwrap(),
         bootstraps,
         script_storage_config,
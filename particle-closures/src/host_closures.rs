@@ -16,68 +16,107 @@
 
 use crate::identify::{identify, NodeInfo};
 
+use aquamarine::InterpreterApi;
 use connection_pool::{ConnectionPoolApi, ConnectionPoolT};
 use host_closure::{
     from_base58, Args, Closure, ClosureDescriptor, JError, ParticleClosure, ParticleParameters,
 };
 use ivalue_utils::{into_record, into_record_opt, ok, IValue};
-use kademlia::{KademliaApi, KademliaApiT};
+use kademlia::{KademliaApi, KademliaApiT, Quorum};
+use module_exchange::ModuleExchangeApi;
 use now_millis::{now_ms, now_sec};
 use particle_protocol::Contact;
 use particle_providers::ProviderRepository;
 use particle_services::ParticleAppServices;
-use script_storage::ScriptStorageApi;
-use server_config::ServicesConfig;
+use script_storage::{Schedule, ScriptStorageApi};
+use server_config::{PermissionsConfig, ServicesConfig};
 
 use async_std::task;
 use humantime_serde::re::humantime::format_duration as pretty;
 use libp2p::{core::Multiaddr, PeerId};
 use multihash::{Code, MultihashDigest};
-use particle_modules::ModuleRepository;
+use particle_modules::{AddBlueprint, ModuleRepository};
 use serde_json::{json, Value as JValue};
 use std::borrow::Borrow;
-use std::num::ParseIntError;
+use std::num::{NonZeroUsize, ParseIntError};
 use std::time::{Duration, Instant};
-use std::{str::FromStr, sync::Arc};
+use std::{path::PathBuf, str::FromStr, sync::Arc};
 use JValue::Array;
 
+/// Builtins that only the management peer can call, regardless of configured permissions
+const MANAGEMENT_ONLY: &[(&str, &str)] = &[
+    ("peer", "disconnect"),
+    ("kad", "unban"),
+    ("dist", "gc_modules"),
+    ("peer", "reload_interpreter"),
+];
+
+/// Builtins that only the management peer can call, unless permissions allow them to others.
+/// Fetching makes the node download modules from arbitrary peers, and modules and blueprints
+/// aren't owned by their uploaders, so neither is open by default
+const CLOSED_BY_DEFAULT: &[(&str, &str)] = &[
+    ("dist", "fetch_module"),
+    ("dist", "remove_module"),
+    ("dist", "remove_blueprint"),
+];
+
 #[derive(Clone)]
 pub struct HostClosures<C> {
     pub create_service: ParticleClosure,
     pub call_service: ParticleClosure,
 
     pub add_module: Closure,
-    pub add_blueprint: Closure,
+    pub upload_start: ParticleClosure,
+    pub upload_chunk: ParticleClosure,
+    pub upload_finish: ParticleClosure,
     pub list_modules: Closure,
     pub get_module_interface: Closure,
     pub get_blueprints: Closure,
+    pub modules: ModuleRepository,
 
     pub get_interface: Closure,
     pub list_services: Closure,
 
     pub identify: Closure,
     pub add_alias: ParticleClosure,
+    pub remove_service: ParticleClosure,
+    pub restart_service: ParticleClosure,
+    pub upgrade_service: ParticleClosure,
     pub connectivity: C,
     pub script_storage: ScriptStorageApi,
+    pub interpreter: InterpreterApi,
+    pub management_peer_id: PeerId,
+    pub permissions: PermissionsConfig,
 
     // deprecated
     pub add_provider: Closure,
     pub get_providers: Closure,
 }
 
-impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoolApi>>
-    HostClosures<C>
+impl<
+        C: Clone
+            + Send
+            + Sync
+            + 'static
+            + AsRef<KademliaApi>
+            + AsRef<ConnectionPoolApi>
+            + AsRef<ModuleExchangeApi>,
+    > HostClosures<C>
 {
     pub fn new(
         connectivity: C,
         script_storage: ScriptStorageApi,
         node_info: NodeInfo,
         config: ServicesConfig,
+        interpreter: InterpreterApi,
     ) -> Self {
         let modules_dir = config.modules_dir.clone();
         let blueprint_dir = config.blueprint_dir.clone();
         let providers = ProviderRepository::new(config.local_peer_id);
-        let modules = ModuleRepository::new(&modules_dir, &blueprint_dir);
+        let services_dir = config.services_dir.clone();
+        let modules = ModuleRepository::new(&modules_dir, &blueprint_dir, &services_dir);
+        let management_peer_id = config.management_peer_id;
+        let permissions = config.permissions.clone();
 
         let services = ParticleAppServices::new(config, modules.clone());
 
@@ -88,15 +127,24 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
             get_module_interface: modules.get_interface(),
             get_blueprints: modules.get_blueprints(),
             add_module: modules.add_module(),
-            add_blueprint: modules.add_blueprint(),
+            upload_start: modules.upload_start(),
+            upload_chunk: modules.upload_chunk(),
+            upload_finish: modules.upload_finish(),
+            modules: modules.clone(),
             create_service: services.create_service(),
             call_service: services.call_service(),
             get_interface: services.get_interface(),
             list_services: services.list_services(),
-            identify: identify(node_info),
+            identify: identify(node_info, interpreter.clone()),
             add_alias: services.add_alias(),
+            remove_service: services.remove_service(),
+            restart_service: services.restart_service(),
+            upgrade_service: services.upgrade_service(),
             connectivity,
             script_storage,
+            interpreter,
+            management_peer_id,
+            permissions,
         }
     }
 
@@ -116,6 +164,15 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
             }
         };
 
+        if !self.is_allowed(&params, &args) {
+            let err = format!(
+                "Forbidden. User id '{}' cannot call '{}' '{}'",
+                params.init_user_id, args.service_id, args.function_name
+            );
+            log::warn!("{}", err);
+            return ivalue_utils::error(json!(err));
+        }
+
         log::trace!("Host function call, args: {:#?}", args);
         let log_args = format!(
             "Executed host call {:?} {:?}",
@@ -128,23 +185,44 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
         let result = match (args.service_id.as_str(), args.function_name.as_str()) {
             ("peer", "is_connected")          => wrap(self.is_connected(args)),
             ("peer", "connect")               => wrap(self.connect(args)),
+            ("peer", "disconnect")            => wrap(self.disconnect(args)),
             ("peer", "get_contact")           => wrap_opt(self.get_contact(args)),
             ("peer", "identify")              => (self.identify)(args),
             ("peer", "timestamp_ms")          => ok(json!(now_ms())),
             ("peer", "timestamp_sec")         => ok(json!(now_sec())),
+            ("peer", "reload_interpreter")    => wrap(self.reload_interpreter(args)),
 
             ("kad", "neighborhood")           => wrap(self.neighborhood(args)),
+            ("kad", "put_value")              => wrap(self.put_value(args, params)),
+            ("kad", "get_value")              => wrap(self.get_value(args)),
+            ("kad", "remove_value")           => wrap(self.remove_value(args, params)),
+            ("kad", "routing_table")          => wrap(self.routing_table()),
+            ("kad", "banned")                 => wrap(self.banned()),
+            ("kad", "unban")                  => wrap(self.unban(args)),
+
+            ("providers", "add")              => wrap(self.add_signed_provider(args)),
+            ("providers", "get")              => wrap(self.get_signed_providers(args)),
 
             ("srv", "create")                 => (self.create_service)(params, args),
             ("srv", "list")                   => (self.list_services)(args),
             ("srv", "get_interface")          => (self.get_interface)(args),
             ("srv", "add_alias")              => (self.add_alias)(params, args),
+            ("srv", "remove")                 => (self.remove_service)(params, args),
+            ("srv", "restart")                => (self.restart_service)(params, args),
+            ("srv", "upgrade")                => (self.upgrade_service)(params, args),
 
             ("dist", "add_module")            => (self.add_module)(args),
+            ("dist", "upload_start")          => (self.upload_start)(params, args),
+            ("dist", "upload_chunk")          => (self.upload_chunk)(params, args),
+            ("dist", "upload_finish")         => (self.upload_finish)(params, args),
             ("dist", "list_modules")          => (self.list_modules)(args),
             ("dist", "get_module_interface")  => (self.get_module_interface)(args),
-            ("dist", "add_blueprint")         => (self.add_blueprint)(args),
+            ("dist", "add_blueprint")         => wrap(self.add_blueprint(args, params)),
+            ("dist", "fetch_module")          => wrap(self.fetch_module(args)),
             ("dist", "list_blueprints")       => (self.get_blueprints)(args),
+            ("dist", "remove_module")         => wrap(self.remove_module(args, params)),
+            ("dist", "remove_blueprint")      => wrap(self.remove_blueprint(args, params)),
+            ("dist", "gc_modules")            => wrap(self.gc_modules()),
 
             ("script", "add")                 => wrap(self.add_script(args, params)),
             ("script", "remove")              => wrap(self.remove_script(args, params)),
@@ -161,6 +239,26 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
         result
     }
 
+    /// Management peer is allowed to call anything, others are checked against configured permissions
+    fn is_allowed(&self, params: &ParticleParameters, args: &Args) -> bool {
+        self.may_call(&params.init_user_id, &args.service_id, &args.function_name)
+    }
+
+    fn may_call(&self, init_user_id: &str, service_id: &str, function_name: &str) -> bool {
+        let builtin = (service_id, function_name);
+        let management_only = MANAGEMENT_ONLY.contains(&builtin);
+        let closed = CLOSED_BY_DEFAULT.contains(&builtin)
+            && !self.permissions.is_restricted(service_id, function_name);
+        match PeerId::from_str(init_user_id) {
+            Ok(peer_id) if peer_id == self.management_peer_id => true,
+            _ if management_only || closed => false,
+            Ok(peer_id) => self
+                .permissions
+                .is_allowed(&peer_id, service_id, function_name),
+            Err(_) => !self.permissions.is_restricted(service_id, function_name),
+        }
+    }
+
     fn neighborhood(&self, args: Args) -> Result<JValue, JError> {
         let key = from_base58("key", &mut args.function_args.into_iter())?;
         let key = Code::Sha2_256.digest(&key);
@@ -171,6 +269,127 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
         Ok(neighbors)
     }
 
+    /// Stores value in the DHT under the key namespaced by particle's init peer, so `kad` calls
+    /// of other peers don't touch it. Optional third argument is a quorum, see `quorum`
+    fn put_value(&self, args: Args, params: ParticleParameters) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let key: String = Args::next("key", &mut args)?;
+        let value: String = Args::next("value", &mut args)?;
+        let quorum = quorum(Args::maybe_next("quorum", &mut args)?)?;
+        let publisher = PeerId::from_str(&params.init_user_id)?;
+
+        let put = self
+            .kademlia()
+            .put_value(publisher, key, value.into_bytes(), quorum);
+        task::block_on(put)?;
+        Ok(JValue::Null)
+    }
+
+    /// Returns all distinct values `publisher` stored in the DHT under the key, possibly none.
+    /// Optional third argument is a quorum, see `quorum`
+    fn get_value(&self, args: Args) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let key: String = Args::next("key", &mut args)?;
+        let publisher: String = Args::next("publisher", &mut args)?;
+        let publisher = PeerId::from_str(&publisher)?;
+        let quorum = quorum(Args::maybe_next("quorum", &mut args)?)?;
+
+        let get = self.kademlia().get_value(publisher, key, quorum);
+        let values = task::block_on(get)?;
+        let values = values
+            .into_iter()
+            .map(|v| JValue::String(String::from_utf8_lossy(&v).into_owned()))
+            .collect();
+
+        Ok(JValue::Array(values))
+    }
+
+    /// Removes value that particle's init peer stored under the key from the DHT.
+    /// Optional second argument is a quorum, see `quorum`
+    fn remove_value(&self, args: Args, params: ParticleParameters) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let key: String = Args::next("key", &mut args)?;
+        let quorum = quorum(Args::maybe_next("quorum", &mut args)?)?;
+        let publisher = PeerId::from_str(&params.init_user_id)?;
+        task::block_on(self.kademlia().remove_value(publisher, key, quorum))?;
+        Ok(JValue::Null)
+    }
+
+    fn routing_table(&self) -> Result<JValue, JError> {
+        let peers = task::block_on(self.kademlia().routing_table())?;
+        let peers = peers
+            .into_iter()
+            .map(|p| {
+                json!({
+                    "peer_id": p.peer_id.to_string(),
+                    "addresses": p.addresses,
+                    "bucket": p.bucket,
+                    "connected": p.connected,
+                })
+            })
+            .collect();
+
+        Ok(JValue::Array(peers))
+    }
+
+    fn banned(&self) -> Result<JValue, JError> {
+        let peers = task::block_on(self.kademlia().banned())?;
+        let peers = peers
+            .into_iter()
+            .map(|p| {
+                json!({
+                    "peer_id": p.peer_id.to_string(),
+                    "failures": p.failures,
+                    "unban_in_sec": p.unban_in.as_secs(),
+                })
+            })
+            .collect();
+
+        Ok(JValue::Array(peers))
+    }
+
+    fn unban(&self, args: Args) -> Result<JValue, JError> {
+        let peer_id: String = Args::next("peer_id", &mut args.function_args.into_iter())?;
+        let peer_id = PeerId::from_str(peer_id.as_str())?;
+        let ok = task::block_on(self.kademlia().unban(peer_id))?;
+        Ok(json!(ok))
+    }
+
+    /// Announces this node, or a service on it, as a provider of the key.
+    /// Optional third argument is a TTL in seconds, `provider_ttl` from config by default
+    fn add_signed_provider(&self, args: Args) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let key: String = Args::next("key", &mut args)?;
+        let service_id: Option<Option<String>> = Args::maybe_next("service_id", &mut args)?;
+        let ttl: Option<Option<u64>> = Args::maybe_next("ttl_sec", &mut args)?;
+        let ttl = ttl.flatten().map(Duration::from_secs);
+
+        let add = self.kademlia().add_provider(key, service_id.flatten(), ttl);
+        task::block_on(add)?;
+        Ok(JValue::Null)
+    }
+
+    fn get_signed_providers(&self, args: Args) -> Result<JValue, JError> {
+        let key: String = Args::next("key", &mut args.function_args.into_iter())?;
+        let providers = task::block_on(self.kademlia().get_providers(key))?;
+        let providers = providers
+            .into_iter()
+            .map(|p| {
+                json!({
+                    "peer": p.peer.to_string(),
+                    "service_id": p.service_id,
+                    "expires_at_sec": p.expires_at,
+                })
+            })
+            .collect();
+
+        Ok(JValue::Array(providers))
+    }
+
     fn is_connected(&self, args: Args) -> Result<JValue, JError> {
         let peer: String = Args::next("peer_id", &mut args.function_args.into_iter())?;
         let peer = PeerId::from_str(peer.as_str())?;
@@ -191,6 +410,24 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
         Ok(json!(ok))
     }
 
+    fn disconnect(&self, args: Args) -> Result<JValue, JError> {
+        let peer_id: String = Args::next("peer_id", &mut args.function_args.into_iter())?;
+        let peer_id = PeerId::from_str(peer_id.as_str())?;
+        let contact = Contact::new(peer_id, vec![]);
+
+        let ok = task::block_on(self.connection_pool().disconnect(contact));
+        Ok(json!(ok))
+    }
+
+    /// Recreates interpreters from the wasm at the given path, or from the current one.
+    /// Particles being executed finish on the previous interpreter.
+    fn reload_interpreter(&self, args: Args) -> Result<JValue, JError> {
+        let path: Option<String> = Args::maybe_next("path", &mut args.function_args.into_iter())?;
+        let path = self.interpreter.reload(path.map(PathBuf::from))?;
+
+        Ok(json!(path))
+    }
+
     fn get_contact(&self, args: Args) -> Result<Option<JValue>, JError> {
         let peer: String = Args::next("peer_id", &mut args.function_args.into_iter())?;
         let peer = PeerId::from_str(peer.as_str())?;
@@ -198,21 +435,50 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
         Ok(contact.map(|c| json!(c)))
     }
 
+    /// Second argument is either an interval in seconds as a string, or a schedule spec like
+    /// `{"cron": "0 */5 * * * *", "start_at_sec": 1613000000, "end_at_sec": 1614000000, "max_runs": 10}`
     fn add_script(&self, args: Args, params: ParticleParameters) -> Result<JValue, JError> {
         #[derive(thiserror::Error, Debug)]
         #[error("Error while deserializing field interval_sec: not a valid u64")]
         struct Error(#[source] ParseIntError);
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct ScheduleSpec {
+            #[serde(default)]
+            interval_sec: Option<u64>,
+            #[serde(default)]
+            cron: Option<String>,
+            #[serde(default)]
+            start_at_sec: Option<u64>,
+            #[serde(default)]
+            end_at_sec: Option<u64>,
+            #[serde(default)]
+            max_runs: Option<u32>,
+        }
+
         let mut args = args.function_args.into_iter();
 
         let script: String = Args::next("script", &mut args)?;
-        let interval = Args::maybe_next("interval_sec", &mut args)?;
-        let interval = interval
-            .map(|s: String| s.parse::<u64>())
-            .transpose()
-            .map_err(Error)?;
-        let interval = interval.map(Duration::from_secs);
+        let schedule = match Args::maybe_next("schedule", &mut args)? {
+            Some(JValue::String(interval)) => {
+                let interval = interval.parse::<u64>().map_err(Error)?;
+                Schedule::interval(Some(Duration::from_secs(interval)))
+            }
+            Some(spec) => {
+                let spec: ScheduleSpec = Args::next("schedule", &mut std::iter::once(spec))?;
+                Schedule {
+                    interval: spec.interval_sec.map(Duration::from_secs),
+                    cron: spec.cron.as_deref().map(Schedule::parse_cron).transpose()?,
+                    start_at: spec.start_at_sec,
+                    end_at: spec.end_at_sec,
+                    max_runs: spec.max_runs,
+                }
+            }
+            None => Schedule::interval(None),
+        };
         let creator = PeerId::from_str(&params.init_user_id)?;
-        let id = self.script_storage.add_script(script, interval, creator)?;
+        let id = self.script_storage.add_script(script, schedule, creator)?;
 
         Ok(json!(id))
     }
@@ -221,16 +487,132 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
         let mut args = args.function_args.into_iter();
 
         let uuid: String = Args::next("uuid", &mut args)?;
-        let force: Option<String> = Args::maybe_next("force", &mut args)?;
-        // TODO HACK: this is a hack to allow anyone to delete any script if they know this secret
-        let force = force.map_or(false, |s| s == "--force");
         let actor = PeerId::from_str(&params.init_user_id)?;
+        // management peer is allowed to remove any script
+        let force = actor == self.management_peer_id;
 
         let ok = task::block_on(self.script_storage.remove_script(uuid, actor, force))?;
 
         Ok(json!(ok))
     }
 
+    /// Saves blueprint. Modules it references by hash, but that are missing locally,
+    /// are fetched from peers passed as an optional second argument.
+    /// Fetching requires the same permissions as `("dist", "fetch_module")`
+    fn add_blueprint(&self, args: Args, params: ParticleParameters) -> Result<JValue, JError> {
+        #[derive(thiserror::Error, Debug)]
+        #[error("Forbidden. User id '{0}' cannot fetch modules {1:?}")]
+        struct Forbidden(String, Vec<String>);
+
+        let mut args = args.function_args.into_iter();
+
+        let blueprint: AddBlueprint = Args::next("blueprint_request", &mut args)?;
+        let peers: Vec<String> = Args::maybe_next("peers", &mut args)?.unwrap_or_default();
+        let missing = self.modules.missing_modules(&blueprint);
+        if !missing.is_empty() && !peers.is_empty() {
+            if !self.may_call(&params.init_user_id, "dist", "fetch_module") {
+                return Err(Forbidden(params.init_user_id, missing).into());
+            }
+            let peers = parse_peers(peers)?;
+            for hash in missing {
+                self.fetch(&hash, &peers)?;
+            }
+        }
+        let blueprint_id = self.modules.save_blueprint(blueprint)?;
+
+        Ok(json!(blueprint_id))
+    }
+
+    /// Fetches module by hash from the first peer that has it, and saves it locally
+    fn fetch_module(&self, args: Args) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let hash: String = Args::next("hash", &mut args)?;
+        let peers: Vec<String> = Args::next("peers", &mut args)?;
+        self.fetch(&hash, &parse_peers(peers)?)?;
+
+        Ok(json!(hash))
+    }
+
+    fn fetch(&self, hash: &str, peers: &[PeerId]) -> Result<(), JError> {
+        #[derive(thiserror::Error, Debug)]
+        #[error("Module {0} wasn't fetched from any of {1:?}")]
+        struct Error(String, Vec<PeerId>);
+
+        for &peer in peers {
+            // connected peer is reachable without addresses, others have to be discovered
+            let addresses = if task::block_on(self.connection_pool().is_connected(peer)) {
+                vec![]
+            } else {
+                task::block_on(self.kademlia().discover_peer(peer)).unwrap_or_default()
+            };
+
+            let fetch = self
+                .module_exchange()
+                .fetch(peer, addresses, hash.to_string());
+            match task::block_on(fetch) {
+                Ok(fetched) => {
+                    match self
+                        .modules
+                        .add_fetched_module(hash, &fetched.module, &fetched.name)
+                    {
+                        Ok(_) => return Ok(()),
+                        Err(err) => {
+                            log::warn!("Module {} from {} is rejected: {}", hash, peer, err)
+                        }
+                    }
+                }
+                Err(err) => log::warn!("{}", err),
+            }
+        }
+
+        Err(Error(hash.to_string(), peers.to_vec()).into())
+    }
+
+    /// Removes module by hash. Optional second argument forces removal of a module
+    /// that is still used by blueprints or services, only the management peer can do that.
+    fn remove_module(&self, args: Args, params: ParticleParameters) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let hash: String = Args::next("hash", &mut args)?;
+        let force = self.force(Args::maybe_next("force", &mut args)?, &params)?;
+        self.modules.remove_module(&hash, force)?;
+
+        Ok(JValue::Null)
+    }
+
+    /// Removes blueprint by id. Optional second argument forces removal of a blueprint
+    /// that is still used by services, only the management peer can do that.
+    fn remove_blueprint(&self, args: Args, params: ParticleParameters) -> Result<JValue, JError> {
+        let mut args = args.function_args.into_iter();
+
+        let blueprint_id: String = Args::next("blueprint_id", &mut args)?;
+        let force = self.force(Args::maybe_next("force", &mut args)?, &params)?;
+        self.modules.remove_blueprint(&blueprint_id, force)?;
+
+        Ok(JValue::Null)
+    }
+
+    /// Removes module files that aren't referenced and weren't added recently, see `collect_garbage`
+    fn gc_modules(&self) -> Result<JValue, JError> {
+        let removed = self.modules.collect_garbage()?;
+
+        Ok(json!(removed))
+    }
+
+    fn force(&self, force: Option<bool>, params: &ParticleParameters) -> Result<bool, JError> {
+        #[derive(thiserror::Error, Debug)]
+        #[error("Forbidden. Only management peer can force removal")]
+        struct Error;
+
+        let force = force.unwrap_or(false);
+        if force && PeerId::from_str(&params.init_user_id).ok() != Some(self.management_peer_id) {
+            return Err(Error.into());
+        }
+
+        Ok(force)
+    }
+
     fn list_scripts(&self) -> Result<JValue, JError> {
         let scripts = task::block_on(self.script_storage.list_scripts())?;
 
@@ -239,11 +621,19 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
                 .into_iter()
                 .map(|(id, script)| {
                     let id: &String = id.borrow();
+                    let next_fire_sec = script.deadline().map(|ms| ms / 1000);
+                    let schedule = script.schedule;
                     json!({
                         "id": id,
                         "src": script.src,
                         "failures": script.failures,
-                        "interval": script.interval.map(|i| pretty(i).to_string()),
+                        "interval": schedule.interval.map(|i| pretty(i).to_string()),
+                        "cron": schedule.cron.map(|c| c.to_string()),
+                        "start_at_sec": schedule.start_at,
+                        "end_at_sec": schedule.end_at,
+                        "max_runs": schedule.max_runs,
+                        "runs": script.runs,
+                        "next_fire_sec": next_fire_sec,
                         "owner": script.owner.to_string(),
                     })
                 })
@@ -258,6 +648,39 @@ impl<C: Clone + Send + Sync + 'static + AsRef<KademliaApi> + AsRef<ConnectionPoo
     fn connection_pool(&self) -> &ConnectionPoolApi {
         self.connectivity.as_ref()
     }
+
+    fn module_exchange(&self) -> &ModuleExchangeApi {
+        self.connectivity.as_ref()
+    }
+}
+
+/// Quorum is either "one", "majority", "all" or a number of peers. Default is "one"
+fn quorum(value: Option<JValue>) -> Result<Quorum, JError> {
+    #[derive(thiserror::Error, Debug)]
+    #[error("Invalid quorum {0}: expected \"one\", \"majority\", \"all\" or a positive number")]
+    struct Error(JValue);
+
+    let quorum = match &value {
+        None | Some(JValue::Null) => Some(Quorum::One),
+        Some(JValue::String(q)) => match q.as_str() {
+            "one" => Some(Quorum::One),
+            "majority" => Some(Quorum::Majority),
+            "all" => Some(Quorum::All),
+            n => n.parse().ok().and_then(NonZeroUsize::new).map(Quorum::N),
+        },
+        Some(JValue::Number(n)) => n
+            .as_u64()
+            .and_then(|n| NonZeroUsize::new(n as usize))
+            .map(Quorum::N),
+        Some(_) => None,
+    };
+
+    quorum.ok_or_else(|| Error(value.unwrap_or_default()).into())
+}
+
+fn parse_peers(peers: Vec<String>) -> Result<Vec<PeerId>, JError> {
+    let peers = peers.iter().map(|p| PeerId::from_str(p));
+    Ok(peers.collect::<Result<_, _>>()?)
 }
 
 fn wrap(r: Result<JValue, JError>) -> Option<IValue> {
@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 
+use aquamarine::{InterpreterApi, InterpreterInfo};
 use host_closure::{closure, Closure};
 
 use libp2p::core::Multiaddr;
@@ -25,7 +26,20 @@ pub struct NodeInfo {
     pub external_addresses: Vec<Multiaddr>,
 }
 
-/// Information about current node
-pub fn identify(info: NodeInfo) -> Closure {
-    closure(move |_| Ok(json!(info.clone())))
+#[derive(Serialize)]
+struct Identity<'a> {
+    #[serde(flatten)]
+    node_info: &'a NodeInfo,
+    air_interpreter: InterpreterInfo,
+}
+
+/// Information about current node, and the interpreter it runs
+pub fn identify(node_info: NodeInfo, interpreter: InterpreterApi) -> Closure {
+    closure(move |_| {
+        let identity = Identity {
+            node_info: &node_info,
+            air_interpreter: interpreter.current(),
+        };
+        Ok(json!(identity))
+    })
 }
@@ -17,10 +17,14 @@
 use crate::dependency::Dependency;
 
 use serde::{Deserialize, Serialize};
+use server_config::ServiceLimits;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blueprint {
     pub name: String,
     pub id: String,
     pub dependencies: Vec<Dependency>,
+    /// Limits for services created from this blueprint, on top of the node-wide ones
+    #[serde(default)]
+    pub limits: ServiceLimits,
 }
@@ -36,6 +36,11 @@ pub enum ModuleError {
         #[source]
         err: toml::ser::Error,
     },
+    #[error("Error serializing blueprint limits to json: {err}")]
+    SerializeLimits {
+        #[source]
+        err: serde_json::Error,
+    },
     #[error("Error saving config to {path:?}: {err}")]
     WriteConfig {
         path: PathBuf,
@@ -83,6 +88,50 @@ pub enum ModuleError {
     },
     #[error("Module with name {0} wasn't found, consider using module hash instead of a name")]
     InvalidModuleName(String),
+    #[error("Error removing module file {path:?}: {err}")]
+    RemoveModule {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Error removing blueprint {path:?}: {err}")]
+    RemoveBlueprint {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error(
+        "Module {hash} is used by blueprints {blueprints:?} and services {services:?}, remove them first"
+    )]
+    ModuleInUse {
+        hash: String,
+        blueprints: Vec<String>,
+        services: Vec<String>,
+    },
+    #[error("Blueprint {id} is used by services {services:?}, remove them first")]
+    BlueprintInUse { id: String, services: Vec<String> },
+    #[error("Invalid module hash {hash}: {err}")]
+    InvalidModuleHash {
+        hash: String,
+        #[source]
+        err: faster_hex::Error,
+    },
+    #[error("Error writing uploaded module to {path:?}: {err}")]
+    WriteUpload {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Upload {0} wasn't found, it is either finished or expired")]
+    NoSuchUpload(String),
+    #[error("Upload {id} has {size} bytes, can't write chunk at offset {offset}")]
+    UploadGap { id: String, offset: u64, size: u64 },
+    #[error("Upload {id} would grow to {size} bytes, modules can't be larger than {max} bytes")]
+    UploadTooBig { id: String, size: u64, max: u64 },
+    #[error("There are {0} uploads in progress already, finish them or wait until they expire")]
+    TooManyUploads(usize),
+    #[error("Module hash {actual} doesn't match expected {expected}")]
+    ModuleHashMismatch { expected: String, actual: String },
     #[error("Expected module reference of format hash:xx got {reference}. Context: calculating blueprint hash")]
     InvalidModuleReference { reference: String },
 }
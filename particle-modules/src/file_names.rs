@@ -29,6 +29,11 @@ pub(super) fn module_file_name(module_hash: &Hash) -> String {
     format!("{}.wasm", module_hash.to_hex().as_ref())
 }
 
+/// Calculates the name of a marker file, present if module was fetched from another peer
+pub(super) fn module_fetched_name(module_hash: &Hash) -> String {
+    format!("{}.fetched", module_hash.to_hex().as_ref())
+}
+
 /// Calculates filename of the blueprint
 pub(super) fn blueprint_file_name(blueprint: &Blueprint) -> String {
     blueprint_fname(blueprint.id.as_str())
@@ -44,7 +49,7 @@ pub(super) fn is_blueprint(name: &str) -> bool {
 }
 
 /// Return file name with .wasm extension stripped. None if extension wasn't .wasm
-pub(super) fn extract_module_file_name(path: &Path) -> Option<&str> {
+pub fn extract_module_file_name(path: &Path) -> Option<&str> {
     // return None if extension isn't "wasm"
     path.extension().filter(|ext| ext == &"wasm")?;
     // strip extension
@@ -55,6 +60,15 @@ pub(super) fn is_module_wasm(path: &Path) -> bool {
     path.extension().map_or(false, |ext| ext == "wasm")
 }
 
+/// Calculates the name of a temporary file for module upload
+pub(super) fn upload_file_name(upload_id: &str) -> String {
+    format!("{}.upload", upload_id)
+}
+
+pub(super) fn is_upload(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "upload")
+}
+
 pub fn service_file_name(service_id: &str) -> String {
     format!("{}_service.toml", service_id)
 }
@@ -17,10 +17,11 @@
 use crate::blueprint::Blueprint;
 use crate::error::{ModuleError::*, Result};
 use crate::file_names;
-use crate::file_names::{module_config_name, module_file_name};
+use crate::file_names::{module_config_name, module_fetched_name, module_file_name};
 use crate::hash::Hash;
 
 use fluence_app_service::{ModuleDescriptor, TomlFaaSNamedModuleConfig};
+use serde::Deserialize;
 
 use std::path::Path;
 use std::{convert::TryInto, path::PathBuf};
@@ -75,11 +76,67 @@ pub fn add_module(
     modules_dir: &Path,
     module_hash: &Hash,
     bytes: &[u8],
-    mut config: TomlFaaSNamedModuleConfig,
+    config: TomlFaaSNamedModuleConfig,
 ) -> Result<TomlFaaSNamedModuleConfig> {
     let wasm = modules_dir.join(module_file_name(module_hash));
     std::fs::write(&wasm, bytes).map_err(|err| AddModule { path: wasm, err })?;
 
+    remove_fetched_marker(modules_dir, module_hash)?;
+    write_config(modules_dir, module_hash, config)
+}
+
+/// Adds a module fetched from another peer, and marks it as fetched,
+/// so it isn't registered by name when modules are loaded on restart
+pub fn add_fetched_module(
+    modules_dir: &Path,
+    module_hash: &Hash,
+    bytes: &[u8],
+    config: TomlFaaSNamedModuleConfig,
+) -> Result<TomlFaaSNamedModuleConfig> {
+    let marker = modules_dir.join(module_fetched_name(module_hash));
+    std::fs::write(&marker, &[]).map_err(|err| AddModule { path: marker, err })?;
+
+    let wasm = modules_dir.join(module_file_name(module_hash));
+    std::fs::write(&wasm, bytes).map_err(|err| AddModule { path: wasm, err })?;
+
+    write_config(modules_dir, module_hash, config)
+}
+
+/// Whether module was fetched from another peer
+pub fn is_fetched_module(modules_dir: &Path, module_hash: &Hash) -> bool {
+    modules_dir.join(module_fetched_name(module_hash)).exists()
+}
+
+fn remove_fetched_marker(modules_dir: &Path, module_hash: &Hash) -> Result<()> {
+    let marker = modules_dir.join(module_fetched_name(module_hash));
+    match std::fs::remove_file(&marker) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(AddModule { path: marker, err })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Moves an uploaded module file to the modules dir, overwriting existing module.
+/// Also adds module config to the TomlFaaSNamedModuleConfig
+pub fn add_module_file(
+    modules_dir: &Path,
+    module_hash: &Hash,
+    file: &Path,
+    config: TomlFaaSNamedModuleConfig,
+) -> Result<TomlFaaSNamedModuleConfig> {
+    let wasm = modules_dir.join(module_file_name(module_hash));
+    std::fs::rename(file, &wasm).map_err(|err| AddModule { path: wasm, err })?;
+
+    remove_fetched_marker(modules_dir, module_hash)?;
+    write_config(modules_dir, module_hash, config)
+}
+
+fn write_config(
+    modules_dir: &Path,
+    module_hash: &Hash,
+    mut config: TomlFaaSNamedModuleConfig,
+) -> Result<TomlFaaSNamedModuleConfig> {
     // replace existing configuration with a new one
     // TODO HACK: use custom structure for API; TomlFaaSNamedModuleConfig is too powerful and clumsy.
     // Set file_name = ${hash}.wasm
@@ -91,6 +148,19 @@ pub fn add_module(
     Ok(config)
 }
 
+/// Load module bytes and its name, so they can be sent to other peers.
+/// The rest of the config isn't loaded: envs, mapped dirs and binaries are specific to this host.
+pub fn load_module_with_name(modules_dir: &Path, module_hash: &str) -> Result<(Vec<u8>, String)> {
+    let hash = Hash::from_hex(module_hash).map_err(|err| InvalidModuleHash {
+        hash: module_hash.to_string(),
+        err,
+    })?;
+    let module = load_module_by_path(&modules_dir.join(module_file_name(&hash)))?;
+    let config = load_config_by_path(&modules_dir.join(module_config_name(&hash)))?;
+
+    Ok((module, config.name))
+}
+
 pub fn load_module_by_path(path: &Path) -> Result<Vec<u8>> {
     std::fs::read(path).map_err(|err| ModuleNotFound {
         path: path.to_path_buf(),
@@ -110,3 +180,86 @@ pub fn add_blueprint(blueprint_dir: &Path, blueprint: &Blueprint) -> Result<()>
 
     Ok(())
 }
+
+/// Load all blueprints from disk, skipping (and logging) malformed ones
+pub fn load_blueprints(blueprint_dir: &Path) -> Vec<Blueprint> {
+    list_files(blueprint_dir)
+        .into_iter()
+        .flatten()
+        .filter(|path| {
+            let fname = path.file_name().and_then(|n| n.to_str());
+            fname.map_or(false, file_names::is_blueprint)
+        })
+        .filter_map(|path| {
+            let blueprint: eyre::Result<Blueprint> = try {
+                let bytes = std::fs::read(&path)?;
+                toml::from_slice(&bytes)?
+            };
+
+            match blueprint {
+                Ok(blueprint) => Some(blueprint),
+                Err(err) => {
+                    log::warn!("error loading blueprint {:?}: {:?}", path, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Load blueprint ids of all persisted services as (service_id, blueprint_id)
+pub fn load_service_blueprints(services_dir: &Path) -> Vec<(String, String)> {
+    // Subset of particle_services::PersistedService
+    #[derive(Deserialize)]
+    struct PersistedService {
+        service_id: String,
+        blueprint_id: String,
+    }
+
+    list_files(services_dir)
+        .into_iter()
+        .flatten()
+        .filter(|path| file_names::is_service(path))
+        .filter_map(|path| {
+            let service: eyre::Result<PersistedService> = try {
+                let bytes = std::fs::read(&path)?;
+                toml::from_slice(&bytes)?
+            };
+
+            match service {
+                Ok(s) => Some((s.service_id, s.blueprint_id)),
+                Err(err) => {
+                    log::warn!("error loading persisted service {:?}: {:?}", path, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Removes module and its config from the filesystem
+pub fn remove_module(modules_dir: &Path, module_hash: &Hash) -> Result<()> {
+    let wasm = modules_dir.join(module_file_name(module_hash));
+    std::fs::remove_file(&wasm).map_err(|err| RemoveModule { path: wasm, err })?;
+
+    for file in &[
+        module_config_name(module_hash),
+        module_fetched_name(module_hash),
+    ] {
+        let path = modules_dir.join(file);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(RemoveModule { path, err })
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes blueprint from the filesystem
+pub fn remove_blueprint(blueprint_dir: &Path, blueprint_id: &str) -> Result<()> {
+    let path = blueprint_dir.join(file_names::blueprint_fname(blueprint_id));
+    std::fs::remove_file(&path).map_err(|err| RemoveBlueprint { path, err })
+}
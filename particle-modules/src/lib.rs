@@ -36,9 +36,12 @@ mod file_names;
 mod files;
 mod hash;
 mod modules;
+mod upload;
 
 pub use blueprint::Blueprint;
+pub use dependency::Dependency;
 pub use error::ModuleError;
-pub use file_names::{is_service, service_file_name};
-pub use files::{list_files, load_blueprint, load_module_descriptor};
-pub use modules::ModuleRepository;
+pub use file_names::{extract_module_file_name, is_service, service_file_name};
+pub use files::{list_files, load_blueprint, load_module_descriptor, load_module_with_name};
+pub use modules::{AddBlueprint, ModuleRepository};
+pub use upload::UPLOAD_TIMEOUT;
@@ -15,43 +15,60 @@
  */
 
 use crate::dependency::Dependency;
-use crate::error::ModuleError::{InvalidModuleName, InvalidModuleReference};
+use crate::error::ModuleError::{
+    BlueprintInUse, InvalidModuleHash, InvalidModuleName, InvalidModuleReference,
+    ModuleHashMismatch, ModuleInUse, SerializeLimits,
+};
 use crate::error::Result;
 use crate::file_names::{extract_module_file_name, is_module_wasm};
 use crate::file_names::{module_config_name, module_file_name};
 use crate::files::{load_config_by_path, load_module_by_path};
 use crate::hash::Hash;
-use crate::{file_names, files, load_blueprint, load_module_descriptor, Blueprint};
+use crate::upload::Uploads;
+use crate::{files, load_blueprint, load_module_descriptor, Blueprint};
 
 use fce_wit_parser::module_interface;
-use fluence_app_service::ModuleDescriptor;
-use host_closure::{closure, Args, Closure};
+use fluence_app_service::{ModuleDescriptor, TomlFaaSNamedModuleConfig};
+use host_closure::{closure, closure_params, Args, Closure, ParticleClosure};
 
 use eyre::WrapErr;
 use fstrings::f;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JValue};
-use std::{collections::HashMap, path::Path, path::PathBuf, sync::Arc};
+use server_config::ServiceLimits;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use std::{path::Path, path::PathBuf, sync::Arc};
 
 type ModuleName = String;
 
+/// Modules added less than that ago are never collected as garbage. Fetched modules aren't
+/// referenced by name, so a blueprint that depends on them must have time to be added.
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AddBlueprint {
     pub name: String,
     pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub limits: ServiceLimits,
 }
 
 #[derive(Clone)]
 pub struct ModuleRepository {
     modules_dir: PathBuf,
     blueprints_dir: PathBuf,
+    /// Persisted services are checked before a blueprint is removed
+    services_dir: PathBuf,
     /// Map of module_config.name to blake3::hash(module bytes)
     modules_by_name: Arc<Mutex<HashMap<ModuleName, Hash>>>,
+    /// Modules being uploaded in chunks
+    uploads: Uploads,
 }
 
 impl ModuleRepository {
-    pub fn new(modules_dir: &Path, blueprints_dir: &Path) -> Self {
+    pub fn new(modules_dir: &Path, blueprints_dir: &Path, services_dir: &Path) -> Self {
         let modules_by_name: HashMap<_, _> = files::list_files(&modules_dir)
             .into_iter()
             .flatten()
@@ -63,12 +80,17 @@ impl ModuleRepository {
 
                     Self::maybe_migrate_module(&path, &hash, &modules_dir);
 
-                    let module = load_module_descriptor(&modules_dir, &hash)?;
-                    (module.import_name, hash)
+                    // fetched modules are referenced only by hash
+                    if files::is_fetched_module(&modules_dir, &hash) {
+                        None
+                    } else {
+                        let module = load_module_descriptor(&modules_dir, &hash)?;
+                        Some((module.import_name, hash))
+                    }
                 };
 
                 match name_hash {
-                    Ok(name_hash) => Some(name_hash),
+                    Ok(name_hash) => name_hash,
                     Err(err) => {
                         log::warn!("Error loading module list: {:?}", err);
                         None
@@ -79,10 +101,17 @@ impl ModuleRepository {
 
         let modules_by_name = Arc::new(Mutex::new(modules_by_name));
 
+        // uploads don't survive restart
+        let uploads = Uploads::default();
+        uploads.remove_orphaned(modules_dir);
+        uploads.expire_periodically();
+
         Self {
             modules_by_name,
+            uploads,
             modules_dir: modules_dir.to_path_buf(),
             blueprints_dir: blueprints_dir.to_path_buf(),
+            services_dir: services_dir.to_path_buf(),
         }
     }
 
@@ -129,33 +158,133 @@ impl ModuleRepository {
         })
     }
 
+    /// Starts a chunked upload of a module with a given hash, returns upload id.
+    /// Only the peer that started the upload can send its chunks and finish it
+    pub fn upload_start(&self) -> ParticleClosure {
+        let this = self.clone();
+        closure_params(move |particle, args| {
+            let mut args = args.function_args.into_iter();
+            let hash: String = Args::next("hash", &mut args)?;
+            let hash = Hash::from_hex(&hash).map_err(|err| InvalidModuleHash { hash, err })?;
+            let config = Args::next("config", &mut args)?;
+            let initiator = &particle.init_user_id;
+            let id = this
+                .uploads
+                .start(&this.modules_dir, initiator, hash, config)?;
+
+            Ok(JValue::String(id))
+        })
+    }
+
+    /// Writes base64-encoded chunk at offset, returns number of bytes uploaded so far
+    pub fn upload_chunk(&self) -> ParticleClosure {
+        let uploads = self.uploads.clone();
+        closure_params(move |particle, args| {
+            let mut args = args.function_args.into_iter();
+            let upload_id: String = Args::next("upload_id", &mut args)?;
+            let offset: u64 = Args::next("offset", &mut args)?;
+            let chunk: String = Args::next("chunk", &mut args)?;
+            let chunk = base64::decode(&chunk).map_err(|err| {
+                JValue::String(format!("error decoding chunk from base64: {:?}", err))
+            })?;
+            let initiator = &particle.init_user_id;
+            let size = uploads.chunk(&upload_id, initiator, offset, &chunk)?;
+
+            Ok(json!(size))
+        })
+    }
+
+    /// Checks hash of the uploaded module and adds it to the filesystem, returns module hash
+    pub fn upload_finish(&self) -> ParticleClosure {
+        let this = self.clone();
+        closure_params(move |particle, args| {
+            let mut args = args.function_args.into_iter();
+            let upload_id: String = Args::next("upload_id", &mut args)?;
+            let initiator = &particle.init_user_id;
+            let (hash, file, config) = this.uploads.finish(&upload_id, initiator)?;
+            let config = files::add_module_file(&this.modules_dir, &hash, &file, config)?;
+
+            let hash_str = hash.to_hex().as_ref().to_owned();
+            this.modules_by_name.lock().insert(config.name, hash);
+
+            Ok(JValue::String(hash_str))
+        })
+    }
+
+    /// Adds a module fetched from another peer, checking its bytes match the requested hash.
+    /// Module is saved with the default config: peers send only the module name, so a module
+    /// from an untrusted peer gets no mounted binaries or preopened dirs on this host.
+    /// Fetched module isn't registered by name, so it can't replace a local module of that name,
+    /// neither now nor after restart. A module that is already present is kept as is.
+    pub fn add_fetched_module(&self, hash: &str, module: &[u8], name: &str) -> Result<()> {
+        let actual = Hash::hash(module);
+        if actual.to_hex().as_ref() != hash {
+            return Err(ModuleHashMismatch {
+                expected: hash.to_string(),
+                actual: actual.to_hex().as_ref().to_string(),
+            });
+        }
+        let config = TomlFaaSNamedModuleConfig {
+            name: name.to_string(),
+            file_name: None,
+            config: <_>::default(),
+        };
+        if self.modules_dir.join(module_file_name(&actual)).exists() {
+            return Ok(());
+        }
+        files::add_fetched_module(&self.modules_dir, &actual, module, config)?;
+
+        Ok(())
+    }
+
+    /// Hashes of modules that blueprint references by hash, but that are missing on disk
+    pub fn missing_modules(&self, blueprint: &AddBlueprint) -> Vec<String> {
+        blueprint
+            .dependencies
+            .iter()
+            .filter_map(|d| match d {
+                Dependency::Hash(hash) => Some(hash),
+                Dependency::Name(_) => None,
+            })
+            .filter(|hash| !self.modules_dir.join(module_file_name(hash)).exists())
+            .map(|hash| hash.to_hex().as_ref().to_string())
+            .collect()
+    }
+
     /// Saves new blueprint to disk
     pub fn add_blueprint(&self) -> Closure {
-        use Dependency::Hash;
-
-        let blueprints_dir = self.blueprints_dir.clone();
-        let modules = self.modules_by_name.clone();
+        let this = self.clone();
         closure(move |mut args| {
             let blueprint: AddBlueprint = Args::next("blueprint_request", &mut args)?;
-            // resolve dependencies by name to hashes, if any
-            let dependencies = blueprint.dependencies.into_iter();
-            let dependencies: Vec<Dependency> = dependencies
-                .map(|module| Ok(Hash(resolve_hash(&modules, module)?)))
-                .collect::<Result<_>>()?;
-
-            let hash = hash_dependencies(dependencies.clone())?.to_hex();
-
-            let blueprint = Blueprint {
-                id: hash.as_ref().to_string(),
-                dependencies,
-                name: blueprint.name,
-            };
-            files::add_blueprint(&blueprints_dir, &blueprint)?;
+            let blueprint_id = this.save_blueprint(blueprint)?;
 
-            Ok(JValue::String(blueprint.id))
+            Ok(JValue::String(blueprint_id))
         })
     }
 
+    /// Saves new blueprint to disk, returns its id
+    pub fn save_blueprint(&self, blueprint: AddBlueprint) -> Result<String> {
+        use Dependency::Hash;
+
+        // resolve dependencies by name to hashes, if any
+        let dependencies = blueprint.dependencies.into_iter();
+        let dependencies: Vec<Dependency> = dependencies
+            .map(|module| Ok(Hash(resolve_hash(&self.modules_by_name, module)?)))
+            .collect::<Result<_>>()?;
+
+        let hash = hash_blueprint(dependencies.clone(), &blueprint.limits)?.to_hex();
+
+        let blueprint = Blueprint {
+            id: hash.as_ref().to_string(),
+            dependencies,
+            name: blueprint.name,
+            limits: blueprint.limits,
+        };
+        files::add_blueprint(&self.blueprints_dir, &blueprint)?;
+
+        Ok(blueprint.id)
+    }
+
     pub fn list_modules(&self) -> Closure {
         let modules_dir = self.modules_dir.clone();
         closure(move |_| {
@@ -223,40 +352,160 @@ impl ModuleRepository {
         let blueprints_dir = self.blueprints_dir.clone();
 
         closure(move |_| {
-            Ok(JValue::Array(
-                files::list_files(&blueprints_dir)
-                    .into_iter()
-                    .flatten()
-                    .filter_map(|path| {
-                        // Check if file name matches blueprint schema
-                        let fname = path.file_name()?.to_str()?;
-                        if !file_names::is_blueprint(fname) {
-                            return None;
-                        }
-
-                        let blueprint: eyre::Result<_> = try {
-                            // Read & deserialize TOML
-                            let bytes = std::fs::read(&path)?;
-                            let blueprint: Blueprint = toml::from_slice(&bytes)?;
-
-                            // Convert to json
-                            serde_json::to_value(blueprint)?
-                        };
-
-                        match blueprint {
-                            Ok(blueprint) => Some(blueprint),
-                            Err(err) => {
-                                log::warn!("get_blueprints error on file {}: {:?}", fname, err);
-                                None
-                            }
-                        }
-                    })
-                    .collect(),
-            ))
+            let blueprints = files::load_blueprints(&blueprints_dir);
+            Ok(json!(blueprints))
         })
     }
 
-    pub fn resolve_blueprint(&self, blueprint_id: &str) -> Result<Vec<ModuleDescriptor>> {
+    /// Removes module files. Fails if module is used by any blueprint or persisted service,
+    /// unless `force` is true.
+    pub fn remove_module(&self, hash: &str, force: bool) -> Result<()> {
+        let hash = Hash::from_hex(hash).map_err(|err| InvalidModuleHash {
+            hash: hash.to_string(),
+            err,
+        })?;
+        let hex = hash.to_hex().as_ref().to_string();
+
+        let blueprints: Vec<_> = files::load_blueprints(&self.blueprints_dir)
+            .into_iter()
+            .filter(|bp| self.dependency_hashes(bp).contains(&hex))
+            .map(|bp| bp.id)
+            .collect();
+        let services: Vec<_> = self
+            .service_dependency_hashes()
+            .into_iter()
+            .filter(|(_, hashes)| hashes.contains(&hex))
+            .map(|(service_id, _)| service_id)
+            .collect();
+        if !blueprints.is_empty() || !services.is_empty() {
+            if !force {
+                return Err(ModuleInUse {
+                    hash: hex,
+                    blueprints,
+                    services,
+                });
+            }
+            log::warn!(
+                "force removing module {} used by blueprints {:?} and services {:?}",
+                hex,
+                blueprints,
+                services
+            );
+        }
+
+        files::remove_module(&self.modules_dir, &hash)?;
+        self.modules_by_name
+            .lock()
+            .retain(|_, h| h.to_hex().as_ref() != hex);
+
+        Ok(())
+    }
+
+    /// Removes blueprint. Fails if blueprint is used by any persisted service, unless `force` is true.
+    pub fn remove_blueprint(&self, blueprint_id: &str, force: bool) -> Result<()> {
+        // make sure blueprint exists and id is a well-formed one
+        let blueprint = load_blueprint(&self.blueprints_dir, blueprint_id)?;
+
+        let services: Vec<_> = files::load_service_blueprints(&self.services_dir)
+            .into_iter()
+            .filter(|(_, bp)| bp == &blueprint.id)
+            .map(|(service_id, _)| service_id)
+            .collect();
+        if !services.is_empty() {
+            if !force {
+                return Err(BlueprintInUse {
+                    id: blueprint.id,
+                    services,
+                });
+            }
+            log::warn!(
+                "force removing blueprint {} used by {:?}",
+                blueprint.id,
+                services
+            );
+        }
+
+        files::remove_blueprint(&self.blueprints_dir, &blueprint.id)
+    }
+
+    /// Removes module files that can't be referenced anymore: modules that aren't used by any
+    /// blueprint or persisted service, aren't the latest module added under their name, and
+    /// were added, uploaded or fetched more than `GC_GRACE_PERIOD` ago.
+    /// Returns hashes of removed modules.
+    pub fn collect_garbage(&self) -> Result<Vec<String>> {
+        self.collect_garbage_older_than(GC_GRACE_PERIOD)
+    }
+
+    fn collect_garbage_older_than(&self, grace_period: Duration) -> Result<Vec<String>> {
+        self.uploads.remove_expired();
+        self.uploads.remove_orphaned(&self.modules_dir);
+
+        let mut referenced: HashSet<String> = files::load_blueprints(&self.blueprints_dir)
+            .iter()
+            .flat_map(|bp| self.dependency_hashes(bp))
+            .collect();
+        referenced.extend(
+            self.service_dependency_hashes()
+                .into_iter()
+                .flat_map(|(_, hashes)| hashes),
+        );
+        referenced.extend(
+            self.modules_by_name
+                .lock()
+                .values()
+                .map(|h| h.to_hex().as_ref().to_string()),
+        );
+
+        let garbage: Vec<_> = files::list_files(&self.modules_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|path| {
+                let name = extract_module_file_name(&path)?;
+                let hash = Hash::from_hex(name).ok()?;
+                Some(hash)
+                    .filter(|h| !referenced.contains(h.to_hex().as_ref()))
+                    .filter(|_| !modified_within(&path, grace_period))
+            })
+            .collect();
+
+        garbage
+            .into_iter()
+            .map(|hash| {
+                files::remove_module(&self.modules_dir, &hash)?;
+                let hex = hash.to_hex().as_ref().to_string();
+                log::info!("removed unreferenced module {}", hex);
+                Ok(hex)
+            })
+            .collect()
+    }
+
+    /// Hex hashes of all modules blueprint depends on; dependencies that can't be resolved are skipped
+    fn dependency_hashes(&self, blueprint: &Blueprint) -> HashSet<String> {
+        blueprint
+            .dependencies
+            .iter()
+            .filter_map(|d| resolve_hash(&self.modules_by_name, d.clone()).ok())
+            .map(|h| h.to_hex().as_ref().to_string())
+            .collect()
+    }
+
+    /// Hex hashes of modules used by each persisted service, as (service_id, hashes).
+    /// Services whose blueprint was force removed are skipped, their modules can't be resolved
+    fn service_dependency_hashes(&self) -> Vec<(String, HashSet<String>)> {
+        files::load_service_blueprints(&self.services_dir)
+            .into_iter()
+            .filter_map(|(service_id, blueprint_id)| {
+                let blueprint = load_blueprint(&self.blueprints_dir, &blueprint_id).ok()?;
+                Some((service_id, self.dependency_hashes(&blueprint)))
+            })
+            .collect()
+    }
+
+    /// Returns descriptors of blueprint modules, along with limits set by the blueprint
+    pub fn resolve_blueprint(
+        &self,
+        blueprint_id: &str,
+    ) -> Result<(Vec<ModuleDescriptor>, ServiceLimits)> {
         let blueprint = load_blueprint(&self.blueprints_dir, blueprint_id)?;
 
         // Load all module descriptors
@@ -270,7 +519,7 @@ impl ModuleRepository {
             })
             .collect::<Result<_>>()?;
 
-        Ok(module_descriptors)
+        Ok((module_descriptors, blueprint.limits))
     }
 }
 
@@ -289,7 +538,10 @@ fn resolve_hash(
     }
 }
 
-fn hash_dependencies(deps: Vec<Dependency>) -> Result<Hash> {
+/// Limits are a part of the blueprint id, so re-adding the same dependencies with different limits
+/// makes a new blueprint instead of changing limits of services created from the existing one.
+/// Blueprints without limits keep ids they had before limits were introduced.
+fn hash_blueprint(deps: Vec<Dependency>, limits: &ServiceLimits) -> Result<Hash> {
     let mut hasher = blake3::Hasher::new();
     for d in deps.iter() {
         match d {
@@ -303,28 +555,53 @@ fn hash_dependencies(deps: Vec<Dependency>) -> Result<Hash> {
             }
         }
     }
+    if *limits != ServiceLimits::default() {
+        let limits = serde_json::to_vec(limits).map_err(|err| SerializeLimits { err })?;
+        hasher.update(&limits);
+    }
 
     let hash = hasher.finalize();
     let bytes = hash.as_bytes();
     Ok(Hash::from(*bytes))
 }
 
+/// Whether file at `path` was modified less than `period` ago.
+/// File that can't be checked is considered modified, so it isn't removed by mistake
+fn modified_within(path: &Path, period: Duration) -> bool {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified());
+    match modified.map(|m| m.elapsed()) {
+        Ok(Ok(elapsed)) => elapsed < period,
+        // modified in the future
+        Ok(Err(_)) => true,
+        Err(err) => {
+            log::warn!("Unable to check modification time of {:?}: {}", path, err);
+            true
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::dependency::Dependency;
+    use crate::error::ModuleError::ModuleInUse;
+    use crate::file_names::{module_config_name, module_file_name};
+    use crate::files::{self, load_config_by_path};
     use crate::hash::Hash;
     use crate::modules::AddBlueprint;
     use crate::ModuleRepository;
     use host_closure::Args;
     use serde::{Deserialize, Serialize};
     use serde_json::Value as JValue;
+    use server_config::ServiceLimits;
+    use std::time::Duration;
     use tempdir::TempDir;
-    use test_utils::{response_to_return, RetStruct};
+    use test_utils::{module_config, response_to_return, RetStruct};
 
     fn add_bp(repo: &ModuleRepository, name: String, deps: Vec<Dependency>) -> RetStruct {
         let req1 = AddBlueprint {
             name,
             dependencies: deps,
+            limits: <_>::default(),
         };
 
         let v: JValue = serde_json::to_value(req1).unwrap();
@@ -363,7 +640,7 @@ mod tests {
     fn test_add_blueprint() {
         let module_dir = TempDir::new("test").unwrap();
         let bp_dir = TempDir::new("test").unwrap();
-        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path());
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), bp_dir.path());
 
         let dep1 = Dependency::Hash(Hash::hash(&[1, 2, 3]));
         let dep2 = Dependency::Hash(Hash::hash(&[3, 2, 1]));
@@ -387,4 +664,142 @@ mod tests {
         assert_eq!(resp1.result, resp2.result);
         assert_eq!(bp1.id, bp2.id);
     }
+
+    #[test]
+    fn limits_are_part_of_blueprint_id() {
+        let module_dir = TempDir::new("test").unwrap();
+        let bp_dir = TempDir::new("test").unwrap();
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), bp_dir.path());
+
+        let dependencies = vec![Dependency::Hash(Hash::hash(&[1, 2, 3]))];
+        let blueprint = |max_memory_pages| AddBlueprint {
+            name: "bp".to_string(),
+            dependencies: dependencies.clone(),
+            limits: ServiceLimits {
+                max_memory_pages,
+                ..<_>::default()
+            },
+        };
+
+        let unlimited = repo.save_blueprint(blueprint(None)).unwrap();
+        let limited = repo.save_blueprint(blueprint(Some(10))).unwrap();
+        assert_ne!(unlimited, limited);
+        assert_eq!(repo.save_blueprint(blueprint(Some(10))).unwrap(), limited);
+        assert_eq!(files::load_blueprints(bp_dir.path()).len(), 2);
+    }
+
+    fn add_module(repo: &ModuleRepository, name: &str, bytes: &[u8]) -> String {
+        let args = Args {
+            service_id: "".to_string(),
+            function_name: "".to_string(),
+            function_args: vec![JValue::String(base64::encode(bytes)), module_config(name)],
+            tetraplets: vec![],
+        };
+
+        let resp = response_to_return(repo.add_module()(args).unwrap());
+        serde_json::from_str(&resp.result).unwrap()
+    }
+
+    #[test]
+    fn remove_module_and_blueprint() {
+        let module_dir = TempDir::new("test").unwrap();
+        let bp_dir = TempDir::new("test").unwrap();
+        let services_dir = TempDir::new("test").unwrap();
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), services_dir.path());
+
+        let old = add_module(&repo, "module", &[1, 2, 3]);
+        let used = add_module(&repo, "module", &[3, 2, 1]);
+        let unused = add_module(&repo, "other", &[1, 1, 1]);
+        let dep = Dependency::Name("module".to_string());
+        let bp = add_bp(&repo, "bp".to_string(), vec![dep]);
+        let bp_id: String = serde_json::from_str(&bp.result).unwrap();
+
+        // blueprint is used by a persisted service
+        let service = format!("service_id = \"srv\"\nblueprint_id = \"{}\"\n", bp_id);
+        std::fs::write(services_dir.path().join("srv_service.toml"), service).unwrap();
+
+        match repo.remove_module(&used, false) {
+            Err(ModuleInUse {
+                blueprints,
+                services,
+                ..
+            }) => {
+                assert_eq!(blueprints, vec![bp_id.clone()]);
+                assert_eq!(services, vec!["srv".to_string()]);
+            }
+            r => panic!("expected module to be in use, got {:?}", r),
+        }
+        assert!(repo.remove_blueprint(&bp_id, false).is_err());
+
+        // old module was just added, so it isn't collected yet
+        assert!(repo.collect_garbage().unwrap().is_empty());
+        // old module was overwritten by name, so it is collected once grace period is over
+        let collected = repo.collect_garbage_older_than(Duration::from_secs(0));
+        assert_eq!(collected.unwrap(), vec![old]);
+
+        repo.remove_module(&unused, false).unwrap();
+        repo.remove_blueprint(&bp_id, true).unwrap();
+        repo.remove_module(&used, false).unwrap();
+        assert!(std::fs::read_dir(module_dir.path())
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    fn hex(hash: &Hash) -> String {
+        hash.to_hex().as_ref().to_string()
+    }
+
+    #[test]
+    fn fetched_module_has_default_config() {
+        let module_dir = TempDir::new("test").unwrap();
+        let bp_dir = TempDir::new("test").unwrap();
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), bp_dir.path());
+
+        add_module(&repo, "module", &[1, 2, 3]);
+        let bytes = [3, 2, 1];
+        let hash = Hash::hash(&bytes);
+        assert!(repo
+            .add_fetched_module(&hex(&hash), &[1, 1, 1], "module")
+            .is_err());
+        repo.add_fetched_module(&hex(&hash), &bytes, "module")
+            .unwrap();
+
+        let path = module_dir.path().join(module_config_name(&hash));
+        let config = load_config_by_path(&path).unwrap();
+        assert_eq!(config.name, "module");
+        assert!(config.config.mounted_binaries.is_none());
+        // local module is still resolved by name
+        let by_name = repo.modules_by_name.lock().get("module").map(hex);
+        assert_eq!(by_name, Some(hex(&Hash::hash(&[1, 2, 3]))));
+        // fetched module isn't referenced by anything, but it's kept until grace period is over
+        assert!(repo.collect_garbage().unwrap().is_empty());
+        assert!(module_dir.path().join(module_file_name(&hash)).exists());
+    }
+
+    #[test]
+    fn fetched_module_isnt_resolved_by_name_after_restart() {
+        let module_dir = TempDir::new("test").unwrap();
+        let bp_dir = TempDir::new("test").unwrap();
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), bp_dir.path());
+
+        let local = add_module(&repo, "module", &[1, 2, 3]);
+        let bytes = [3, 2, 1];
+        let fetched = hex(&Hash::hash(&bytes));
+        repo.add_fetched_module(&fetched, &bytes, "module").unwrap();
+        // fetching a module that is present locally doesn't change its config
+        repo.add_fetched_module(&local, &[1, 2, 3], "remote")
+            .unwrap();
+
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), bp_dir.path());
+        let by_name = repo.modules_by_name.lock().clone();
+        assert_eq!(by_name.len(), 1);
+        assert_eq!(by_name.get("module").map(hex), Some(local));
+
+        // once added locally, module is resolved by name
+        add_module(&repo, "fetched", &bytes);
+        let repo = ModuleRepository::new(module_dir.path(), bp_dir.path(), bp_dir.path());
+        let by_name = repo.modules_by_name.lock().clone();
+        assert_eq!(by_name.get("fetched").map(hex), Some(fetched));
+    }
 }
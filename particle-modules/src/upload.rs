@@ -0,0 +1,400 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Modules too big to fit into a single particle are uploaded in chunks.
+//! Chunks are written to a temporary file in modules dir, which becomes a module
+//! once the whole file is uploaded and its hash is checked.
+
+use crate::error::{ModuleError::*, Result};
+use crate::file_names::{is_upload, upload_file_name};
+use crate::files::list_files;
+use crate::hash::Hash;
+
+use fluence_app_service::TomlFaaSNamedModuleConfig;
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Upload is dropped along with uploaded data if no chunks were received for that long
+pub const UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How often expired uploads are looked for
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+/// Max size of an uploaded module, same as for modules fetched from other peers
+pub const MAX_MODULE_SIZE: u64 = 100 * 1024 * 1024;
+/// Max number of uploads in progress, so abandoned ones can't fill the disk before they expire
+pub const MAX_UPLOADS: usize = 16;
+/// Max number of uploads in progress started by a single peer, so one peer can't take them all
+pub const MAX_UPLOADS_PER_PEER: usize = 4;
+
+struct Upload {
+    /// Expected hash of the whole module
+    hash: Hash,
+    config: TomlFaaSNamedModuleConfig,
+    file: PathBuf,
+    /// Number of bytes uploaded so far
+    size: u64,
+    last_active: Instant,
+    /// Set when upload is finished or expired, so a late chunk isn't written to its file
+    closed: bool,
+}
+
+/// Each upload has its own lock, so file I/O of one upload doesn't block the others
+#[derive(Clone)]
+struct SharedUpload {
+    /// Peer that started the upload, the only one allowed to continue it
+    initiator: String,
+    upload: Arc<Mutex<Upload>>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Uploads(Arc<Mutex<HashMap<String, SharedUpload>>>);
+
+impl Uploads {
+    /// Starts a new upload on behalf of `initiator`, returns its id
+    pub fn start(
+        &self,
+        modules_dir: &Path,
+        initiator: &str,
+        hash: Hash,
+        config: TomlFaaSNamedModuleConfig,
+    ) -> Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let file = modules_dir.join(upload_file_name(&id));
+        let upload = Upload {
+            hash,
+            config,
+            file: file.clone(),
+            size: 0,
+            last_active: Instant::now(),
+            closed: false,
+        };
+
+        {
+            let mut uploads = self.0.lock();
+            if uploads.len() >= MAX_UPLOADS {
+                return Err(TooManyUploads(MAX_UPLOADS));
+            }
+            let started = uploads.values().filter(|u| u.initiator == initiator);
+            if started.count() >= MAX_UPLOADS_PER_PEER {
+                return Err(TooManyUploads(MAX_UPLOADS_PER_PEER));
+            }
+            // upload is registered before its file is created, so the file is never orphaned
+            let upload = SharedUpload {
+                initiator: initiator.to_string(),
+                upload: Arc::new(Mutex::new(upload)),
+            };
+            uploads.insert(id.clone(), upload);
+        }
+
+        if let Err(err) = File::create(&file) {
+            self.0.lock().remove(&id);
+            return Err(WriteUpload { path: file, err });
+        }
+
+        Ok(id)
+    }
+
+    /// Writes chunk at `offset`. Offset may be less than the uploaded size, so a lost chunk
+    /// can be sent again, but it can't leave a gap. Returns number of bytes uploaded so far.
+    pub fn chunk(&self, id: &str, initiator: &str, offset: u64, chunk: &[u8]) -> Result<u64> {
+        let upload = self.get(id, initiator)?;
+        let mut upload = upload.lock();
+        if upload.closed {
+            return Err(NoSuchUpload(id.to_string()));
+        }
+        if offset > upload.size {
+            return Err(UploadGap {
+                id: id.to_string(),
+                offset,
+                size: upload.size,
+            });
+        }
+        let size = offset + chunk.len() as u64;
+        if size > MAX_MODULE_SIZE {
+            return Err(UploadTooBig {
+                id: id.to_string(),
+                size,
+                max: MAX_MODULE_SIZE,
+            });
+        }
+
+        let written: std::io::Result<_> = try {
+            let mut file = OpenOptions::new().write(true).open(&upload.file)?;
+            file.set_len(offset)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(chunk)?;
+        };
+        written.map_err(|err| WriteUpload {
+            path: upload.file.clone(),
+            err,
+        })?;
+
+        upload.size = size;
+        upload.last_active = Instant::now();
+
+        Ok(upload.size)
+    }
+
+    /// Ends upload, checking that uploaded file has the expected hash.
+    /// Returns the hash, the file and config of the uploaded module.
+    pub fn finish(
+        &self,
+        id: &str,
+        initiator: &str,
+    ) -> Result<(Hash, PathBuf, TomlFaaSNamedModuleConfig)> {
+        let upload = {
+            let mut uploads = self.0.lock();
+            match uploads.get(id) {
+                Some(upload) if upload.initiator == initiator => uploads.remove(id),
+                _ => None,
+            }
+        };
+        let upload = upload.ok_or_else(|| NoSuchUpload(id.to_string()))?.upload;
+        // waits for a chunk that's being written
+        let mut upload = upload.lock();
+        upload.closed = true;
+
+        let actual = hash_file(&upload.file);
+        let expected = upload.hash.to_hex().as_ref().to_string();
+        match actual {
+            Ok(actual) if actual.to_hex().as_ref() == expected => Ok((
+                upload.hash.clone(),
+                upload.file.clone(),
+                upload.config.clone(),
+            )),
+            Ok(actual) => {
+                remove_file(&upload.file);
+                Err(ModuleHashMismatch {
+                    expected,
+                    actual: actual.to_hex().as_ref().to_string(),
+                })
+            }
+            Err(err) => {
+                remove_file(&upload.file);
+                Err(err)
+            }
+        }
+    }
+
+    /// Drops uploads that weren't active for `UPLOAD_TIMEOUT`, and removes their files
+    pub fn remove_expired(&self) {
+        let mut expired = vec![];
+        self.0.lock().retain(|id, upload| {
+            // upload is active if a chunk is being written to it
+            let is_expired = upload
+                .upload
+                .try_lock()
+                .map_or(false, |u| u.last_active.elapsed() > UPLOAD_TIMEOUT);
+            if is_expired {
+                expired.push((id.clone(), upload.upload.clone()));
+            }
+            !is_expired
+        });
+
+        for (id, upload) in expired {
+            let mut upload = upload.lock();
+            upload.closed = true;
+            log::info!("Upload {} expired, removing {:?}", id, upload.file);
+            remove_file(&upload.file);
+        }
+    }
+
+    /// Removes expired uploads every `EXPIRE_INTERVAL`, until all clones of `self` are dropped
+    pub fn expire_periodically(&self) {
+        let uploads = Arc::downgrade(&self.0);
+        let spawned = std::thread::Builder::new()
+            .name("upload-expiry".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(EXPIRE_INTERVAL);
+                match uploads.upgrade() {
+                    Some(uploads) => Uploads(uploads).remove_expired(),
+                    None => break,
+                }
+            });
+        if let Err(err) = spawned {
+            log::error!("Unable to start expiry of uploads: {}", err);
+        }
+    }
+
+    /// Removes upload files that don't belong to any upload, e.g. left after restart
+    pub fn remove_orphaned(&self, modules_dir: &Path) {
+        // files are listed before uploads are, so a file of a new upload isn't taken for orphan
+        let files: Vec<_> = list_files(modules_dir)
+            .into_iter()
+            .flatten()
+            .filter(|path| is_upload(path))
+            .collect();
+        let active: HashSet<_> = self
+            .0
+            .lock()
+            .keys()
+            .map(|id| modules_dir.join(upload_file_name(id)))
+            .collect();
+
+        files
+            .into_iter()
+            .filter(|path| !active.contains(path))
+            .for_each(|path| remove_file(&path));
+    }
+
+    /// Returns upload, if it was started by `initiator`.
+    /// Others' uploads are reported as missing, so their ids can't be probed
+    fn get(&self, id: &str, initiator: &str) -> Result<Arc<Mutex<Upload>>> {
+        let upload = self.0.lock().get(id).cloned();
+        upload
+            .filter(|u| u.initiator == initiator)
+            .map(|u| u.upload)
+            .ok_or_else(|| NoSuchUpload(id.to_string()))
+    }
+}
+
+fn hash_file(path: &Path) -> Result<Hash> {
+    let hashed: std::io::Result<_> = try {
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut File::open(path)?, &mut hasher)?;
+        Hash::from(*hasher.finalize().as_bytes())
+    };
+
+    hashed.map_err(|err| WriteUpload {
+        path: path.to_path_buf(),
+        err,
+    })
+}
+
+fn remove_file(path: &Path) {
+    if let Err(err) = std::fs::remove_file(path) {
+        log::warn!("Unable to remove upload file {:?}: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Uploads, MAX_MODULE_SIZE, MAX_UPLOADS, MAX_UPLOADS_PER_PEER, UPLOAD_TIMEOUT};
+    use crate::hash::Hash;
+
+    use fluence_app_service::TomlFaaSNamedModuleConfig;
+    use std::time::{Duration, Instant};
+    use tempdir::TempDir;
+
+    const PEER: &str = "peer";
+
+    fn config() -> TomlFaaSNamedModuleConfig {
+        toml::from_str("name = \"module\"").unwrap()
+    }
+
+    #[test]
+    fn upload_in_chunks() {
+        let dir = TempDir::new("test").unwrap();
+        let uploads = Uploads::default();
+        let module = b"some module bytes";
+
+        let id = uploads
+            .start(dir.path(), PEER, Hash::hash(module), config())
+            .unwrap();
+        assert_eq!(uploads.chunk(&id, PEER, 0, &module[..4]).unwrap(), 4);
+        // gaps aren't allowed
+        assert!(uploads.chunk(&id, PEER, 10, &module[10..]).is_err());
+        // resend of the last chunk overwrites it
+        assert_eq!(uploads.chunk(&id, PEER, 0, &module[..8]).unwrap(), 8);
+        let size = uploads.chunk(&id, PEER, 8, &module[8..]).unwrap();
+        assert_eq!(size, module.len() as u64);
+
+        let (hash, file, _) = uploads.finish(&id, PEER).unwrap();
+        assert_eq!(hash.to_hex().as_ref(), Hash::hash(module).to_hex().as_ref());
+        assert_eq!(std::fs::read(file).unwrap(), module);
+        assert!(uploads.finish(&id, PEER).is_err());
+
+        // hash mismatch removes uploaded data
+        let id = uploads
+            .start(dir.path(), PEER, Hash::hash(module), config())
+            .unwrap();
+        uploads.chunk(&id, PEER, 0, b"other bytes").unwrap();
+        assert!(uploads.finish(&id, PEER).is_err());
+
+        uploads
+            .start(dir.path(), PEER, Hash::hash(module), config())
+            .unwrap();
+        uploads.remove_orphaned(dir.path());
+        // finished upload wasn't moved to a module, so only the active upload is left
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn uploads_are_limited() {
+        let dir = TempDir::new("test").unwrap();
+        let uploads = Uploads::default();
+        let hash = Hash::hash(b"module");
+
+        let id = uploads
+            .start(dir.path(), PEER, hash.clone(), config())
+            .unwrap();
+        let too_big = vec![0; MAX_MODULE_SIZE as usize + 1];
+        assert!(uploads.chunk(&id, PEER, 0, &too_big).is_err());
+        assert_eq!(uploads.chunk(&id, PEER, 0, b"module").unwrap(), 6);
+
+        for _ in 1..MAX_UPLOADS_PER_PEER {
+            uploads
+                .start(dir.path(), PEER, hash.clone(), config())
+                .unwrap();
+        }
+        assert!(uploads
+            .start(dir.path(), PEER, hash.clone(), config())
+            .is_err());
+
+        // other peers can't continue or finish the upload
+        assert!(uploads.chunk(&id, "other", 0, b"module").is_err());
+        assert!(uploads.finish(&id, "other").is_err());
+
+        // other peers can start their own, up to the node-wide limit
+        for i in MAX_UPLOADS_PER_PEER..MAX_UPLOADS {
+            let peer = format!("peer{}", i);
+            uploads
+                .start(dir.path(), &peer, hash.clone(), config())
+                .unwrap();
+        }
+        assert!(uploads
+            .start(dir.path(), "other", hash.clone(), config())
+            .is_err());
+
+        // finished upload makes room for a new one
+        uploads.finish(&id, PEER).unwrap();
+        uploads.start(dir.path(), PEER, hash, config()).unwrap();
+    }
+
+    #[test]
+    fn expired_uploads_are_removed() {
+        let dir = TempDir::new("test").unwrap();
+        let uploads = Uploads::default();
+        let hash = Hash::hash(b"module");
+
+        let expired = uploads
+            .start(dir.path(), PEER, hash.clone(), config())
+            .unwrap();
+        let active = uploads.start(dir.path(), PEER, hash, config()).unwrap();
+        uploads.0.lock()[&expired].upload.lock().last_active =
+            Instant::now() - UPLOAD_TIMEOUT - Duration::from_secs(1);
+
+        uploads.remove_expired();
+        assert!(uploads.chunk(&expired, PEER, 0, b"module").is_err());
+        assert_eq!(uploads.chunk(&active, PEER, 0, b"module").unwrap(), 6);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -20,6 +20,7 @@ use connection_pool::{ConnectionPoolBehaviour, ConnectionPoolInlet, ConnectionPo
 use fluence_libp2p::generate_swarm_event_type;
 use fluence_libp2p::types::BackPressuredInlet;
 use kademlia::{Kademlia, KademliaApi, KademliaApiInlet, KademliaConfig};
+use module_exchange::ModuleExchange;
 use particle_protocol::{Contact, Particle};
 use server_config::NetworkConfig;
 
@@ -43,6 +44,7 @@ pub struct NetworkBehaviour {
     ping: Ping,
     pub(crate) connection_pool: ConnectionPoolInlet,
     pub(crate) kademlia: KademliaApiInlet,
+    module_exchange: ModuleExchange,
     #[behaviour(ignore)]
     /// Whether to allow local (127.0.0.1) addresses in identify
     pub(super) allow_local_addresses: bool,
@@ -62,6 +64,7 @@ impl NetworkBehaviour {
             peer_id: cfg.local_peer_id,
             keypair: cfg.key_pair,
             kad_config: cfg.kademlia_config,
+            persistence_dir: cfg.kademlia_dir,
         };
 
         // TODO: this is hazy; names are bad, conversion is far from transparent. Hide behaviours?
@@ -71,13 +74,17 @@ impl NetworkBehaviour {
             cfg.particle_queue_buffer,
             cfg.protocol_config,
             cfg.local_peer_id,
+            cfg.registry.as_ref(),
         );
         let (connection_pool_api, connection_pool) = connection_pool.into();
+        let (module_exchange_api, module_exchange) =
+            ModuleExchange::new(cfg.modules_dir, cfg.module_fetch_timeout);
 
         Ok((
             Self {
                 kademlia,
                 connection_pool,
+                module_exchange,
                 identity,
                 ping,
                 allow_local_addresses: cfg.allow_local_addresses,
@@ -87,6 +94,7 @@ impl NetworkBehaviour {
                 cfg.particle_parallelism,
                 kademlia_api,
                 connection_pool_api,
+                module_exchange_api,
                 cfg.bootstrap_frequency,
                 cfg.particle_timeout,
             ),
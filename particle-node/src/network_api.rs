@@ -31,6 +31,7 @@ use connection_pool::{ConnectionPoolApi, ConnectionPoolT, LifecycleEvent};
 use control_macro::unwrap_return;
 use fluence_libp2p::types::BackPressuredInlet;
 use kademlia::{KademliaApi, KademliaApiT, KademliaError};
+use module_exchange::ModuleExchangeApi;
 use particle_protocol::Contact;
 use particle_protocol::Particle;
 use server_config::NodeConfig;
@@ -74,6 +75,7 @@ impl NetworkApi {
         particle_parallelism: usize,
         kademlia: KademliaApi,
         connection_pool: ConnectionPoolApi,
+        module_exchange: ModuleExchangeApi,
         bootstrap_frequency: usize,
         particle_timeout: Duration,
     ) -> Self {
@@ -83,6 +85,7 @@ impl NetworkApi {
             connectivity: Connectivity {
                 kademlia,
                 connection_pool,
+                module_exchange,
             },
             bootstrap_frequency,
             particle_timeout,
@@ -172,12 +175,13 @@ impl NetworkApi {
 }
 
 #[derive(Clone)]
-/// This structure is just a composition of Kademlia and ConnectionPool.
+/// This structure is just a composition of Kademlia, ConnectionPool and ModuleExchange.
 /// It exists solely for code conciseness (i.e. avoid tuples);
 /// there's no architectural motivation behind
 pub struct Connectivity {
     pub kademlia: KademliaApi,
     pub connection_pool: ConnectionPoolApi,
+    pub module_exchange: ModuleExchangeApi,
 }
 
 impl Connectivity {
@@ -356,3 +360,9 @@ impl AsRef<ConnectionPoolApi> for Connectivity {
         &self.connection_pool
     }
 }
+
+impl AsRef<ModuleExchangeApi> for Connectivity {
+    fn as_ref(&self) -> &ModuleExchangeApi {
+        &self.module_exchange
+    }
+}
@@ -19,7 +19,10 @@ use crate::metrics::start_metrics_endpoint;
 use crate::network_api::NetworkApi;
 use crate::network_tasks::NetworkTasks;
 
-use aquamarine::{AquamarineApi, AquamarineBackend, StepperEffects, VmPoolConfig};
+use aquamarine::{
+    AquamarineApi, AquamarineBackend, InterpreterReloads, StepperEffects, VmPoolConfig,
+    VmPoolSettings,
+};
 use config_utils::to_peer_id;
 use connection_pool::ConnectionPoolApi;
 use fluence_libp2p::{
@@ -77,12 +80,27 @@ impl Node {
 
         let local_peer_id = to_peer_id(&key_pair);
 
+        let pool_settings = VmPoolSettings {
+            pool_size: config.stepper_pool_size,
+            min_pool_size: config
+                .stepper_pool_min_size
+                .unwrap_or(config.stepper_pool_size),
+            vm_idle_timeout: config.stepper_vm_idle_timeout,
+            execution_timeout: config.particle_execution_timeout,
+            max_lost_vms: config.stepper_max_lost_vms,
+            mailbox_size: config.actor_mailbox_size,
+            max_queued_particles: config.max_queued_particles,
+            seen_particles_limit: config.seen_particles_limit,
+            particle_data_max_age: config.particle_data_max_age,
+            particle_data_max_size: config.particle_data_max_size,
+            recordings_dir: config.particle_recordings_dir.clone(),
+            interpreter_watch_interval: config.air_interpreter_watch_interval,
+        };
         let pool_config = VmPoolConfig::new(
             local_peer_id,
             config.stepper_base_dir.clone(),
             config.air_interpreter_path.clone(),
-            config.stepper_pool_size,
-            config.particle_execution_timeout,
+            pool_settings,
         )
         .expect("create vm pool config");
 
@@ -90,21 +108,26 @@ impl Node {
             local_peer_id,
             config.services_base_dir.clone(),
             config.services_envs.clone(),
+            config.services_scoped_envs.clone(),
             config.management_peer_id,
+            config.permissions.clone(),
+            config.service_limits.clone(),
         )
         .expect("create services config");
 
-        let registry = Registry::new();
-        let network_config =
-            NetworkConfig::new(trust_graph, Some(registry.clone()), key_pair, &config);
-
         let script_storage_config = ScriptStorageConfig {
             timer_resolution: config.script_storage_timer_resolution,
             max_failures: config.script_storage_max_failures,
             particle_ttl: config.script_storage_particle_ttl,
             peer_id: local_peer_id,
+            key_pair: key_pair.clone(),
+            scripts_dir: services_config.scripts_dir.clone(),
         };
 
+        let registry = Registry::new();
+        let network_config =
+            NetworkConfig::new(trust_graph, Some(registry.clone()), key_pair, &config);
+
         Self::with(
             local_peer_id,
             transport,
@@ -155,11 +178,21 @@ impl Node {
             ScriptStorageBackend::new(pool.clone(), failures, cfg)
         };
         let node_info = NodeInfo { external_addresses };
-        let host_closures =
-            HostClosures::new(connectivity, script_storage_api, node_info, services_config);
+        let (interpreter_reloads, interpreter_api) = InterpreterReloads::new(&pool_config);
+        let host_closures = HostClosures::new(
+            connectivity,
+            script_storage_api,
+            node_info,
+            services_config,
+            interpreter_api,
+        );
 
-        let (stepper_pool, stepper_pool_api) =
-            AquamarineBackend::new(pool_config, host_closures.descriptor());
+        let (stepper_pool, stepper_pool_api) = AquamarineBackend::new(
+            pool_config,
+            host_closures.descriptor(),
+            interpreter_reloads,
+            registry.as_ref(),
+        );
 
         let node_service = Self {
             network_api,
@@ -21,26 +21,31 @@ use libp2p::core::Multiaddr;
 use maplit::hashmap;
 use serde::Deserialize;
 use serde_json::json;
+use std::thread::sleep;
+use std::time::Duration;
 
 #[derive(Deserialize, Debug)]
 struct NodeInfo {
     pub external_addresses: Vec<Multiaddr>,
+    pub air_interpreter: InterpreterInfo,
 }
 
-#[test]
-fn identify() {
-    let swarms = make_swarms(1);
-
-    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
-        .wrap_err("connect client")
-        .unwrap();
+#[derive(Deserialize, Debug)]
+// interpreter path isn't exposed
+#[serde(deny_unknown_fields)]
+struct InterpreterInfo {
+    pub version: Option<String>,
+    pub hash: Option<String>,
+    pub generation: u64,
+}
 
+fn identify_node(client: &mut ConnectedClient) -> NodeInfo {
     client.send_particle(
         r#"
         (seq
             (call relay ("peer" "identify") [] info)
             (call client ("op" "return") [info])
-        ) 
+        )
         "#,
         hashmap! {
             "relay" => json!(client.node.to_string()),
@@ -50,5 +55,122 @@ fn identify() {
 
     let info = client.receive_args().wrap_err("receive args").unwrap();
     let info = info.into_iter().next().unwrap();
-    let _: NodeInfo = serde_json::from_value(info).unwrap();
+    serde_json::from_value(info).unwrap()
+}
+
+#[test]
+fn identify() {
+    let swarms = make_swarms(1);
+
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let info = identify_node(&mut client).air_interpreter;
+    assert_eq!(info.version.as_deref(), Some(air_interpreter_wasm::VERSION));
+    assert!(info.hash.is_some());
+    assert_eq!(info.generation, 0);
+}
+
+#[test]
+fn disconnect_peer() {
+    let swarms = make_swarms(2);
+
+    // only the management peer is allowed to disconnect peers
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    stranger.send_particle(
+        r#"
+        (xor
+            (call relay ("peer" "disconnect") [peer])
+            (call client ("op" "return") ["forbidden"])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(stranger.node.to_string()),
+            "client" => json!(stranger.peer_id.to_string()),
+            "peer" => json!(swarms[1].0.to_string()),
+        },
+    );
+    let result = stranger.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(result, vec![json!("forbidden")]);
+
+    let mut management =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(swarms[0].3.clone()))
+            .wrap_err("connect management client")
+            .unwrap();
+    management.send_particle(
+        r#"
+        (seq
+            (seq
+                (call relay ("peer" "disconnect") [peer] disconnected)
+                (call relay ("peer" "is_connected") [peer] connected)
+            )
+            (call client ("op" "return") [disconnected connected])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(management.node.to_string()),
+            "client" => json!(management.peer_id.to_string()),
+            "peer" => json!(swarms[1].0.to_string()),
+        },
+    );
+    let result = management.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(result, vec![json!(true), json!(false)]);
+}
+
+#[test]
+fn reload_interpreter() {
+    let swarms = make_swarms(1);
+
+    // only the management peer is allowed to reload interpreter
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    stranger.send_particle(
+        r#"
+        (xor
+            (call relay ("peer" "reload_interpreter") [])
+            (call client ("op" "return") ["forbidden"])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(stranger.node.to_string()),
+            "client" => json!(stranger.peer_id.to_string()),
+        },
+    );
+    let result = stranger.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(result, vec![json!("forbidden")]);
+
+    let mut management =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(swarms[0].3.clone()))
+            .wrap_err("connect management client")
+            .unwrap();
+    let before = identify_node(&mut management).air_interpreter;
+    management.send_particle(
+        r#"
+        (seq
+            (call relay ("peer" "reload_interpreter") [] path)
+            (call client ("op" "return") [path])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(management.node.to_string()),
+            "client" => json!(management.peer_id.to_string()),
+        },
+    );
+    management.receive_args().wrap_err("receive args").unwrap();
+
+    // new generation of interpreters is created in background
+    let mut after = identify_node(&mut management).air_interpreter;
+    for _ in 0..30 {
+        if after.generation > before.generation {
+            break;
+        }
+        sleep(Duration::from_secs(1));
+        after = identify_node(&mut management).air_interpreter;
+    }
+    assert_eq!(after.generation, before.generation + 1);
+    assert_eq!(after.hash, before.hash);
 }
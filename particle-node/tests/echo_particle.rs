@@ -14,12 +14,19 @@
  * limitations under the License.
  */
 
-use test_utils::{make_swarms, ConnectedClient, KAD_TIMEOUT};
+use test_utils::{
+    make_particle, make_swarms, make_swarms_with_cfg, make_tmp_dir, replay_particle,
+    ConnectedClient, KAD_TIMEOUT,
+};
 
 use eyre::WrapErr;
+use libp2p::identity::PublicKey;
+use libp2p::PeerId;
 use maplit::hashmap;
+use prometheus::Registry;
 use serde_json::json;
 use std::thread::sleep;
+use std::time::Duration;
 
 #[test]
 fn echo_particle() {
@@ -45,3 +52,113 @@ fn echo_particle() {
     let response = client.receive_args().wrap_err("receive").unwrap();
     assert_eq!(data["name"], response[0]);
 }
+
+#[test]
+fn vm_pool_grows_and_shrinks() {
+    let registry = Registry::new();
+    let swarms = make_swarms_with_cfg(1, |mut cfg| {
+        cfg.pool_size = Some(2);
+        cfg.min_pool_size = Some(0);
+        cfg.vm_idle_timeout = Some(Duration::from_secs(1));
+        cfg.registry = Some(registry.clone());
+        cfg
+    });
+    let pool_size = || {
+        let metrics = registry.gather();
+        let size = metrics
+            .iter()
+            .find(|m| m.get_name() == "aquamarine_vm_pool_size")?;
+        Some(size.get_metric()[0].get_gauge().get_value() as usize)
+    };
+
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let mut echo = |name: &str| {
+        let data = hashmap! {
+            "name" => json!(name),
+            "client" => json!(client.peer_id.to_string()),
+            "relay" => json!(client.node.to_string()),
+        };
+        client.send_particle(
+            r#"
+            (seq
+                (call relay ("op" "identity") [])
+                (call client ("return" "") [name])
+            )"#,
+            data,
+        );
+        client.receive_args().wrap_err("receive").unwrap()
+    };
+
+    // pool is empty at start, so it's grown to execute the particle
+    assert_eq!(echo("first"), vec![json!("first")]);
+    // and shrunk back once the VM is idle
+    sleep(Duration::from_secs(3));
+    assert_eq!(pool_size(), Some(0));
+    assert_eq!(echo("second"), vec![json!("second")]);
+}
+
+#[test]
+fn forged_particle_dropped() {
+    let swarms = make_swarms(1);
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let script = format!(
+        r#"
+        (seq
+            (call "{}" ("op" "identity") [])
+            (call "{}" ("return" "") ["hello"])
+        )"#,
+        client.node, client.peer_id
+    );
+    let mut particle = make_particle(
+        &client.client.key_pair,
+        client.call_service_in.clone(),
+        script,
+        client.node,
+        &mut client.local_vm,
+    );
+    // impersonate management peer, signature doesn't match anymore
+    particle.init_peer_id = PeerId::from(PublicKey::Ed25519(swarms[0].3.public()));
+    client.send(particle);
+
+    client.timeout = client.short_timeout;
+    assert!(client.receive().is_err());
+}
+
+#[test]
+fn record_and_replay_particle() {
+    let recordings_dir = make_tmp_dir();
+    let swarms = make_swarms_with_cfg(1, |mut cfg| {
+        cfg.recordings_dir = Some(recordings_dir.clone());
+        cfg
+    });
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let data = hashmap! {
+        "client" => json!(client.peer_id.to_string()),
+        "relay" => json!(client.node.to_string()),
+    };
+    let particle_id = client.send_particle(
+        r#"
+        (seq
+            (call relay ("op" "identity") ["hello"] result)
+            (call client ("return" "") [result])
+        )"#,
+        data,
+    );
+    let response = client.receive_args().wrap_err("receive").unwrap();
+    assert_eq!(response[0], json!(["hello"]));
+
+    let replayed = replay_particle(&recordings_dir, &particle_id);
+    assert_eq!(replayed.len(), 1);
+    for (record, outcome) in replayed {
+        assert_eq!(record.host_calls.len(), 1);
+        assert_eq!(record.outcome, outcome.map_err(|e| e.to_string()));
+    }
+}
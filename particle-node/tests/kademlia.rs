@@ -14,13 +14,17 @@
  * limitations under the License.
  */
 
-use test_utils::{make_swarms_with_cfg, ConnectedClient, KAD_TIMEOUT};
+use test_utils::{
+    create_memory_maddr, create_swarm, make_swarms, make_swarms_with_cfg, make_tmp_dir, uuid,
+    ConnectedClient, SwarmConfig, KAD_TIMEOUT,
+};
 
 use eyre::WrapErr;
 use libp2p::PeerId;
 use maplit::hashmap;
 use serde_json::{json, Value as JValue};
 use std::thread::sleep;
+use std::time::Duration;
 
 #[test]
 fn neighborhood() {
@@ -59,3 +63,241 @@ fn neighborhood() {
         panic!("response[0] must be an array, response was {:#?}", response);
     }
 }
+
+#[test]
+fn put_get_value() {
+    let swarms = make_swarms_with_cfg(5, |cfg| cfg);
+    sleep(KAD_TIMEOUT);
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let mut client2 = ConnectedClient::connect_to(swarms[4].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let key = uuid();
+    client.send_particle(
+        r#"
+            (seq
+                (call node ("kad" "put_value") [key value "majority"] void[])
+                (call client ("return" "") [] void)
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "key" => json!(key),
+            "value" => json!("hello"),
+        },
+    );
+    client.receive_args().wrap_err("receive").unwrap();
+
+    client2.send_particle(
+        r#"
+            (seq
+                (seq
+                    (call node ("kad" "get_value") [key publisher] values)
+                    (call node ("kad" "get_value") [missing publisher 1] empty)
+                )
+                (call client ("return" "") [values empty] void)
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client2.node.to_string()),
+            "client" => json!(client2.peer_id.to_string()),
+            "publisher" => json!(client.peer_id.to_string()),
+            "key" => json!(key),
+            "missing" => json!(uuid()),
+        },
+    );
+    let response = client2.receive_args().wrap_err("receive").unwrap();
+    assert_eq!(response[0], json!(["hello"]));
+    assert_eq!(response[1], json!([]));
+}
+
+#[test]
+fn remove_value() {
+    let swarms = make_swarms_with_cfg(3, |cfg| cfg);
+    sleep(KAD_TIMEOUT);
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let mut client2 = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let mut client3 = ConnectedClient::connect_to(swarms[1].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let key = uuid();
+    client.send_particle(
+        r#"
+            (seq
+                (call node ("kad" "put_value") [key value "majority"] void[])
+                (call client ("return" "") [] void)
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "key" => json!(key),
+            "value" => json!("hello"),
+        },
+    );
+    client.receive_args().wrap_err("receive").unwrap();
+
+    // kad calls of another peer work in its own namespace, so they don't touch the value
+    client2.send_particle(
+        r#"
+            (seq
+                (seq
+                    (call node ("kad" "remove_value") [key] void[])
+                    (call node ("kad" "put_value") [key value "majority"] void[])
+                )
+                (seq
+                    (seq
+                        (call node ("kad" "get_value") [key owner] owner_values)
+                        (call node ("kad" "get_value") [key client] own_values)
+                    )
+                    (call client ("return" "") [owner_values own_values] void)
+                )
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client2.node.to_string()),
+            "client" => json!(client2.peer_id.to_string()),
+            "owner" => json!(client.peer_id.to_string()),
+            "key" => json!(key),
+            "value" => json!("other"),
+        },
+    );
+    let response = client2.receive_args().wrap_err("receive").unwrap();
+    assert_eq!(response[0], json!(["hello"]));
+    assert_eq!(response[1], json!(["other"]));
+
+    // owner removes its value from replicas too
+    client.send_particle(
+        r#"
+            (seq
+                (call node ("kad" "remove_value") [key "majority"] void[])
+                (call client ("return" "") [] void)
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "key" => json!(key),
+        },
+    );
+    client.receive_args().wrap_err("receive").unwrap();
+
+    client3.send_particle(
+        r#"
+            (seq
+                (call node ("kad" "get_value") [key owner] values)
+                (call client ("return" "") [values] void)
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client3.node.to_string()),
+            "client" => json!(client3.peer_id.to_string()),
+            "owner" => json!(client.peer_id.to_string()),
+            "key" => json!(key),
+        },
+    );
+    let response = client3.receive_args().wrap_err("receive").unwrap();
+    assert_eq!(response[0], json!([]));
+}
+
+#[test]
+fn rejoin_from_persisted_routing_table() {
+    let swarms = make_swarms(3);
+    let tmp = make_tmp_dir();
+
+    // first incarnation learns about the network from bootstrap nodes
+    let bootstraps = vec![swarms[0].1.clone()];
+    let (_, node, ..) = create_swarm(SwarmConfig {
+        tmp_dir: Some(tmp.clone()),
+        ..SwarmConfig::new(bootstraps, create_memory_maddr())
+    });
+    let stop = node.start();
+    sleep(KAD_TIMEOUT);
+    // routing table is saved when node is stopped
+    stop.send(()).ok();
+    sleep(Duration::from_millis(500));
+
+    // second incarnation doesn't have any bootstrap nodes
+    let maddr = create_memory_maddr();
+    let (_, node, ..) = create_swarm(SwarmConfig {
+        tmp_dir: Some(tmp),
+        ..SwarmConfig::new(vec![], maddr.clone())
+    });
+    let _stop = node.start();
+
+    let mut client = ConnectedClient::connect_to(maddr)
+        .wrap_err("connect client")
+        .unwrap();
+    client.send_particle(
+        r#"
+            (seq
+                (call node ("kad" "neighborhood") [node] peers)
+                (call client ("return" "") [peers] void)
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string())
+        },
+    );
+    let response = client.receive_args().wrap_err("receive").unwrap();
+    let neighborhood = response[0]
+        .as_array()
+        .expect("neighborhood must be an array");
+    for swarm in swarms.iter() {
+        assert!(neighborhood.contains(&json!(swarm.0.to_string())));
+    }
+}
+
+#[test]
+fn routing_table_and_bans() {
+    let swarms = make_swarms_with_cfg(3, |cfg| cfg);
+    sleep(KAD_TIMEOUT);
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    client.send_particle(
+        r#"
+            (seq
+                (seq
+                    (call node ("kad" "routing_table") [] table)
+                    (call node ("kad" "banned") [] banned)
+                )
+                (seq
+                    (xor
+                        (call node ("kad" "unban") [node] unbanned)
+                        (call node ("op" "identity") ["forbidden"] unbanned)
+                    )
+                    (call client ("return" "") [table banned unbanned] void)
+                )
+            )
+        "#,
+        hashmap! {
+            "node" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string())
+        },
+    );
+    let response = client.receive_args().wrap_err("receive").unwrap();
+    let table = response[0]
+        .as_array()
+        .expect("routing table must be an array");
+    assert_eq!(table.len(), 2);
+    for swarm in swarms.iter().skip(1) {
+        assert!(table
+            .iter()
+            .any(|p| p["peer_id"] == json!(swarm.0.to_string())));
+    }
+    assert_eq!(response[1], json!([]));
+    // only management peer can unban
+    assert_eq!(response[2], json!("forbidden"));
+}
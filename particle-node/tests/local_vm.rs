@@ -21,6 +21,8 @@ extern crate fstrings;
 use fluence_libp2p::RandomPeerId;
 use test_utils::{make_call_service_closure, make_particle, make_vm, read_args};
 
+use libp2p::identity::{ed25519::Keypair, PublicKey};
+use libp2p::PeerId;
 use maplit::hashmap;
 use parking_lot::Mutex;
 use serde_json::json;
@@ -30,7 +32,8 @@ use std::sync::Arc;
 
 #[test]
 fn make() {
-    let client_a = RandomPeerId::random();
+    let key_pair_a = Keypair::generate();
+    let client_a = PeerId::from(PublicKey::Ed25519(key_pair_a.public()));
     let client_b = RandomPeerId::random();
 
     let call_service_in_a: Arc<Mutex<HashMap<String, JValue>>> = <_>::default();
@@ -60,12 +63,13 @@ fn make() {
         .collect();
 
     let particle = make_particle(
-        client_a,
+        &key_pair_a,
         call_service_in_a.clone(),
         script,
         None,
         &mut local_vm_a,
     );
+    assert!(particle.verify().is_ok());
 
     let args = read_args(
         particle,
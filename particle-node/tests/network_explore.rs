@@ -14,8 +14,8 @@
  * limitations under the License.
  */
 use test_utils::{
-    create_service, load_module, make_swarms, module_config, read_args, test_module_cfg, timeout,
-    ClientEvent, ConnectedClient, KAD_TIMEOUT,
+    create_service, load_module, make_swarms, make_swarms_with_cfg, module_config, read_args,
+    test_module_cfg, timeout, ClientEvent, ConnectedClient, KAD_TIMEOUT,
 };
 
 use eyre::{ContextCompat, WrapErr};
@@ -24,6 +24,7 @@ use maplit::hashmap;
 use serde::Deserialize;
 use serde_json::json;
 use serde_json::Value as JValue;
+use server_config::{PermissionRule, PermissionsConfig};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
@@ -109,6 +110,324 @@ fn get_interfaces() {
     assert_eq!(interfaces_count, 2);
 }
 
+#[test]
+fn remove_service() {
+    let swarms = make_swarms(1);
+
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let service = create_service(
+        &mut client,
+        "tetraplets",
+        load_module("tests/tetraplets/artifacts", "tetraplets"),
+    );
+
+    // only the owner or the management peer could remove a service
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    stranger.send_particle(
+        r#"
+        (xor
+            (call relay ("srv" "remove") [service])
+            (call client ("return" "") ["forbidden"])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(stranger.node.to_string()),
+            "client" => json!(stranger.peer_id.to_string()),
+            "service" => json!(service.id),
+        },
+    );
+    let args = stranger.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(args, vec![json!("forbidden")]);
+
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (call relay ("srv" "restart") [service])
+                (call relay ("srv" "remove") [service])
+            )
+            (seq
+                (call relay ("srv" "list") [] services)
+                (call client ("return" "") [services])
+            )
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "service" => json!(service.id),
+        },
+    );
+
+    let args = client.receive_args().wrap_err("receive args").unwrap();
+    let services: Vec<Service> = serde_json::from_value(args.into_iter().next().unwrap())
+        .wrap_err("deserialize services")
+        .unwrap();
+    assert!(!services.iter().any(|d| d.id == service.id));
+}
+
+#[test]
+fn upgrade_service() {
+    let swarms = make_swarms(1);
+
+    // service is owned by the management peer, so it can add aliases to it
+    let mut client =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(swarms[0].3.clone()))
+            .wrap_err("connect management client")
+            .unwrap();
+    let service = create_service(
+        &mut client,
+        "tetraplets",
+        load_module("tests/tetraplets/artifacts", "tetraplets"),
+    );
+
+    // only the owner could upgrade a service
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    stranger.send_particle(
+        r#"
+        (xor
+            (call relay ("srv" "upgrade") [service "blueprint"])
+            (call client ("return" "") ["forbidden"])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(stranger.node.to_string()),
+            "client" => json!(stranger.peer_id.to_string()),
+            "service" => json!(service.id),
+        },
+    );
+    let args = stranger.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(args, vec![json!("forbidden")]);
+
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (seq
+                    (call relay ("srv" "add_alias") ["upgraded" service])
+                    (call relay ("srv" "list") [] before)
+                )
+                (seq
+                    (call relay ("dist" "add_module") [sqlite_bytes sqlite_config])
+                    (call relay ("dist" "add_blueprint") [blueprint] blueprint_id)
+                )
+            )
+            (seq
+                (seq
+                    (xor
+                        (call relay ("srv" "upgrade") [service "missing"])
+                        (call relay ("op" "identity") ["missing blueprint"] failed)
+                    )
+                    (call relay ("srv" "upgrade") [service blueprint_id])
+                )
+                (seq
+                    (call relay ("srv" "list") [] after)
+                    (call client ("return" "") [failed blueprint_id before after])
+                )
+            )
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "service" => json!(service.id),
+            "sqlite_bytes" => json!(base64::encode(load_module("tests/chat", "sqlite3"))),
+            "sqlite_config" => module_config("sqlite3"),
+            "blueprint" => json!({ "name": "upgraded", "dependencies": ["sqlite3", "tetraplets"] }),
+        },
+    );
+
+    let find = |services: JValue| {
+        let services = services.as_array().cloned().unwrap_or_default();
+        services
+            .into_iter()
+            .find(|s| s["id"] == json!(service.id))
+            .expect("upgraded service is kept")
+    };
+    let mut args = client
+        .receive_args()
+        .wrap_err("receive args")
+        .unwrap()
+        .into_iter();
+    assert_eq!(args.next().unwrap(), json!(["missing blueprint"]));
+    let blueprint_id = args.next().unwrap();
+    let before = find(args.next().unwrap());
+    let upgraded = find(args.next().unwrap());
+    assert_ne!(before["blueprint_id"], blueprint_id);
+    assert_eq!(upgraded["blueprint_id"], blueprint_id);
+    assert_eq!(upgraded["aliases"], json!(["upgraded"]));
+
+    // failed upgrade leaves both the running and the persisted service as they were
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (call relay ("dist" "add_blueprint") [blueprint] too_big_id)
+                (xor
+                    (call relay ("srv" "upgrade") [service too_big_id])
+                    (call relay ("op" "identity") ["memory limit exceeded"] failed)
+                )
+            )
+            (seq
+                (seq
+                    (call relay ("srv" "list") [] services)
+                    (call relay ("upgraded" "get_tetraplets") ["file"] tetraplets)
+                )
+                (call client ("return" "") [failed services])
+            )
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "service" => json!(service.id),
+            "blueprint" => json!({
+                "name": "too big",
+                "dependencies": ["tetraplets"],
+                "limits": { "max_memory_pages": 1 }
+            }),
+        },
+    );
+
+    let mut args = client
+        .receive_args()
+        .wrap_err("receive args")
+        .unwrap()
+        .into_iter();
+    assert_eq!(args.next().unwrap(), json!(["memory limit exceeded"]));
+    assert_eq!(find(args.next().unwrap()), upgraded);
+
+    let persisted = swarms[0]
+        .2
+        .join("services")
+        .join("services")
+        .join(format!("{}_service.toml", service.id));
+    let persisted = std::fs::read(&persisted).expect("read persisted service");
+    let persisted: toml::Value = toml::from_slice(&persisted).expect("parse persisted service");
+    assert_eq!(persisted["blueprint_id"].as_str(), blueprint_id.as_str());
+    assert_eq!(
+        persisted["aliases"].as_array(),
+        Some(&vec![toml::Value::from("upgraded")])
+    );
+}
+
+#[test]
+fn remove_blueprint_and_module() {
+    let swarms = make_swarms(1);
+
+    // only the management peer can remove modules and blueprints by default
+    let mut client =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(swarms[0].3.clone()))
+            .wrap_err("connect management client")
+            .unwrap();
+    let service = create_service(
+        &mut client,
+        "tetraplets",
+        load_module("tests/tetraplets/artifacts", "tetraplets"),
+    );
+
+    client.send_particle(
+        r#"
+        (seq
+            (call relay ("dist" "list_blueprints") [] blueprints)
+            (call client ("return" "") [blueprints])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+        },
+    );
+    let args = client.receive_args().wrap_err("receive args").unwrap();
+    let blueprints: Vec<Blueprint> = serde_json::from_value(args[0].clone())
+        .wrap_err("deserialize blueprints")
+        .unwrap();
+    let blueprint = blueprints[0].clone();
+    let module = blueprint.dependencies[0].trim_start_matches("hash:");
+
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    stranger.send_particle(
+        r#"
+        (xor
+            (call relay ("dist" "remove_blueprint") [blueprint])
+            (call client ("op" "return") ["forbidden"])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(stranger.node.to_string()),
+            "client" => json!(stranger.peer_id.to_string()),
+            "blueprint" => json!(blueprint.id),
+        },
+    );
+    let args = stranger.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(args, vec![json!("forbidden")]);
+
+    // neither blueprint nor module can be removed while they're in use
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (xor
+                    (call relay ("dist" "remove_blueprint") [blueprint])
+                    (call relay ("op" "identity") ["blueprint in use"] bp_result)
+                )
+                (xor
+                    (call relay ("dist" "remove_module") [module])
+                    (call relay ("op" "identity") ["module in use"] module_result)
+                )
+            )
+            (call client ("return" "") [bp_result module_result])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "blueprint" => json!(blueprint.id),
+            "module" => json!(module),
+        },
+    );
+    let args = client.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(
+        args,
+        vec![json!(["blueprint in use"]), json!(["module in use"])]
+    );
+
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (call relay ("srv" "remove") [service])
+                (seq
+                    (call relay ("dist" "remove_blueprint") [blueprint])
+                    (call relay ("dist" "remove_module") [module])
+                )
+            )
+            (seq
+                (call relay ("dist" "list_modules") [] modules)
+                (call client ("return" "") [modules])
+            )
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "service" => json!(service.id),
+            "blueprint" => json!(blueprint.id),
+            "module" => json!(module),
+        },
+    );
+    let args = client.receive_args().wrap_err("receive args").unwrap();
+    assert_eq!(args, vec![json!([])]);
+}
+
 #[test]
 fn get_modules() {
     let swarms = make_swarms(3);
@@ -142,6 +461,101 @@ fn get_modules() {
     assert_eq!(modules[0].name.as_deref(), Some("greeting"));
 }
 
+#[test]
+fn fetch_module_from_peer() {
+    let allowed_kp = libp2p::identity::ed25519::Keypair::generate();
+    let allowed_peer = libp2p::identity::PublicKey::Ed25519(allowed_kp.public()).into_peer_id();
+    let swarms = make_swarms_with_cfg(2, |mut cfg| {
+        cfg.permissions = PermissionsConfig {
+            rules: vec![PermissionRule {
+                service_id: "dist".into(),
+                function_name: "fetch_module".into(),
+                peers: vec![allowed_peer],
+                trusted_roots: vec![],
+            }],
+            certificates: vec![],
+        };
+        cfg
+    });
+    sleep(KAD_TIMEOUT);
+
+    // fetching isn't open to everyone
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let particle_id = stranger.send_particle(
+        r#"
+        (seq
+            (xor
+                (seq
+                    (call remote ("dist" "fetch_module") [hash peers])
+                    (call relay ("op" "identity") ["fetched"] result)
+                )
+                (call relay ("op" "identity") ["failed"] result)
+            )
+            (call client ("op" "return") [result])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(stranger.node.to_string()),
+            "remote" => json!(swarms[1].0.to_string()),
+            "client" => json!(stranger.peer_id.to_string()),
+            "hash" => json!(blake3::hash(b"module").to_hex().as_str()),
+            "peers" => json!([stranger.node.to_string()]),
+        },
+    );
+    let args = stranger.wait_particle_args(particle_id).unwrap();
+    assert_eq!(args, vec![json!("failed")]);
+
+    let mut client =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(allowed_kp))
+            .wrap_err("connect client")
+            .unwrap();
+
+    let (module_a, module_b) = (b"module a", b"module b");
+    let hash_b = format!("hash:{}", blake3::hash(module_b).to_hex());
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (call relay ("dist" "add_module") [module_a config_a] hash_a)
+                (call relay ("dist" "add_module") [module_b config_b] hash_b)
+            )
+            (seq
+                (seq
+                    (call remote ("dist" "fetch_module") [hash_a peers])
+                    (seq
+                        (call remote ("dist" "add_blueprint") [blueprint peers] blueprint_id)
+                        (call remote ("dist" "list_modules") [] modules)
+                    )
+                )
+                (seq
+                    (call relay ("op" "identity") [])
+                    (call client ("return" "") [modules])
+                )
+            )
+        )
+        "#,
+        hashmap! {
+            "module_a" => json!(base64::encode(module_a)),
+            "module_b" => json!(base64::encode(module_b)),
+            "config_a" => module_config("a"),
+            "config_b" => module_config("b"),
+            "relay" => json!(client.node.to_string()),
+            "remote" => json!(swarms[1].0.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "peers" => json!([client.node.to_string()]),
+            "blueprint" => json!({ "name": "blueprint", "dependencies": [hash_b] }),
+        },
+    );
+
+    let args = client.receive_args().wrap_err("receive args").unwrap();
+    let modules: Vec<ModuleDescriptor> = serde_json::from_value(args[0].clone()).unwrap();
+    let mut names: Vec<_> = modules.into_iter().filter_map(|m| m.name).collect();
+    names.sort();
+    assert_eq!(names, vec!["a", "b"]);
+}
+
 #[test]
 fn list_blueprints() {
     let swarms = make_swarms(3);
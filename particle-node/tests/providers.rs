@@ -183,3 +183,65 @@ fn add_providers_to_neighborhood() {
     assert!(providers.contains(&provider));
     assert!(providers.contains(&provider2));
 }
+
+#[test]
+fn providers_from_dht() {
+    let swarms = make_swarms_with_cfg(5, |cfg| cfg);
+    sleep(KAD_TIMEOUT);
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let mut client2 = ConnectedClient::connect_to(swarms[4].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let key = uuid();
+    client.send_particle(
+        r#"
+        (seq
+            (seq
+                (call node ("providers" "add") [key service_id] void[])
+                (call node ("providers" "add") [key] void[])
+            )
+            (call client ("return" "") [] void[])
+        )
+        "#,
+        hashmap! {
+            "client" => json!(client.peer_id.to_string()),
+            "node" => json!(client.node.to_string()),
+            "key" => json!(key),
+            "service_id" => json!("service"),
+        },
+    );
+    client.receive_args().wrap_err("receive").unwrap();
+
+    client2.send_particle(
+        r#"
+        (seq
+            (call node ("providers" "get") [key] providers)
+            (call client ("return" "") [providers] void[])
+        )
+        "#,
+        hashmap! {
+            "client" => json!(client2.peer_id.to_string()),
+            "node" => json!(client2.node.to_string()),
+            "key" => json!(key),
+        },
+    );
+
+    let response = client2.receive_args().wrap_err("receive").unwrap();
+    let providers = into_array(response[0].clone())
+        .wrap_err(format!("providers must be array, was {:#?}", response))
+        .unwrap();
+    assert_eq!(providers.len(), 2);
+    let node = swarms[0].0.to_string();
+    for provider in providers.iter() {
+        assert_eq!(provider["peer"], json!(node));
+    }
+    let service = providers
+        .iter()
+        .find(|p| p["service_id"].is_string())
+        .wrap_err("service provider")
+        .unwrap();
+    assert_eq!(service["service_id"], json!("service"));
+}
@@ -14,7 +14,9 @@
  * limitations under the License.
  */
 
-use test_utils::{make_swarms, ConnectedClient};
+use test_utils::{make_swarms, make_swarms_with_cfg, make_tmp_dir, ConnectedClient};
+
+use server_config::{PermissionRule, PermissionsConfig};
 
 use eyre::WrapErr;
 use fstrings::f;
@@ -239,6 +241,71 @@ fn autoremove_failed() {
     panic!("failed script wasn't deleted in time or at all");
 }
 
+#[test]
+fn schedule_max_runs() {
+    let swarms = make_swarms(1);
+
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let script = f!(r#"
+        (call "{client.peer_id}" ("op" "return") ["hello"])
+    "#);
+
+    client.send_particle(
+        r#"
+        (seq
+            (call relay ("script" "add") [script schedule] id)
+            (seq
+                (call relay ("script" "list") [] list)
+                (call client ("op" "return") [list])
+            )
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "script" => json!(script),
+            "schedule" => json!({ "interval_sec": 0, "max_runs": 2 }),
+        },
+    );
+
+    let mut hellos = 0;
+    let mut listed = false;
+    while hellos < 2 || !listed {
+        let res = client.receive_args().wrap_err("receive args").unwrap();
+        match res.into_iter().next().unwrap() {
+            serde_json::Value::Array(list) => {
+                assert_eq!(list.len(), 1);
+                assert_eq!(list[0]["max_runs"], json!(2));
+                assert!(list[0]["next_fire_sec"].is_u64());
+                listed = true;
+            }
+            hello => {
+                assert_eq!(hello, "hello");
+                hellos += 1;
+            }
+        }
+    }
+
+    // script is removed after max_runs executions
+    let list_id = client.send_particle(
+        r#"
+        (seq
+            (call relay ("script" "list") [] list)
+            (call client ("op" "return") [list])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+        },
+    );
+    let list = client.wait_particle_args(list_id).unwrap();
+    assert_eq!(list, vec![serde_json::Value::Array(vec![])]);
+}
+
 #[test]
 fn remove_script_unauth() {
     let swarms = make_swarms(1);
@@ -318,23 +385,27 @@ fn remove_script_unauth() {
         panic!("expected array");
     }
 
-    // remove with --force
-    let remove_id = client2.send_particle(
+    // management peer is allowed to remove any script
+    let mut management =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(swarms[0].3.clone()))
+            .wrap_err("connect management client")
+            .unwrap();
+    let remove_id = management.send_particle(
         r#"
         (seq
-            (call relay ("script" "remove") [id "--force"] removed)
+            (call relay ("script" "remove") [id] removed)
             (call client ("op" "return") [removed])
         )
         "#,
         hashmap! {
-            "relay" => json!(client2.node.to_string()),
-            "client" => json!(client2.peer_id.to_string()),
+            "relay" => json!(management.node.to_string()),
+            "client" => json!(management.peer_id.to_string()),
             "id" => json!(script_id),
         },
     );
 
     // check removal succeeded
-    let removed = client2.wait_particle_args(remove_id).unwrap();
+    let removed = management.wait_particle_args(remove_id).unwrap();
     assert_eq!(removed, vec![serde_json::Value::Bool(true)]);
 
     // check script is not in the list anymore
@@ -353,3 +424,128 @@ fn remove_script_unauth() {
     let list = client.wait_particle_args(list_id).unwrap();
     assert_eq!(list, vec![serde_json::Value::Array(vec![])]);
 }
+
+#[test]
+fn scripts_survive_restart() {
+    let tmp_dir = make_tmp_dir();
+    let with_tmp = |mut cfg: test_utils::SwarmConfig| {
+        cfg.tmp_dir = Some(tmp_dir.clone());
+        cfg
+    };
+    let swarms = make_swarms_with_cfg(1, with_tmp);
+
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+
+    let script = f!(r#"
+        (call "{client.peer_id}" ("op" "return") ["hello"])
+    "#);
+
+    // add script with a large interval, so it is executed only once before restart
+    let add_id = client.send_particle(
+        r#"
+        (seq
+            (call relay ("script" "add") [script "3600"] id)
+            (call client ("op" "return") [id])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+            "script" => json!(script),
+        },
+    );
+    let script_id = client.wait_particle_args(add_id).unwrap();
+    let script_id = script_id.into_iter().next().unwrap();
+
+    // stop the node, and start a new one on top of the same directory
+    let swarm = swarms.into_iter().next().unwrap();
+    swarm.4.send(()).expect("stop node");
+    let swarms = make_swarms_with_cfg(1, with_tmp);
+
+    let mut client = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    let list_id = client.send_particle(
+        r#"
+        (seq
+            (call relay ("script" "list") [] list)
+            (call client ("op" "return") [list])
+        )
+        "#,
+        hashmap! {
+            "relay" => json!(client.node.to_string()),
+            "client" => json!(client.peer_id.to_string()),
+        },
+    );
+    let list = client
+        .wait_particle_args(list_id)
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    let list = list.as_array().expect("expected array");
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0]["id"], script_id);
+    assert_eq!(list[0]["src"], json!(script));
+}
+
+#[test]
+fn add_script_permissions() {
+    let allowed_kp = libp2p::identity::ed25519::Keypair::generate();
+    let allowed_peer = libp2p::identity::PublicKey::Ed25519(allowed_kp.public()).into_peer_id();
+
+    let swarms = make_swarms_with_cfg(1, |mut cfg| {
+        cfg.permissions = PermissionsConfig {
+            rules: vec![PermissionRule {
+                service_id: "script".into(),
+                function_name: "add".into(),
+                peers: vec![allowed_peer],
+                trusted_roots: vec![],
+            }],
+            certificates: vec![],
+        };
+        cfg
+    });
+
+    let add_script = |client: &mut ConnectedClient| {
+        let add_id = client.send_particle(
+            r#"
+            (xor
+                (seq
+                    (call relay ("script" "add") [script] id)
+                    (call client ("op" "return") ["added"])
+                )
+                (call client ("op" "return") ["failed"])
+            )
+            "#,
+            hashmap! {
+                "relay" => json!(client.node.to_string()),
+                "client" => json!(client.peer_id.to_string()),
+                "script" => json!("(null)"),
+            },
+        );
+        client.wait_particle_args(add_id).unwrap()
+    };
+
+    // stranger isn't allowed to add scripts
+    let mut stranger = ConnectedClient::connect_to(swarms[0].1.clone())
+        .wrap_err("connect client")
+        .unwrap();
+    assert_eq!(add_script(&mut stranger), vec![json!("failed")]);
+
+    // listed peer is allowed to add scripts
+    let mut allowed =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(allowed_kp))
+            .wrap_err("connect client")
+            .unwrap();
+    assert_eq!(add_script(&mut allowed), vec![json!("added")]);
+
+    // management peer is allowed to call any builtin
+    let mut management =
+        ConnectedClient::connect_to_with_peer_id(swarms[0].1.clone(), Some(swarms[0].3.clone()))
+            .wrap_err("connect management client")
+            .unwrap();
+    assert_eq!(add_script(&mut management), vec![json!("added")]);
+}
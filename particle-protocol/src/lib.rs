@@ -28,6 +28,8 @@
 )]
 
 mod libp2p_protocol {
+    pub(super) mod codec;
+    pub(super) mod handler;
     pub(super) mod message;
     pub(super) mod upgrade;
 }
@@ -36,7 +38,9 @@ mod contact;
 mod particle;
 
 pub use contact::Contact;
+pub use libp2p_protocol::codec::WireFormat;
+pub use libp2p_protocol::handler::ParticleHandler;
 pub use libp2p_protocol::message::CompletionChannel;
 pub use libp2p_protocol::message::HandlerMessage;
 pub use libp2p_protocol::upgrade::ProtocolConfig;
-pub use particle::Particle;
+pub use particle::{Particle, SignatureError};
@@ -0,0 +1,211 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::libp2p_protocol::message::ProtocolMessage;
+use crate::Particle;
+
+use libp2p::PeerId;
+use minicbor::{decode, encode, Decode, Decoder, Encode, Encoder};
+use serde::Deserialize;
+use std::io;
+
+/// JSON encoding, particle data is base64-encoded
+pub const PROTOCOL_INFO: &[u8] = b"/fluence/faas/1.0.0";
+/// CBOR encoding, particle data is sent as is
+pub const PROTOCOL_INFO_CBOR: &[u8] = b"/fluence/faas/cbor/1.0.0";
+/// Long-lived substream carrying length-prefixed JSON messages
+pub const PROTOCOL_INFO_STREAM: &[u8] = b"/fluence/faas/stream/1.0.0";
+/// Long-lived substream carrying length-prefixed CBOR messages
+pub const PROTOCOL_INFO_CBOR_STREAM: &[u8] = b"/fluence/faas/cbor/stream/1.0.0";
+
+/// Encoding of the messages sent over the wire. Both are always supported,
+/// preferred one is proposed first when negotiating a protocol with a remote peer
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    Json,
+    Cbor,
+}
+
+impl Default for WireFormat {
+    fn default() -> Self {
+        WireFormat::Json
+    }
+}
+
+impl WireFormat {
+    /// Protocol names to negotiate, preferred format goes first
+    pub fn protocols(self) -> Vec<&'static [u8]> {
+        match self {
+            WireFormat::Json => vec![PROTOCOL_INFO, PROTOCOL_INFO_CBOR],
+            WireFormat::Cbor => vec![PROTOCOL_INFO_CBOR, PROTOCOL_INFO],
+        }
+    }
+
+    /// Protocol names of long-lived streams, preferred format goes first
+    pub fn stream_protocols(self) -> Vec<&'static [u8]> {
+        match self {
+            WireFormat::Json => vec![PROTOCOL_INFO_STREAM, PROTOCOL_INFO_CBOR_STREAM],
+            WireFormat::Cbor => vec![PROTOCOL_INFO_CBOR_STREAM, PROTOCOL_INFO_STREAM],
+        }
+    }
+
+    /// Format corresponding to the negotiated protocol name
+    pub fn from_protocol(info: &[u8]) -> Self {
+        if info == PROTOCOL_INFO_CBOR || info == PROTOCOL_INFO_CBOR_STREAM {
+            WireFormat::Cbor
+        } else {
+            WireFormat::Json
+        }
+    }
+
+    pub fn encode(self, msg: &ProtocolMessage) -> io::Result<Vec<u8>> {
+        match self {
+            WireFormat::Json => Ok(serde_json::to_vec(msg)?),
+            WireFormat::Cbor => minicbor::to_vec(msg)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> io::Result<ProtocolMessage> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            WireFormat::Cbor => minicbor::decode(bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+        }
+    }
+}
+
+/// Whether negotiated protocol is a long-lived stream rather than a single message substream
+pub fn is_stream(info: &[u8]) -> bool {
+    info == PROTOCOL_INFO_STREAM || info == PROTOCOL_INFO_CBOR_STREAM
+}
+
+const PARTICLE: u8 = 0;
+const INBOUND_UPGRADE_ERROR: u8 = 1;
+const UPGRADE: u8 = 2;
+
+/// Messages are encoded as arrays, first element being the message type
+impl Encode for ProtocolMessage {
+    fn encode<W: encode::Write>(&self, e: &mut Encoder<W>) -> Result<(), encode::Error<W::Error>> {
+        match self {
+            ProtocolMessage::Particle(p) => {
+                e.array(8)?
+                    .u8(PARTICLE)?
+                    .str(&p.id)?
+                    .bytes(&p.init_peer_id.to_bytes())?
+                    .u64(p.timestamp)?
+                    .u32(p.ttl)?
+                    .str(&p.script)?
+                    .bytes(&p.signature)?
+                    .bytes(&p.data)?;
+            }
+            ProtocolMessage::InboundUpgradeError(err) => {
+                e.array(2)?
+                    .u8(INBOUND_UPGRADE_ERROR)?
+                    .str(&err.to_string())?;
+            }
+            ProtocolMessage::Upgrade => {
+                e.array(1)?.u8(UPGRADE)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'b> Decode<'b> for ProtocolMessage {
+    fn decode(d: &mut Decoder<'b>) -> Result<Self, decode::Error> {
+        d.array()?;
+        match d.u8()? {
+            PARTICLE => {
+                let id = d.str()?.to_string();
+                let init_peer_id = PeerId::from_bytes(d.bytes()?)
+                    .map_err(|_| decode::Error::Message("invalid init_peer_id"))?;
+                let timestamp = d.u64()?;
+                let ttl = d.u32()?;
+                let script = d.str()?.to_string();
+                let signature = d.bytes()?.to_vec();
+                let data = d.bytes()?.to_vec();
+
+                Ok(ProtocolMessage::Particle(Particle {
+                    id,
+                    init_peer_id,
+                    timestamp,
+                    ttl,
+                    script,
+                    signature,
+                    data,
+                }))
+            }
+            INBOUND_UPGRADE_ERROR => {
+                let err = d.str()?;
+                let err = serde_json::from_str(err)
+                    .map_err(|_| decode::Error::Message("invalid InboundUpgradeError"))?;
+                Ok(ProtocolMessage::InboundUpgradeError(err))
+            }
+            UPGRADE => Ok(ProtocolMessage::Upgrade),
+            _ => Err(decode::Error::Message("unknown ProtocolMessage type")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libp2p_protocol::codec::WireFormat;
+    use crate::libp2p_protocol::message::ProtocolMessage;
+    use crate::Particle;
+    use serde_json::json;
+
+    fn roundtrip(format: WireFormat, msg: ProtocolMessage) {
+        let bytes = format.encode(&msg).expect("encode");
+        let decoded = format.decode(&bytes).expect("decode");
+        assert_eq!(msg, decoded);
+    }
+
+    #[test]
+    fn cbor_roundtrip() {
+        let particle = Particle {
+            id: "123".to_string(),
+            timestamp: 1000,
+            ttl: 100,
+            script: "(null)".to_string(),
+            signature: vec![4, 5, 6],
+            data: vec![1, 2, 3],
+            ..<_>::default()
+        };
+        let error = json!({ "error": "failed" });
+
+        for format in [WireFormat::Json, WireFormat::Cbor].iter() {
+            roundtrip(*format, ProtocolMessage::Particle(particle.clone()));
+            roundtrip(*format, ProtocolMessage::InboundUpgradeError(error.clone()));
+            roundtrip(*format, ProtocolMessage::Upgrade);
+        }
+    }
+
+    #[test]
+    fn cbor_is_smaller() {
+        let particle = Particle {
+            data: vec![42; 1024],
+            ..<_>::default()
+        };
+        let msg = ProtocolMessage::Particle(particle);
+
+        let json = WireFormat::Json.encode(&msg).expect("encode json");
+        let cbor = WireFormat::Cbor.encode(&msg).expect("encode cbor");
+        assert!(cbor.len() < json.len());
+    }
+}
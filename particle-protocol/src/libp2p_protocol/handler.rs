@@ -0,0 +1,513 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::libp2p_protocol::codec::{is_stream, WireFormat};
+use crate::libp2p_protocol::upgrade::MAX_BUF_SIZE;
+use crate::{HandlerMessage, ProtocolConfig};
+
+use futures::{
+    future,
+    future::BoxFuture,
+    stream::{self, BoxStream, FuturesUnordered, SelectAll},
+    AsyncRead, AsyncWrite, FutureExt, StreamExt,
+};
+use libp2p::{
+    core::{upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo},
+    swarm::{
+        KeepAlive, NegotiatedSubstream, ProtocolsHandler, ProtocolsHandlerEvent,
+        ProtocolsHandlerUpgrErr, SubstreamProtocol,
+    },
+};
+use std::{
+    collections::VecDeque,
+    io, mem,
+    task::{Context, Poll},
+};
+use wasm_timer::Instant;
+
+/// Negotiates either a long-lived particle stream, or a single message substream
+/// for peers that don't support streams. Substream is returned as is,
+/// reading and writing is up to the `ParticleHandler`
+#[derive(Clone, Debug)]
+pub struct StreamUpgrade {
+    wire_format: WireFormat,
+}
+
+impl UpgradeInfo for StreamUpgrade {
+    type Info = &'static [u8];
+    type InfoIter = std::vec::IntoIter<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let mut protocols = self.wire_format.stream_protocols();
+        protocols.extend(self.wire_format.protocols());
+        protocols.into_iter()
+    }
+}
+
+impl<Socket: Send + 'static> InboundUpgrade<Socket> for StreamUpgrade {
+    type Output = (Socket, &'static [u8]);
+    type Error = io::Error;
+    type Future = future::Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: Socket, info: Self::Info) -> Self::Future {
+        future::ok((socket, info))
+    }
+}
+
+impl<Socket: Send + 'static> OutboundUpgrade<Socket> for StreamUpgrade {
+    type Output = (Socket, &'static [u8]);
+    type Error = io::Error;
+    type Future = future::Ready<Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: Socket, info: Self::Info) -> Self::Future {
+        future::ok((socket, info))
+    }
+}
+
+enum Outbound {
+    /// There's no outbound stream, it will be opened once there's something to send
+    Closed,
+    /// Outbound substream was requested, waiting for it to be negotiated
+    Opening,
+    /// Stream is open and ready to send messages
+    Idle(NegotiatedSubstream, WireFormat),
+    /// Message is being written to the stream
+    Sending(
+        BoxFuture<'static, io::Result<NegotiatedSubstream>>,
+        WireFormat,
+    ),
+}
+
+/// Keeps a single long-lived outbound stream to the remote peer, sending messages
+/// one by one in the order they were submitted. Reads messages from all inbound streams.
+/// Connection is kept alive while there are open particle streams.
+///
+/// Behaviour is told how many messages left `send_queue` via `HandlerMessage::SendQueueReady`,
+/// so it can hold messages back instead of overflowing the queue.
+pub struct ParticleHandler {
+    config: ProtocolConfig,
+    /// Messages waiting to be sent, bounded by `ProtocolConfig::send_queue_size`
+    send_queue: VecDeque<HandlerMessage>,
+    /// Number of messages that left `send_queue` since it was last reported to the behaviour
+    dequeued: usize,
+    /// Ids of particles dropped because `send_queue` was full, to be reported to the behaviour
+    dropped: VecDeque<String>,
+    outbound: Outbound,
+    /// Writes to single message substreams, used if remote doesn't support streams
+    oneshot_writes: FuturesUnordered<BoxFuture<'static, ()>>,
+    /// Messages from all inbound substreams
+    inbound: SelectAll<BoxStream<'static, HandlerMessage>>,
+    keep_alive: KeepAlive,
+    /// Set when connection was requested to be closed
+    disconnecting: bool,
+}
+
+impl ParticleHandler {
+    pub fn new(config: ProtocolConfig) -> Self {
+        Self {
+            config,
+            send_queue: <_>::default(),
+            dequeued: 0,
+            dropped: <_>::default(),
+            outbound: Outbound::Closed,
+            oneshot_writes: <_>::default(),
+            inbound: SelectAll::new(),
+            keep_alive: KeepAlive::Yes,
+            disconnecting: false,
+        }
+    }
+
+    fn upgrade(&self) -> StreamUpgrade {
+        StreamUpgrade {
+            wire_format: self.config.wire_format,
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        let sending = matches!(self.outbound, Outbound::Opening | Outbound::Sending(..));
+        sending || !self.send_queue.is_empty() || !self.oneshot_writes.is_empty()
+    }
+
+    fn has_streams(&self) -> bool {
+        let outbound = matches!(self.outbound, Outbound::Idle(..) | Outbound::Sending(..));
+        outbound || !self.inbound.is_empty()
+    }
+
+    /// Connection without particle streams and messages to send is closed after
+    /// `keep_alive_timeout`, otherwise it's kept alive until disconnect is requested
+    fn update_keep_alive(&mut self) {
+        if self.is_busy() || self.has_streams() {
+            self.keep_alive = KeepAlive::Yes;
+        } else if self.keep_alive.is_yes() {
+            self.keep_alive = KeepAlive::Until(Instant::now() + self.config.keep_alive_timeout);
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<HandlerMessage> {
+        let msg = self.send_queue.pop_front();
+        if msg.is_some() {
+            self.dequeued += 1;
+        }
+        msg
+    }
+
+    fn fail_queued(&mut self) {
+        self.dequeued += self.send_queue.len();
+        for msg in self.send_queue.drain(..) {
+            complete(msg, false)
+        }
+    }
+}
+
+/// Signal message completion to the sender, if it is waiting for it
+fn complete(msg: HandlerMessage, success: bool) {
+    let (_, channel) = msg.into();
+    if let Some(channel) = channel {
+        // it's ok to ignore error here: inlet might be dropped any time
+        channel.send(success).ok();
+    }
+}
+
+/// Writes length-prefixed message to the stream, returns the stream back so it can be reused
+fn send_frame<Socket>(
+    mut socket: Socket,
+    wire_format: WireFormat,
+    msg: HandlerMessage,
+) -> BoxFuture<'static, io::Result<Socket>>
+where
+    Socket: AsyncWrite + Send + Unpin + 'static,
+{
+    async move {
+        let (msg, channel) = msg.into();
+        log::debug!("Sending ProtocolMessage: {:?}", msg);
+
+        let result = match wire_format.encode(&msg) {
+            Ok(bytes) => upgrade::write_with_len_prefix(&mut socket, bytes).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &result {
+            log::warn!("Error sending ProtocolMessage: {:?}", err);
+        }
+        if let Some(channel) = channel {
+            channel.send(result.is_ok()).ok();
+        }
+
+        result.map(|_| socket)
+    }
+    .boxed()
+}
+
+/// Reads length-prefixed messages until the stream is closed.
+/// Messages that can't be decoded are reported as `InboundUpgradeError`
+fn read_frames<Socket>(
+    socket: Socket,
+    wire_format: WireFormat,
+    config: ProtocolConfig,
+) -> BoxStream<'static, HandlerMessage>
+where
+    Socket: AsyncRead + Send + Unpin + 'static,
+{
+    stream::unfold(socket, move |mut socket| {
+        let config = config.clone();
+        async move {
+            let packet = match upgrade::read_one(&mut socket, MAX_BUF_SIZE).await {
+                // read_one yields an empty packet on EOF, and messages are never empty
+                Ok(packet) if packet.is_empty() => {
+                    log::debug!("Inbound particle stream closed by remote");
+                    return None;
+                }
+                Ok(packet) => packet,
+                Err(err) => {
+                    log::debug!("Inbound particle stream closed: {}", err);
+                    return None;
+                }
+            };
+            let msg = match wire_format.decode(&packet) {
+                Ok(msg) => {
+                    log::debug!("Got inbound ProtocolMessage: {:?}", msg);
+                    msg.into()
+                }
+                Err(err) => {
+                    log::warn!("Error processing inbound ProtocolMessage: {:?}", err);
+                    config.gen_error(err)
+                }
+            };
+
+            Some((msg, socket))
+        }
+    })
+    .boxed()
+}
+
+impl ProtocolsHandler for ParticleHandler {
+    type InEvent = HandlerMessage;
+    type OutEvent = HandlerMessage;
+    type Error = io::Error;
+    type InboundProtocol = StreamUpgrade;
+    type OutboundProtocol = StreamUpgrade;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, Self::InboundOpenInfo> {
+        SubstreamProtocol::new(self.upgrade(), ()).with_timeout(self.config.upgrade_timeout)
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        (socket, info): (NegotiatedSubstream, &'static [u8]),
+        _: Self::InboundOpenInfo,
+    ) {
+        if is_stream(info) {
+            let wire_format = WireFormat::from_protocol(info);
+            let frames = read_frames(socket, wire_format, self.config.clone());
+            self.inbound.push(frames);
+        } else {
+            // substream carries a single message, read it as before streams were introduced
+            let read = self.config.clone().upgrade_inbound(socket, info);
+            let read = stream::once(read).filter_map(|r| future::ready(r.ok()));
+            self.inbound.push(read.boxed());
+        }
+        self.update_keep_alive();
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        (socket, info): (NegotiatedSubstream, &'static [u8]),
+        _: Self::OutboundOpenInfo,
+    ) {
+        if is_stream(info) {
+            self.outbound = Outbound::Idle(socket, WireFormat::from_protocol(info));
+        } else {
+            // remote doesn't support streams, so spend this substream on a single message
+            self.outbound = Outbound::Closed;
+            if let Some(msg) = self.dequeue() {
+                let write = msg.upgrade_outbound(socket, info).map(|_| ());
+                self.oneshot_writes.push(write.boxed());
+            }
+        }
+    }
+
+    fn inject_event(&mut self, msg: HandlerMessage) {
+        if let HandlerMessage::Disconnect = msg {
+            self.disconnecting = true;
+            self.fail_queued();
+            return;
+        }
+
+        if self.send_queue.len() >= self.config.send_queue_size {
+            log::warn!(
+                "Send queue is full ({} messages), dropping message",
+                self.send_queue.len()
+            );
+            if let HandlerMessage::OutParticle(particle, ..) = &msg {
+                self.dropped.push_back(particle.id.clone());
+            }
+            complete(msg, false);
+            return;
+        }
+
+        self.send_queue.push_back(msg);
+        self.update_keep_alive();
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        _: Self::OutboundOpenInfo,
+        err: ProtocolsHandlerUpgrErr<io::Error>,
+    ) {
+        log::warn!("Failed to open outbound particle stream: {}", err);
+        self.outbound = Outbound::Closed;
+        // remote is either unresponsive or doesn't support the protocol
+        self.fail_queued();
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.keep_alive
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<
+        ProtocolsHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::OutEvent,
+            Self::Error,
+        >,
+    > {
+        if self.disconnecting {
+            let err = io::Error::new(io::ErrorKind::ConnectionAborted, "disconnect requested");
+            return Poll::Ready(ProtocolsHandlerEvent::Close(err));
+        }
+
+        loop {
+            match mem::replace(&mut self.outbound, Outbound::Closed) {
+                Outbound::Closed if !self.send_queue.is_empty() => {
+                    self.outbound = Outbound::Opening;
+                    let protocol = SubstreamProtocol::new(self.upgrade(), ())
+                        .with_timeout(self.config.outbound_substream_timeout);
+                    return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                        protocol,
+                    });
+                }
+                Outbound::Idle(socket, wire_format) => match self.dequeue() {
+                    Some(msg) => {
+                        let write = send_frame(socket, wire_format, msg);
+                        self.outbound = Outbound::Sending(write, wire_format);
+                    }
+                    None => {
+                        self.outbound = Outbound::Idle(socket, wire_format);
+                        break;
+                    }
+                },
+                Outbound::Sending(mut write, wire_format) => match write.poll_unpin(cx) {
+                    Poll::Ready(Ok(socket)) => self.outbound = Outbound::Idle(socket, wire_format),
+                    // stream is broken, it will be reopened for the next message
+                    Poll::Ready(Err(_)) => self.outbound = Outbound::Closed,
+                    Poll::Pending => {
+                        self.outbound = Outbound::Sending(write, wire_format);
+                        break;
+                    }
+                },
+                other => {
+                    self.outbound = other;
+                    break;
+                }
+            }
+        }
+
+        while let Poll::Ready(Some(())) = self.oneshot_writes.poll_next_unpin(cx) {}
+        self.update_keep_alive();
+
+        if let Some(particle_id) = self.dropped.pop_front() {
+            let msg = HandlerMessage::SendQueueFull(particle_id);
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(msg));
+        }
+
+        if self.dequeued > 0 {
+            let msg = HandlerMessage::SendQueueReady(mem::take(&mut self.dequeued));
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(msg));
+        }
+
+        let inbound = self.inbound.poll_next_unpin(cx);
+        // finished inbound streams are removed by poll
+        self.update_keep_alive();
+        if let Poll::Ready(Some(msg)) = inbound {
+            return Poll::Ready(ProtocolsHandlerEvent::Custom(msg));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_frames, send_frame, ParticleHandler};
+    use crate::{CompletionChannel, HandlerMessage, Particle, ProtocolConfig, WireFormat};
+    use futures::{channel::oneshot, executor::block_on, io::Cursor, task, StreamExt};
+    use libp2p::swarm::{ProtocolsHandler, ProtocolsHandlerEvent, ProtocolsHandlerUpgrErr};
+    use std::task::{Context, Poll};
+
+    #[test]
+    fn frames_roundtrip() {
+        for wire_format in [WireFormat::Json, WireFormat::Cbor].iter().copied() {
+            let particles: Vec<_> = (0..3)
+                .map(|i| Particle {
+                    id: i.to_string(),
+                    data: vec![i; 10],
+                    ..<_>::default()
+                })
+                .collect();
+
+            let mut socket = Cursor::new(vec![]);
+            for particle in particles.iter() {
+                let msg = HandlerMessage::OutParticle(
+                    particle.clone(),
+                    CompletionChannel::Ignore,
+                    wire_format,
+                );
+                socket = block_on(send_frame(socket, wire_format, msg)).expect("send frame");
+            }
+
+            socket.set_position(0);
+            let config = ProtocolConfig::default();
+            let received: Vec<_> = block_on(read_frames(socket, wire_format, config).collect());
+            let received: Vec<_> = received
+                .into_iter()
+                .map(|msg| match msg {
+                    HandlerMessage::InParticle(particle) => particle,
+                    other => panic!("expected InParticle, got {:?}", other),
+                })
+                .collect();
+
+            assert_eq!(received, particles);
+        }
+    }
+
+    #[test]
+    fn full_send_queue_is_reported() {
+        let config = ProtocolConfig {
+            send_queue_size: 1,
+            ..<_>::default()
+        };
+        let mut handler = ParticleHandler::new(config);
+        let mut send = |id: &str| {
+            let (outlet, inlet) = oneshot::channel();
+            let particle = Particle {
+                id: id.to_string(),
+                ..<_>::default()
+            };
+            let channel = CompletionChannel::Oneshot(outlet);
+            handler.inject_event(HandlerMessage::OutParticle(
+                particle,
+                channel,
+                <_>::default(),
+            ));
+            inlet
+        };
+        let queued = send("queued");
+        let dropped = send("dropped");
+
+        // sender is notified right away
+        assert_eq!(block_on(dropped), Ok(false));
+
+        // behaviour is notified once outbound stream is requested for the queued particle
+        let waker = task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        match handler.poll(&mut cx) {
+            Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. }) => {}
+            _ => panic!("expected outbound substream request"),
+        }
+        match handler.poll(&mut cx) {
+            Poll::Ready(ProtocolsHandlerEvent::Custom(HandlerMessage::SendQueueFull(id))) => {
+                assert_eq!(id, "dropped")
+            }
+            _ => panic!("expected SendQueueFull"),
+        }
+
+        // queued particle fails along with the stream, and its place in the queue is reported
+        handler.inject_dial_upgrade_error((), ProtocolsHandlerUpgrErr::Timeout);
+        assert_eq!(block_on(queued), Ok(false));
+        match handler.poll(&mut cx) {
+            Poll::Ready(ProtocolsHandlerEvent::Custom(HandlerMessage::SendQueueReady(n))) => {
+                assert_eq!(n, 1)
+            }
+            _ => panic!("expected SendQueueReady"),
+        }
+    }
+}
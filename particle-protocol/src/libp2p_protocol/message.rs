@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 
+use crate::libp2p_protocol::codec::WireFormat;
 use crate::Particle;
 use fluence_libp2p::types::OneshotOutlet;
 use serde::{Deserialize, Serialize};
@@ -41,16 +42,24 @@ impl Default for CompletionChannel {
 
 #[derive(Debug)]
 pub enum HandlerMessage {
-    /// Particle being sent to remote peer. Contains a channel to signal write completion.
-    /// Send-only, can't be received.
-    OutParticle(Particle, CompletionChannel),
+    /// Particle being sent to remote peer. Contains a channel to signal write completion,
+    /// and a preferred wire format. Send-only, can't be received.
+    OutParticle(Particle, CompletionChannel, WireFormat),
     /// Particle being received from a remote peer.
     /// Receive-only, can't be sent.
     InParticle(Particle),
     /// Error while receiving a message
     InboundUpgradeError(serde_json::Value),
-    /// Dummy plug. Generated by the `OneshotHandler` when Inbound or Outbound Upgrade happened.
+    /// Dummy plug, never sent by `ParticleHandler`. Kept for wire compatibility.
     Upgrade,
+    /// Asks the handler to close its connection. Never goes to the wire.
+    Disconnect,
+    /// Particle with that id was dropped by the handler, as its send queue was full.
+    /// Reported to the behaviour, never goes to the wire.
+    SendQueueFull(String),
+    /// That many messages left the handler's send queue, so there's room for as many new ones.
+    /// Reported to the behaviour, never goes to the wire.
+    SendQueueReady(usize),
 }
 
 // impl Default for ProtocolMessage {
@@ -59,14 +68,16 @@ pub enum HandlerMessage {
 //     }
 // }
 
-// Required by OneShotHandler in inject_fully_negotiated_outbound. And that's because
-// <ProtocolMessage as UpgradeOutbound>::Output is (), and OneshotHandler requires it to be
-// convertible to OneshotHandler::TEvent which is a ProtocolMessage
-impl From<()> for HandlerMessage {
-    fn from(_: ()) -> HandlerMessage {
-        HandlerMessage::Upgrade
+impl HandlerMessage {
+    /// Wire format to propose first when sending this message
+    pub fn wire_format(&self) -> WireFormat {
+        match self {
+            HandlerMessage::OutParticle(_, _, wire_format) => *wire_format,
+            _ => WireFormat::default(),
+        }
     }
 }
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "action")]
 pub enum ProtocolMessage {
@@ -80,7 +91,7 @@ pub enum ProtocolMessage {
 impl From<HandlerMessage> for (ProtocolMessage, Option<OneshotOutlet<bool>>) {
     fn from(msg: HandlerMessage) -> (ProtocolMessage, Option<OneshotOutlet<bool>>) {
         match msg {
-            HandlerMessage::OutParticle(particle, channel) => {
+            HandlerMessage::OutParticle(particle, channel, _) => {
                 (ProtocolMessage::Particle(particle), channel.outlet())
             }
             HandlerMessage::InboundUpgradeError(err) => {
@@ -90,6 +101,9 @@ impl From<HandlerMessage> for (ProtocolMessage, Option<OneshotOutlet<bool>>) {
             HandlerMessage::InParticle(_) => {
                 unreachable!("InParticle is never sent, only received")
             }
+            HandlerMessage::Disconnect => unreachable!("Disconnect is never sent"),
+            HandlerMessage::SendQueueFull(_) => unreachable!("SendQueueFull is never sent"),
+            HandlerMessage::SendQueueReady(_) => unreachable!("SendQueueReady is never sent"),
         }
     }
 }
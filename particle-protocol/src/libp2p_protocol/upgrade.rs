@@ -14,20 +14,18 @@
  * limitations under the License.
  */
 
+use crate::libp2p_protocol::codec::WireFormat;
+use crate::libp2p_protocol::handler::ParticleHandler;
 use crate::HandlerMessage;
 
 use futures::{future::BoxFuture, AsyncRead, AsyncWrite, AsyncWriteExt, FutureExt};
-use libp2p::{
-    core::{upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo},
-    swarm::{protocols_handler, OneShotHandler},
-};
+use libp2p::core::{upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use serde::Deserialize;
 use serde_json::json;
-use std::{io, iter, time::Duration};
+use std::{io, time::Duration};
 
 use crate::libp2p_protocol::message::ProtocolMessage;
 pub use failure::Error;
-use libp2p::swarm::OneShotHandlerConfig;
 use log::LevelFilter;
 use std::fmt::Debug;
 
@@ -38,12 +36,24 @@ pub struct ProtocolConfig {
     /// Timeout for applying the given upgrade on a substream
     #[serde(with = "humantime_serde")]
     pub upgrade_timeout: Duration,
-    /// Keep-alive timeout for idle connections.
+    /// Keep-alive timeout for connections without particle streams.
     #[serde(with = "humantime_serde")]
     pub keep_alive_timeout: Duration,
     /// Timeout for outbound substream upgrades.
     #[serde(with = "humantime_serde")]
     pub outbound_substream_timeout: Duration,
+    /// Encoding proposed first when sending messages, falls back to the other one
+    #[serde(default)]
+    pub wire_format: WireFormat,
+    /// Max number of messages waiting to be sent over a single connection.
+    /// Connection pool holds messages back until there's room, up to the same number per peer;
+    /// messages over it are dropped
+    #[serde(default = "default_send_queue_size")]
+    pub send_queue_size: usize,
+}
+
+fn default_send_queue_size() -> usize {
+    1024
 }
 
 impl Default for ProtocolConfig {
@@ -52,6 +62,8 @@ impl Default for ProtocolConfig {
             upgrade_timeout: Duration::from_secs(10),
             keep_alive_timeout: Duration::from_secs(10),
             outbound_substream_timeout: Duration::from_secs(10),
+            wire_format: WireFormat::default(),
+            send_queue_size: default_send_queue_size(),
         }
     }
 }
@@ -66,49 +78,42 @@ impl ProtocolConfig {
             upgrade_timeout,
             keep_alive_timeout,
             outbound_substream_timeout,
+            wire_format: WireFormat::default(),
+            send_queue_size: default_send_queue_size(),
         }
     }
 
-    fn gen_error(&self, err: impl Debug) -> HandlerMessage {
+    pub(super) fn gen_error(&self, err: impl Debug) -> HandlerMessage {
         HandlerMessage::InboundUpgradeError(json!({ "error": format!("{:?}", err) }))
     }
 }
 
-impl<OutProto: protocols_handler::OutboundUpgradeSend, OutEvent> From<ProtocolConfig>
-    for OneShotHandler<ProtocolConfig, OutProto, OutEvent>
-{
-    fn from(item: ProtocolConfig) -> OneShotHandler<ProtocolConfig, OutProto, OutEvent> {
-        OneShotHandler::new(
-            protocols_handler::SubstreamProtocol::new(item.clone(), ())
-                .with_timeout(item.upgrade_timeout),
-            OneShotHandlerConfig {
-                keep_alive_timeout: item.keep_alive_timeout,
-                outbound_substream_timeout: item.outbound_substream_timeout,
-            },
-        )
+impl From<ProtocolConfig> for ParticleHandler {
+    fn from(item: ProtocolConfig) -> ParticleHandler {
+        ParticleHandler::new(item)
     }
 }
 
 // 100 Mb
 #[allow(clippy::identity_op)]
-const MAX_BUF_SIZE: usize = 100 * 1024 * 1024;
-const PROTOCOL_INFO: &[u8] = b"/fluence/faas/1.0.0";
+pub(super) const MAX_BUF_SIZE: usize = 100 * 1024 * 1024;
 
 macro_rules! impl_upgrade_info {
-    ($tname:ident) => {
+    ($tname:ident, $this:ident => $wire_format:expr) => {
         impl UpgradeInfo for $tname {
             type Info = &'static [u8];
-            type InfoIter = iter::Once<Self::Info>;
+            type InfoIter = std::vec::IntoIter<Self::Info>;
 
             fn protocol_info(&self) -> Self::InfoIter {
-                iter::once(PROTOCOL_INFO)
+                let $this = self;
+                $wire_format.protocols().into_iter()
             }
         }
     };
 }
 
-impl_upgrade_info!(ProtocolConfig);
-impl_upgrade_info!(HandlerMessage);
+impl_upgrade_info!(ProtocolConfig, this => this.wire_format);
+impl_upgrade_info!(HandlerMessage, this => this.wire_format());
 
 impl<Socket> InboundUpgrade<Socket> for ProtocolConfig
 where
@@ -120,14 +125,24 @@ where
 
     fn upgrade_inbound(self, mut socket: Socket, info: Self::Info) -> Self::Future {
         async move {
+            let wire_format = WireFormat::from_protocol(info);
             let process = async move |socket| -> Result<ProtocolMessage, Error> {
                 let packet = upgrade::read_one(socket, MAX_BUF_SIZE).await?;
-                match std::str::from_utf8(&packet) {
-                    Ok(str) => log::debug!("Got inbound ProtocolMessage: {}", str),
-                    Err(err) => log::warn!("Can't parse inbound ProtocolMessage to UTF8 {}", err),
+                if wire_format == WireFormat::Json {
+                    match std::str::from_utf8(&packet) {
+                        Ok(str) => log::debug!("Got inbound ProtocolMessage: {}", str),
+                        Err(err) => {
+                            log::warn!("Can't parse inbound ProtocolMessage to UTF8 {}", err)
+                        }
+                    }
+                }
+
+                let msg = wire_format.decode(&packet)?;
+                if wire_format == WireFormat::Cbor {
+                    log::debug!("Got inbound ProtocolMessage: {:?}", msg);
                 }
 
-                Ok(serde_json::from_slice(&packet)?)
+                Ok(msg)
             };
 
             match process(&mut socket).await {
@@ -156,8 +171,9 @@ where
     type Error = io::Error;
     type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
 
-    fn upgrade_outbound(self, mut socket: Socket, _: Self::Info) -> Self::Future {
+    fn upgrade_outbound(self, mut socket: Socket, info: Self::Info) -> Self::Future {
         async move {
+            let wire_format = WireFormat::from_protocol(info);
             let (msg, channel) = self.into();
 
             if log::max_level() >= LevelFilter::Debug {
@@ -168,7 +184,7 @@ where
             }
 
             let write = async move || -> Result<_, io::Error> {
-                let bytes = serde_json::to_vec(&msg)?;
+                let bytes = wire_format.encode(&msg)?;
                 upgrade::write_one(&mut socket, bytes).await?;
                 Ok(())
             };
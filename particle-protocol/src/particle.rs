@@ -18,10 +18,19 @@ use fluence_libp2p::{peerid_serializer, RandomPeerId};
 use json_utils::base64_serde;
 
 use derivative::Derivative;
-use libp2p::PeerId;
+use libp2p::{identity::ed25519::Keypair, PeerId};
 use now_millis::now_ms;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("Can't verify particle signature: init_peer_id {0} doesn't contain public key")]
+    NoPublicKey(PeerId),
+    #[error("Particle signature doesn't match init_peer_id {0}")]
+    InvalidSignature(PeerId),
+}
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Derivative)]
 #[derivative(Debug)]
@@ -69,6 +78,43 @@ impl Particle {
         self.timestamp.checked_add(self.ttl as u64)
     }
 
+    /// Canonical encoding of the signed fields: id, init_peer_id, timestamp, ttl and script.
+    /// Variable-length fields are prefixed with their length, so the encoding is unambiguous
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        fn with_len(bytes: &mut Vec<u8>, field: &[u8]) {
+            bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(field);
+        }
+
+        let mut bytes = Vec::with_capacity(self.id.len() + self.script.len() + 64);
+        with_len(&mut bytes, self.id.as_bytes());
+        with_len(&mut bytes, &self.init_peer_id.to_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes.extend_from_slice(&self.ttl.to_le_bytes());
+        with_len(&mut bytes, self.script.as_bytes());
+
+        bytes
+    }
+
+    /// Sign particle with the key of the `init_peer_id`
+    pub fn sign(&mut self, key_pair: &Keypair) {
+        self.signature = key_pair.sign(&self.signed_bytes());
+    }
+
+    /// Verify particle signature against the public key embedded in `init_peer_id`
+    pub fn verify(&self) -> Result<(), SignatureError> {
+        let public_key = self
+            .init_peer_id
+            .as_public_key()
+            .ok_or(SignatureError::NoPublicKey(self.init_peer_id))?;
+
+        if public_key.verify(&self.signed_bytes(), &self.signature) {
+            Ok(())
+        } else {
+            Err(SignatureError::InvalidSignature(self.init_peer_id))
+        }
+    }
+
     pub fn time_to_live(&self) -> Duration {
         if let Some(ttl) = self.deadline().and_then(|d| d.checked_sub(now_ms() as u64)) {
             Duration::from_millis(ttl)
@@ -82,3 +128,38 @@ impl Particle {
 fn fmt_data(data: &Vec<u8>, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
     write!(f, "{}", bs58::encode(data).into_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::Particle;
+    use libp2p::identity::{ed25519::Keypair, PublicKey};
+    use libp2p::PeerId;
+
+    #[test]
+    fn sign_verify() {
+        let key_pair = Keypair::generate();
+        let mut particle = Particle {
+            id: "123".to_string(),
+            init_peer_id: PeerId::from(PublicKey::Ed25519(key_pair.public())),
+            timestamp: 1000,
+            ttl: 100,
+            script: "(null)".to_string(),
+            ..<_>::default()
+        };
+        assert!(particle.verify().is_err());
+
+        particle.sign(&key_pair);
+        assert!(particle.verify().is_ok());
+
+        // data isn't signed, it's changed on each hop
+        particle.data = vec![1, 2, 3];
+        assert!(particle.verify().is_ok());
+
+        particle.ttl = 200;
+        assert!(particle.verify().is_err());
+
+        // signature by another key
+        particle.sign(&Keypair::generate());
+        assert!(particle.verify().is_err());
+    }
+}
@@ -45,9 +45,8 @@ pub struct Provider {
 }
 
 #[derive(Debug)]
-/// Thread-safe storage of providers
-// TODO: remove on TTL?
-// TODO: disseminate
+/// Thread-safe node-local storage of providers, records never expire and aren't replicated.
+/// Kept for the deprecated builtins, see `kademlia::ProviderRecord` for providers in the DHT
 pub struct ProviderRepository {
     #[allow(dead_code)]
     /// peer id is useful for debug purposes
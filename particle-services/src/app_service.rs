@@ -15,14 +15,18 @@
  */
 
 use crate::error::ServiceError;
+use crate::error::ServiceError::MemoryLimitExceeded;
 use crate::persistence::{persist_service, PersistedService};
 use crate::Result;
 
-use particle_modules::ModuleRepository;
-use server_config::ServicesConfig;
+use particle_modules::{extract_module_file_name, ModuleRepository};
+use server_config::{ServiceLimits, ServicesConfig};
 
 use fluence_app_service::{AppService, AppServiceConfig, FaaSConfig};
+use std::collections::HashMap;
+use std::path::Path;
 
+/// Creates `AppService` from the blueprint, returns it along with limits it must be run with
 pub fn create_app_service(
     config: ServicesConfig,
     modules: &ModuleRepository,
@@ -30,9 +34,40 @@ pub fn create_app_service(
     service_id: String,
     aliases: Vec<String>,
     owner_id: String,
-) -> Result<AppService> {
+    envs: HashMap<String, String>,
+) -> Result<(AppService, ServiceLimits)> {
     try {
-        let modules_config = modules.resolve_blueprint(&blueprint_id)?;
+        let (mut modules_config, limits) = modules.resolve_blueprint(&blueprint_id)?;
+        let limits = config.limits.narrow(&limits);
+
+        if let Some(limit) = limits.max_memory_pages {
+            for module in modules_config.iter_mut() {
+                let pages = &mut module.config.mem_pages_count;
+                match *pages {
+                    Some(requested) if requested > limit => {
+                        return Err(MemoryLimitExceeded {
+                            module: module.import_name.clone(),
+                            requested,
+                            limit,
+                        });
+                    }
+                    Some(_) => {}
+                    None => *pages = Some(limit),
+                }
+            }
+        }
+
+        // Each module sees only envs granted to it. Envs from the node config take precedence
+        // over the owner's ones, and global `config.envs` are added to every module by AppService
+        for module in modules_config.iter_mut() {
+            let hash = extract_module_file_name(Path::new(&module.file_name));
+            let scoped = config.scoped_envs.module_envs(&blueprint_id, hash);
+            let owner = envs.iter();
+            let owner = owner.map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()));
+            module
+                .config
+                .extend_wasi_envs(owner.chain(scoped).collect());
+        }
 
         let modules = AppServiceConfig {
             service_base_dir: config.workdir,
@@ -49,9 +84,9 @@ pub fn create_app_service(
             .map_err(ServiceError::Engine)?;
 
         // Save created service to disk, so it is recreated on restart
-        let persisted = PersistedService::new(service_id, blueprint_id, aliases, owner_id);
+        let persisted = PersistedService::new(service_id, blueprint_id, aliases, owner_id, envs);
         persist_service(&config.services_dir, persisted)?;
 
-        service
+        (service, limits)
     }
 }
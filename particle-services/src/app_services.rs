@@ -15,6 +15,9 @@
  */
 
 use std::ops::Deref;
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, sync::Arc};
 
 use fluence_app_service::{AppService, CallParameters, ServiceInterface};
@@ -24,21 +27,37 @@ use serde_json::{json, Value as JValue};
 
 use host_closure::{closure, closure_params, closure_params_opt, Args, Closure, ParticleClosure};
 use particle_modules::ModuleRepository;
-use server_config::ServicesConfig;
+use server_config::{ServiceLimits, ServicesConfig};
 
 use crate::app_service::create_app_service;
 use crate::error::ServiceError;
-use crate::error::ServiceError::{AliasAsServiceId, Forbidden};
-use crate::persistence::{load_persisted_services, persist_service, PersistedService};
+use crate::error::ServiceError::{
+    AliasAsServiceId, CallTimeout, DiskQuotaExceeded, Forbidden, RemoveServiceWorkdir,
+    ServiceBroken, ServiceBusy, UpgradeConflict,
+};
+use crate::persistence::{
+    load_persisted_services, persist_service, remove_persisted_service, PersistedService,
+};
 
 type Services = Arc<RwLock<HashMap<String, Service>>>;
 type Aliases = Arc<RwLock<HashMap<String, String>>>;
 
+/// Service workdir is measured at most that often, as it requires walking the whole workdir
+const DISK_USAGE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct Service {
-    pub service: Mutex<AppService>,
+    /// None while a timed out call is still running on its own thread,
+    /// service is put back once that call finishes
+    pub service: Arc<Mutex<Option<AppService>>>,
     pub blueprint_id: String,
     pub owner_id: String,
     pub aliases: Vec<String>,
+    /// Resources service calls are allowed to consume
+    pub limits: ServiceLimits,
+    /// Envs set by the owner on creation
+    pub envs: HashMap<String, String>,
+    /// Size of the service workdir, checked against `limits.max_disk_bytes`
+    pub disk_usage: Mutex<DiskUsage>,
 }
 
 impl Service {
@@ -51,8 +70,29 @@ impl Service {
     }
 }
 
+/// Last measured size of the service workdir
+#[derive(Debug, Default)]
+pub struct DiskUsage {
+    bytes: u64,
+    measured_at: Option<Instant>,
+}
+
+impl DiskUsage {
+    /// Returns size of the workdir, measuring it again if the last measurement is outdated
+    fn get(&mut self, workdir: &Path) -> u64 {
+        let outdated = self
+            .measured_at
+            .map_or(true, |at| at.elapsed() >= DISK_USAGE_CHECK_INTERVAL);
+        if outdated {
+            self.bytes = dir_size(workdir);
+            self.measured_at = Some(Instant::now());
+        }
+        self.bytes
+    }
+}
+
 impl Deref for Service {
-    type Target = Mutex<AppService>;
+    type Target = Mutex<Option<AppService>>;
 
     fn deref(&self) -> &Self::Target {
         &self.service
@@ -98,22 +138,28 @@ impl ParticleAppServices {
 
         closure_params(move |particle, args| {
             let service_id = uuid::Uuid::new_v4().to_string();
-            let blueprint_id: String =
-                Args::next("blueprint_id", &mut args.function_args.into_iter())?;
+            let mut args = args.function_args.into_iter();
+            let blueprint_id: String = Args::next("blueprint_id", &mut args)?;
+            let envs: Option<HashMap<String, String>> = Args::maybe_next("envs", &mut args)?;
+            let envs = envs.unwrap_or_default();
 
-            let service = create_app_service(
+            let (service, limits) = create_app_service(
                 config.clone(),
                 &modules,
                 blueprint_id.clone(),
                 service_id.clone(),
                 vec![],
                 particle.init_user_id.clone(),
+                envs.clone(),
             )?;
             let service = Service {
-                service: Mutex::new(service),
+                service: Arc::new(Mutex::new(Some(service))),
                 blueprint_id,
                 owner_id: particle.init_user_id,
                 aliases: vec![],
+                limits,
+                envs,
+                disk_usage: <_>::default(),
             };
 
             services.write().insert(service_id.clone(), service);
@@ -122,10 +168,17 @@ impl ParticleAppServices {
         })
     }
 
+    /// Calls service function, enforcing service limits.
+    /// Waiting for the service to finish previous calls is bound by `lock_timeout`.
+    /// Call that takes longer than `call_timeout` can't be interrupted, so it's left running
+    /// on its own thread, and the service is unavailable until that call finishes.
+    /// Service isn't recreated meanwhile, so there's at most one abandoned call per service,
+    /// and no two instances of the service share its workdir.
     pub fn call_service(&self) -> ParticleClosure {
         let services = self.services.clone();
         let aliases = self.aliases.clone();
         let host_id = self.config.local_peer_id.to_string();
+        let workdir = self.config.workdir.clone();
 
         closure_params(move |particle_params, args| {
             let result: eyre::Result<_> = try {
@@ -142,23 +195,78 @@ impl ParticleAppServices {
                     })
                     .ok_or_else(|| ServiceError::NoSuchService(args.service_id.clone()))?;
 
+                let limits = &service.limits;
+                if let Some(quota) = limits.max_disk_bytes {
+                    let used = service.disk_usage.lock().get(&workdir.join(&id));
+                    if used > quota {
+                        Err(DiskQuotaExceeded {
+                            service_id: id.clone(),
+                            used,
+                            quota,
+                        })?
+                    }
+                }
+
+                let mut app_service = match limits.lock_timeout {
+                    Some(timeout) => service.try_lock_for(timeout).ok_or_else(|| ServiceBusy {
+                        service_id: id.clone(),
+                        timeout,
+                    })?,
+                    None => service.lock(),
+                };
+
                 let params = CallParameters {
                     host_id: host_id.clone(),
                     init_peer_id: particle_params.init_user_id,
                     particle_id: particle_params.particle_id,
                     tetraplets: args.tetraplets,
-                    service_id: id,
+                    service_id: id.clone(),
                     service_creator_peer_id: service.owner_id.clone(),
                 };
 
-                let mut service = service.lock();
-                service
-                    .call(
-                        args.function_name,
-                        JValue::Array(args.function_args),
+                let function_name = args.function_name;
+                let function_args = JValue::Array(args.function_args);
+                let broken = || ServiceBroken {
+                    service_id: id.clone(),
+                };
+                let result = match limits.call_timeout {
+                    None => app_service.as_mut().ok_or_else(broken)?.call(
+                        function_name,
+                        function_args,
                         params,
-                    )
-                    .map_err(ServiceError::Engine)?
+                    ),
+                    Some(timeout) => {
+                        let taken = app_service.take().ok_or_else(broken)?;
+                        let call =
+                            move |s: &mut AppService| s.call(function_name, function_args, params);
+                        let slot = service.service.clone();
+                        let service_id = id.clone();
+                        let finished_late = move |s| {
+                            let mut slot = slot.lock();
+                            // service could be restarted or upgraded meanwhile, keep the new one
+                            if slot.is_none() {
+                                *slot = Some(s);
+                                log::info!("Timed out call to service {} finished", service_id);
+                            }
+                        };
+                        match call_with_timeout(taken, timeout, call, finished_late) {
+                            Some((taken, result)) => {
+                                *app_service = Some(taken);
+                                result
+                            }
+                            None => {
+                                #[rustfmt::skip]
+                                log::warn!("Call to service {} didn't finish in {:?}, service is unavailable until it does", id, timeout);
+                                Err(CallTimeout {
+                                    service_id: id.clone(),
+                                    timeout,
+                                })?
+                            }
+                        }
+                    }
+                };
+
+                result.map_err(ServiceError::Engine)?
             };
 
             result.map_err(|err| {
@@ -173,6 +281,194 @@ impl ParticleAppServices {
         })
     }
 
+    /// Removes service by its id or alias, along with its aliases, workdir and persisted info.
+    /// Only the owner of the service or the management peer are allowed to remove it.
+    pub fn remove_service(&self) -> ParticleClosure {
+        let services = self.services.clone();
+        let aliases = self.aliases.clone();
+        let config = self.config.clone();
+        let management_peer_id = self.management_peer_id.clone();
+
+        closure_params_opt(move |particle, args| {
+            let service_id: String = Args::next("service_id", &mut args.function_args.into_iter())?;
+
+            let service_id = {
+                let mut services = services.write();
+                let service_id = resolve_service_id(&services, &aliases.read(), service_id)?;
+                let service = services
+                    .get(&service_id)
+                    .ok_or_else(|| ServiceError::NoSuchService(service_id.clone()))?;
+                let user = &particle.init_user_id;
+                if service.owner_id.ne(user) && management_peer_id.ne(user) {
+                    return Err(Forbidden(particle.init_user_id, "remove".to_string()).into());
+                }
+
+                // remove persisted info first, so a failure leaves the service intact
+                // instead of bringing it back on the next restart
+                remove_persisted_service(&config.services_dir, &service_id)?;
+                // dropping the service frees its AppService
+                services.remove(&service_id);
+                service_id
+            };
+            aliases.write().retain(|_, id| *id != service_id);
+
+            let workdir = config.workdir.join(&service_id);
+            if let Err(err) = std::fs::remove_dir_all(&workdir) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(RemoveServiceWorkdir { path: workdir, err }.into());
+                }
+            }
+
+            log::info!("Service {} removed", service_id);
+            Ok(None)
+        })
+    }
+
+    /// Recreates `AppService` from the service's blueprint, keeping its id, owner and aliases.
+    /// Service's local dir is preserved between restarts.
+    /// Only the owner of the service or the management peer are allowed to restart it.
+    pub fn restart_service(&self) -> ParticleClosure {
+        let services = self.services.clone();
+        let aliases = self.aliases.clone();
+        let config = self.config.clone();
+        let modules = self.modules.clone();
+        let management_peer_id = self.management_peer_id.clone();
+
+        closure_params_opt(move |particle, args| {
+            let service_id: String = Args::next("service_id", &mut args.function_args.into_iter())?;
+
+            // take a snapshot of the service, so the map isn't locked while wasm is instantiated
+            let (service_id, blueprint_id, aliases_snapshot, owner_id, envs) = {
+                let services = services.read();
+                let service_id = resolve_service_id(&services, &aliases.read(), service_id)?;
+                let service = services
+                    .get(&service_id)
+                    .ok_or_else(|| ServiceError::NoSuchService(service_id.clone()))?;
+                let user = &particle.init_user_id;
+                if service.owner_id.ne(user) && management_peer_id.ne(user) {
+                    return Err(Forbidden(particle.init_user_id, "restart".to_string()).into());
+                }
+                (
+                    service_id,
+                    service.blueprint_id.clone(),
+                    service.aliases.clone(),
+                    service.owner_id.clone(),
+                    service.envs.clone(),
+                )
+            };
+
+            // limits stay the same, since neither node config nor the blueprint have changed
+            let (recreated, _) = create_app_service(
+                config.clone(),
+                &modules,
+                blueprint_id.clone(),
+                service_id.clone(),
+                aliases_snapshot,
+                owner_id.clone(),
+                envs,
+            )?;
+
+            // swap the service in, unless it was removed or upgraded meanwhile
+            let slot = {
+                let services = services.read();
+                let slot = match services.get(&service_id) {
+                    Some(service)
+                        if service.owner_id == owner_id && service.blueprint_id == blueprint_id =>
+                    {
+                        Some(service.service.clone())
+                    }
+                    _ => None,
+                };
+                // aliases could've been changed during the restart, persist the current ones
+                sync_persisted(&services, &config.services_dir, &service_id);
+                slot
+            };
+            // waits for a running call to finish, without holding the map
+            match slot {
+                Some(slot) => *slot.lock() = Some(recreated),
+                None => return Err(UpgradeConflict(service_id).into()),
+            }
+
+            log::info!("Service {} restarted", service_id);
+            Ok(None)
+        })
+    }
+
+    /// Moves service to another blueprint, keeping its id, owner, aliases and local dir.
+    /// If service can't be created from the new blueprint, it's left as it was.
+    /// Only the owner of the service is allowed to upgrade it.
+    pub fn upgrade_service(&self) -> ParticleClosure {
+        let services = self.services.clone();
+        let aliases = self.aliases.clone();
+        let config = self.config.clone();
+        let modules = self.modules.clone();
+
+        closure_params_opt(move |particle, args| {
+            let mut args = args.function_args.into_iter();
+            let service_id: String = Args::next("service_id", &mut args)?;
+            let blueprint_id: String = Args::next("blueprint_id", &mut args)?;
+
+            // take a snapshot of the service, so the map isn't locked while wasm is instantiated
+            let (service_id, previous, aliases_snapshot, owner_id, envs) = {
+                let services = services.read();
+                let service_id = resolve_service_id(&services, &aliases.read(), service_id)?;
+                let service = services
+                    .get(&service_id)
+                    .ok_or_else(|| ServiceError::NoSuchService(service_id.clone()))?;
+                if service.owner_id.ne(&particle.init_user_id) {
+                    return Err(Forbidden(particle.init_user_id, "upgrade".to_string()).into());
+                }
+                (
+                    service_id,
+                    service.blueprint_id.clone(),
+                    service.aliases.clone(),
+                    service.owner_id.clone(),
+                    service.envs.clone(),
+                )
+            };
+
+            let upgraded = create_app_service(
+                config.clone(),
+                &modules,
+                blueprint_id.clone(),
+                service_id.clone(),
+                aliases_snapshot,
+                owner_id.clone(),
+                envs,
+            );
+            let (upgraded, limits) = match upgraded {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    // persisted service may be already pointing to the new blueprint, revert it
+                    sync_persisted(&services.read(), &config.services_dir, &service_id);
+                    return Err(err.into());
+                }
+            };
+
+            // swap the service in, unless it was removed or upgraded meanwhile
+            let mut services = services.write();
+            match services.get_mut(&service_id) {
+                Some(service)
+                    if service.owner_id == owner_id && service.blueprint_id == previous =>
+                {
+                    *service.service.lock() = Some(upgraded);
+                    service.limits = limits;
+                    service.blueprint_id = blueprint_id.clone();
+                }
+                _ => {
+                    sync_persisted(&services, &config.services_dir, &service_id);
+                    return Err(UpgradeConflict(service_id).into());
+                }
+            }
+            // aliases could've been changed during the upgrade, persist the current ones
+            sync_persisted(&services, &config.services_dir, &service_id);
+
+            #[rustfmt::skip]
+            log::info!("Service {} upgraded from blueprint {} to {}", service_id, previous, blueprint_id);
+            Ok(None)
+        })
+    }
+
     pub fn add_alias(&self) -> ParticleClosure {
         let services = self.services.clone();
         let aliases = self.aliases.clone();
@@ -277,8 +573,9 @@ impl ParticleAppServices {
                 s.service_id.clone(),
                 s.aliases.clone(),
                 s.owner_id.clone(),
+                s.envs.clone(),
             );
-            let service = match service {
+            let (service, limits) = match service {
                 Ok(service) => service,
                 Err(err) => {
                     #[rustfmt::skip]
@@ -288,10 +585,13 @@ impl ParticleAppServices {
             };
 
             let service = Service {
-                service: Mutex::new(service),
+                service: Arc::new(Mutex::new(Some(service))),
                 blueprint_id: s.blueprint_id,
                 owner_id: s.owner_id,
                 aliases: s.aliases,
+                limits,
+                envs: s.envs,
+                disk_usage: <_>::default(),
             };
             let replaced = self.services.write().insert(s.service_id.clone(), service);
 
@@ -305,9 +605,118 @@ impl ParticleAppServices {
     }
 }
 
+/// Returns `id` if it is a service id, or service id that `id` is an alias for
+fn resolve_service_id(
+    services: &HashMap<String, Service>,
+    aliases: &HashMap<String, String>,
+    id: String,
+) -> Result<String, ServiceError> {
+    if services.contains_key(&id) {
+        return Ok(id);
+    }
+
+    aliases
+        .get(&id)
+        .cloned()
+        .ok_or(ServiceError::NoSuchService(id))
+}
+
+/// Writes persisted info of the service as it is in memory, or removes it if the service is gone.
+/// Used to overwrite changes `create_app_service` made to the persisted info.
+fn sync_persisted(services: &HashMap<String, Service>, services_dir: &Path, service_id: &str) {
+    let result = match services.get(service_id) {
+        Some(service) => {
+            let persisted = PersistedService::from_service(service_id.to_string(), service);
+            persist_service(services_dir, persisted).map_err(ServiceError::from)
+        }
+        None => remove_persisted_service(services_dir, service_id),
+    };
+    if let Err(err) = result {
+        log::error!("Error persisting service {}: {:?}", service_id, err);
+    }
+}
+
+/// Runs `call` on a separate thread, waiting for it at most `timeout`.
+/// Returns `service` back along with the result if the call finished in time. Otherwise the call
+/// keeps running in background, as wasm calls can't be interrupted, and `service` is passed
+/// to `finished_late` once the call is over.
+fn call_with_timeout<S, R>(
+    mut service: S,
+    timeout: Duration,
+    call: impl FnOnce(&mut S) -> R + Send + 'static,
+    finished_late: impl FnOnce(S) + Send + 'static,
+) -> Option<(S, R)>
+where
+    S: Send + 'static,
+    R: Send + 'static,
+{
+    let abandoned = Arc::new(Mutex::new(false));
+    let (outlet, inlet) = channel();
+    let handle = std::thread::spawn({
+        let abandoned = abandoned.clone();
+        move || {
+            let result = call(&mut service);
+            let abandoned = abandoned.lock();
+            if *abandoned {
+                finished_late(service);
+            } else {
+                outlet.send((service, result)).ok();
+            }
+        }
+    });
+
+    let finished = match inlet.recv_timeout(timeout) {
+        Ok(finished) => Some(finished),
+        Err(RecvTimeoutError::Timeout) => {
+            // call may finish right at the timeout, so check for the result under the lock
+            let mut abandoned = abandoned.lock();
+            match inlet.try_recv() {
+                Ok(finished) => Some(finished),
+                Err(TryRecvError::Empty) => {
+                    *abandoned = true;
+                    return None;
+                }
+                Err(TryRecvError::Disconnected) => None,
+            }
+        }
+        Err(RecvTimeoutError::Disconnected) => None,
+    };
+
+    match finished {
+        Some(finished) => Some(finished),
+        // call has panicked, propagate the panic as if the call was made on this thread
+        None => match handle.join() {
+            Err(panic) => std::panic::resume_unwind(panic),
+            Ok(_) => unreachable!("call thread always sends result before finishing"),
+        },
+    }
+}
+
+/// Total size of files in `dir`, recursively. Files that can't be read are ignored.
+fn dir_size(dir: &Path) -> u64 {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
 fn get_service_interface(service: &Service, service_id: &str) -> Result<JValue, ServiceError> {
     let lock = service.lock();
-    let interface = lock.get_interface();
+    let interface = lock
+        .as_ref()
+        .ok_or_else(|| ServiceBroken {
+            service_id: service_id.to_string(),
+        })?
+        .get_interface();
 
     let descriptor = VmDescriptor {
         interface,
@@ -324,12 +733,15 @@ fn get_service_interface(service: &Service, service_id: &str) -> Result<JValue,
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
 
     use libp2p_core::identity::Keypair;
     use libp2p_core::PeerId;
     use serde_json::Value as JValue;
     use tempdir::TempDir;
 
+    use crate::app_services::{call_with_timeout, dir_size};
     use crate::ParticleAppServices;
     use host_closure::{Args, ParticleParameters};
     use particle_modules::ModuleRepository;
@@ -350,10 +762,13 @@ mod tests {
             local_pid,
             base_dir.into_path(),
             HashMap::new(),
+            <_>::default(),
             management_pid,
+            <_>::default(),
+            <_>::default(),
         )
         .unwrap();
-        let repo = ModuleRepository::new(module_dir.path(), module_dir.path());
+        let repo = ModuleRepository::new(module_dir.path(), module_dir.path(), module_dir.path());
 
         ParticleAppServices::new(config, repo)
     }
@@ -424,6 +839,84 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_remove_service_no_service() {
+        let local_pid = create_pid();
+        let management_pid = create_pid();
+        let pas = create_pas(local_pid, management_pid);
+
+        let params = params(management_pid);
+        let args = create_args(vec![JValue::String("1".to_string())]);
+        let resp = response_to_return(pas.remove_service()(params, args).unwrap());
+
+        assert_eq!(resp.ret_code, 1);
+        assert!(
+            resp.error.contains("Service with id") && resp.error.contains("not found"),
+            "Closure should not found a service to remove `{}`",
+            resp.error
+        );
+    }
+
+    #[test]
+    fn test_upgrade_service_no_service() {
+        let local_pid = create_pid();
+        let management_pid = create_pid();
+        let pas = create_pas(local_pid, management_pid);
+
+        let params = params(management_pid);
+        let args = create_args(vec![
+            JValue::String("1".to_string()),
+            JValue::String("2".to_string()),
+        ]);
+        let resp = response_to_return(pas.upgrade_service()(params, args).unwrap());
+
+        assert_eq!(resp.ret_code, 1);
+        assert!(
+            resp.error.contains("Service with id") && resp.error.contains("not found"),
+            "Closure should not found a service to upgrade `{}`",
+            resp.error
+        );
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let dir = TempDir::new("test").unwrap();
+        assert_eq!(dir_size(&dir.path().join("missing")), 0);
+
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("a"), [0u8; 10]).unwrap();
+        std::fs::write(dir.path().join("nested").join("b"), [0u8; 5]).unwrap();
+        assert_eq!(dir_size(dir.path()), 15);
+    }
+
+    #[test]
+    fn test_call_with_timeout() {
+        let (outlet, inlet) = channel();
+        let finished = call_with_timeout(
+            1,
+            Duration::from_secs(10),
+            |s| {
+                *s += 1;
+                *s * 10
+            },
+            |_| unreachable!("call finished in time"),
+        );
+        assert_eq!(finished, Some((2, 20)));
+
+        let hung = call_with_timeout(
+            1,
+            Duration::from_millis(100),
+            |s| {
+                std::thread::sleep(Duration::from_millis(500));
+                *s += 1;
+            },
+            move |s| outlet.send(s).unwrap(),
+        );
+        assert_eq!(hung, None);
+        // service is given back once the abandoned call finishes
+        assert_eq!(inlet.recv_timeout(Duration::from_secs(5)), Ok(2));
+    }
+
     // TODO: add more tests
     //       - add alias success & fail with service collision & test on rewriting alias
     //       - create_service success & fail
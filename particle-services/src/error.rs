@@ -22,6 +22,7 @@ use json_utils::err_as_value;
 
 use serde_json::Value as JValue;
 use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -48,6 +49,18 @@ pub enum ServiceError {
         #[source]
         err: toml::de::Error,
     },
+    #[error("Error removing persisted service {path:?}: {err}")]
+    RemovePersistedService {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Error removing service workdir {path:?}: {err}")]
+    RemoveServiceWorkdir {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
     #[error("Error creating directory for persisted services {path:?}: {err}")]
     CreateServicesDir {
         path: PathBuf,
@@ -58,6 +71,36 @@ pub enum ServiceError {
     CorruptedFaaSInterface(#[source] serde_json::Error),
     #[error("Error parsing arguments on call_service: {0}")]
     ArgParseError(#[source] ArgsError),
+    #[error(
+        "Module {module} requires {requested} memory pages, but services are limited to {limit}"
+    )]
+    MemoryLimitExceeded {
+        module: String,
+        requested: u32,
+        limit: u32,
+    },
+    #[error("Service {service_id} is busy with other calls for longer than {timeout:?}")]
+    ServiceBusy {
+        service_id: String,
+        timeout: Duration,
+    },
+    #[error("Service {0} was changed or removed while it was being upgraded or restarted")]
+    UpgradeConflict(String),
+    #[error("Call to service {service_id} didn't finish in {timeout:?}")]
+    CallTimeout {
+        service_id: String,
+        timeout: Duration,
+    },
+    #[error("Service {service_id} is unavailable until its timed out call finishes")]
+    ServiceBroken { service_id: String },
+    #[error(
+        "Service {service_id} uses {used} bytes of disk, exceeding its quota of {quota} bytes"
+    )]
+    DiskQuotaExceeded {
+        service_id: String,
+        used: u64,
+        quota: u64,
+    },
 }
 
 impl From<AppServiceError> for ServiceError {
@@ -16,7 +16,7 @@
 
 use crate::error::ServiceError;
 use crate::error::ServiceError::{
-    CreateServicesDir, DeserializePersistedService, ReadPersistedService,
+    CreateServicesDir, DeserializePersistedService, ReadPersistedService, RemovePersistedService,
 };
 
 use config_utils::create_dirs;
@@ -24,6 +24,7 @@ use particle_modules::{is_service, list_files, service_file_name, ModuleError};
 
 use crate::app_services::Service;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 
 // TODO: all fields could be references, but I don't know how to achieve that
@@ -37,6 +38,9 @@ pub struct PersistedService {
     // Old versions of PersistedService may omit `owner` field, tolerate that
     #[serde(default)]
     pub owner_id: String,
+    /// Envs set by the owner on service creation
+    #[serde(default)]
+    pub envs: HashMap<String, String>,
 }
 
 impl PersistedService {
@@ -45,12 +49,14 @@ impl PersistedService {
         blueprint_id: String,
         aliases: Vec<String>,
         owner_id: String,
+        envs: HashMap<String, String>,
     ) -> Self {
         Self {
             service_id,
             blueprint_id,
             aliases,
             owner_id,
+            envs,
         }
     }
 
@@ -60,6 +66,7 @@ impl PersistedService {
             service.blueprint_id.clone(),
             service.aliases.clone(),
             service.owner_id.clone(),
+            service.envs.clone(),
         )
     }
 }
@@ -76,6 +83,17 @@ pub fn persist_service(
     std::fs::write(&path, bytes).map_err(|err| WriteConfig { path, err })
 }
 
+/// Remove persisted service info from disk, so it isn't recreated after restart
+pub fn remove_persisted_service(services_dir: &Path, service_id: &str) -> Result<(), ServiceError> {
+    let path = services_dir.join(service_file_name(service_id));
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(RemovePersistedService { path, err })
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Load info about persisted services from disk, and create `AppService` for each of them
 pub fn load_persisted_services(services_dir: &Path) -> Vec<Result<PersistedService, ServiceError>> {
     // Load all persisted service file names
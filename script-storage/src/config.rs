@@ -15,9 +15,11 @@
  */
 
 use fluence_libp2p::PeerId;
+use libp2p::identity::ed25519::Keypair;
+use std::path::PathBuf;
 use std::time::Duration;
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug)]
 pub struct ScriptStorageConfig {
     /// Minimal interval of script execution
     pub timer_resolution: Duration,
@@ -26,4 +28,8 @@ pub struct ScriptStorageConfig {
     /// ttl to set in generated particles
     pub particle_ttl: Duration,
     pub peer_id: PeerId,
+    /// Key pair of the current node, used to sign generated particles
+    pub key_pair: Keypair,
+    /// Dir to persist scripts, so they survive restarts
+    pub scripts_dir: PathBuf,
 }
@@ -1,9 +1,12 @@
 #![feature(hash_drain_filter)]
 
 mod config;
+mod persistence;
+mod schedule;
 mod script_storage;
 
 pub use crate::config::ScriptStorageConfig;
+pub use crate::schedule::{Cron, Schedule};
 pub use crate::script_storage::ScriptStorageApi;
 pub use crate::script_storage::ScriptStorageBackend;
 pub use crate::script_storage::ScriptStorageError;
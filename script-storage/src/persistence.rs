@@ -0,0 +1,250 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::schedule::Schedule;
+use crate::script_storage::{Script, ScriptId};
+use crate::ScriptStorageError;
+use crate::ScriptStorageError::{
+    CreateScriptsDir, DeserializePersistedScript, ReadPersistedScript, RemovePersistedScript,
+    SerializePersistedScript, WritePersistedScript,
+};
+
+use config_utils::create_dirs;
+use fluence_libp2p::PeerId;
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersistedScript {
+    pub id: String,
+    pub src: String,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub interval: Option<Duration>,
+    pub owner: String,
+    // Failures counter could be omitted by hand-written files, tolerate that
+    #[serde(default)]
+    pub failures: u8,
+    // Old versions of PersistedScript may omit schedule fields, tolerate that
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub start_at: Option<u64>,
+    #[serde(default)]
+    pub end_at: Option<u64>,
+    #[serde(default)]
+    pub max_runs: Option<u32>,
+    #[serde(default)]
+    pub runs: u32,
+    /// UNIX timestamp in milliseconds of the last execution, so schedule is kept after restart
+    #[serde(default)]
+    pub executed_at: Option<u64>,
+}
+
+impl PersistedScript {
+    pub fn from_script(id: &ScriptId, script: &Script) -> Self {
+        let id: &String = id.borrow();
+        Self {
+            id: id.clone(),
+            src: script.src.clone(),
+            interval: script.schedule.interval,
+            owner: script.owner.to_string(),
+            failures: script.failures,
+            cron: script.schedule.cron.as_ref().map(|c| c.to_string()),
+            start_at: script.schedule.start_at,
+            end_at: script.schedule.end_at,
+            max_runs: script.schedule.max_runs,
+            runs: script.runs,
+            executed_at: script.executed_at,
+        }
+    }
+
+    pub fn into_script(self) -> Result<(ScriptId, Script), ScriptStorageError> {
+        let owner = PeerId::from_str(&self.owner).map_err(|_| {
+            ScriptStorageError::InvalidPersistedOwner {
+                id: self.id.clone(),
+                owner: self.owner.clone(),
+            }
+        })?;
+        let cron = self.cron.as_deref().map(Schedule::parse_cron).transpose()?;
+        let schedule = Schedule {
+            interval: self.interval,
+            cron,
+            start_at: self.start_at,
+            end_at: self.end_at,
+            max_runs: self.max_runs,
+        };
+        let mut script = Script::new(self.src, schedule, owner);
+        script.failures = self.failures;
+        script.runs = self.runs;
+        script.executed_at = self.executed_at;
+
+        Ok((ScriptId::new(self.id), script))
+    }
+}
+
+pub fn script_file_name(script_id: &str) -> String {
+    format!("{}_script.toml", script_id)
+}
+
+pub fn is_script(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map_or(false, |n| n.ends_with("_script.toml"))
+}
+
+/// Persist script to disk, so it is reloaded after restart
+pub fn persist_script(
+    scripts_dir: &Path,
+    persisted_script: PersistedScript,
+) -> Result<(), ScriptStorageError> {
+    let path = scripts_dir.join(script_file_name(&persisted_script.id));
+    let bytes = toml::to_vec(&persisted_script).map_err(SerializePersistedScript)?;
+    std::fs::write(&path, bytes).map_err(|err| WritePersistedScript { path, err })
+}
+
+/// Remove persisted script from disk, so it isn't reloaded after restart
+pub fn remove_persisted_script(
+    scripts_dir: &Path,
+    script_id: &str,
+) -> Result<(), ScriptStorageError> {
+    let path = scripts_dir.join(script_file_name(script_id));
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(RemovePersistedScript { path, err })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Load info about persisted scripts from disk
+pub fn load_persisted_scripts(
+    scripts_dir: &Path,
+) -> Vec<Result<PersistedScript, ScriptStorageError>> {
+    // Load all persisted script file names
+    let files = match std::fs::read_dir(scripts_dir) {
+        Ok(dir) => dir.filter_map(|p| p.ok()?.path().into()),
+        Err(_) => {
+            // Attempt to create directory and exit
+            return create_dirs(&[&scripts_dir])
+                .map_err(|err| CreateScriptsDir {
+                    path: scripts_dir.to_path_buf(),
+                    err,
+                })
+                .err()
+                .into_iter()
+                .map(Err)
+                .collect();
+        }
+    };
+
+    files
+        .filter(|p| is_script(p))
+        .map(|file| {
+            // Load script's persisted info
+            let bytes = std::fs::read(&file).map_err(|err| ReadPersistedScript {
+                err,
+                path: file.to_path_buf(),
+            })?;
+            let script =
+                toml::from_slice(bytes.as_slice()).map_err(|err| DeserializePersistedScript {
+                    err,
+                    path: file.to_path_buf(),
+                })?;
+
+            Ok(script)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::persistence::{is_script, load_persisted_scripts, persist_script, PersistedScript};
+    use crate::schedule::Schedule;
+    use crate::script_storage::{Script, ScriptId};
+
+    use fluence_libp2p::RandomPeerId;
+    use std::path::Path;
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    #[test]
+    fn is_script_file() {
+        let path = Path::new("/.fluence/services/scripts/0d5a3d4e_script.toml");
+        assert!(is_script(path));
+
+        let path = Path::new("/.fluence/services/services/0d5a3d4e_service.toml");
+        assert!(!is_script(path));
+    }
+
+    #[test]
+    fn tolerate_missing_fields() {
+        let script = r#"
+            id = "0d5a3d4e"
+            src = "(null)"
+            owner = "12D3KooWEXNUbCXooUwHrHBbrmjsrpHXoEphPwbjQXEGyzbqKnE9"
+        "#;
+        let script: PersistedScript = toml::from_str(script).expect("deserialize script");
+        assert_eq!(script.interval, None);
+        assert_eq!(script.failures, 0);
+
+        let script = r#"
+            id = "0d5a3d4e"
+            src = "(null)"
+            interval = "1m"
+            owner = "12D3KooWEXNUbCXooUwHrHBbrmjsrpHXoEphPwbjQXEGyzbqKnE9"
+            failures = 2
+        "#;
+        let script: PersistedScript = toml::from_str(script).expect("deserialize script");
+        assert_eq!(script.interval, Some(Duration::from_secs(60)));
+        assert_eq!(script.failures, 2);
+        assert_eq!(script.cron, None);
+        assert_eq!(script.runs, 0);
+        assert_eq!(script.executed_at, None);
+    }
+
+    #[test]
+    fn persist_and_load_script() {
+        let dir = TempDir::new("scripts").expect("create temp dir");
+        let id = ScriptId::new("0d5a3d4e".to_string());
+        let schedule = Schedule::interval(Some(Duration::from_secs(60)));
+        let mut script = Script::new("(null)".to_string(), schedule, RandomPeerId::random());
+        script.runs = 3;
+        script.executed_at = Some(1_000_000);
+
+        persist_script(dir.path(), PersistedScript::from_script(&id, &script))
+            .expect("persist script");
+        let mut loaded = load_persisted_scripts(dir.path());
+        assert_eq!(loaded.len(), 1);
+        let (loaded_id, loaded) = loaded
+            .pop()
+            .unwrap()
+            .and_then(PersistedScript::into_script)
+            .expect("load script");
+
+        assert_eq!(loaded_id, id);
+        assert_eq!(loaded.src, script.src);
+        assert_eq!(loaded.owner, script.owner);
+        assert_eq!(loaded.runs, 3);
+        assert_eq!(loaded.executed_at, Some(1_000_000));
+        // next run is scheduled relative to the last execution, not to the restart
+        assert_eq!(loaded.deadline(), Some(1_060_000));
+    }
+}
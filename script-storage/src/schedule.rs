@@ -0,0 +1,140 @@
+/*
+ * Copyright 2020 Fluence Labs Limited
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::ScriptStorageError;
+
+use chrono::{TimeZone, Utc};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use cron::Schedule as Cron;
+
+/// Defines when and how many times a script is executed
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    /// Interval at which to execute the script
+    pub interval: Option<Duration>,
+    /// Cron expression (with seconds) defining fire times. Takes precedence over `interval`
+    pub cron: Option<Cron>,
+    /// UNIX timestamp in seconds, script isn't executed before that time
+    pub start_at: Option<u64>,
+    /// UNIX timestamp in seconds, script is removed once there are no fire times left before it
+    pub end_at: Option<u64>,
+    /// Script is removed after it was executed that many times
+    pub max_runs: Option<u32>,
+}
+
+impl Schedule {
+    /// Execute script at the given interval.
+    /// If interval is None, that means the script will be executed only once
+    pub fn interval(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            ..<_>::default()
+        }
+    }
+
+    pub fn parse_cron(expression: &str) -> Result<Cron, ScriptStorageError> {
+        Cron::from_str(expression).map_err(|err| ScriptStorageError::InvalidCron {
+            expression: expression.to_string(),
+            err: err.to_string(),
+        })
+    }
+
+    /// Calculates UNIX timestamp in milliseconds at which script should be executed next.
+    /// `anchor` is the time script was added at, it's used until the first execution.
+    /// Returns None if script shouldn't be executed anymore.
+    pub fn next_fire(&self, anchor: u64, executed_at: Option<u64>, runs: u32) -> Option<u64> {
+        if self.max_runs.map_or(false, |max| runs >= max) {
+            return None;
+        }
+
+        let start_at = self.start_at.map_or(0, |s| s.saturating_mul(1000));
+        let next = if let Some(cron) = &self.cron {
+            // cron yields fire times strictly after the given one, so step back to include start_at
+            let after = executed_at
+                .unwrap_or(anchor)
+                .max(start_at.saturating_sub(1));
+            let after = Utc.timestamp_millis(after as i64);
+            cron.after(&after).next()?.timestamp_millis() as u64
+        } else if let Some(interval) = self.interval {
+            match executed_at {
+                Some(executed_at) => start_at.max(executed_at + interval.as_millis() as u64),
+                None => start_at,
+            }
+        } else if executed_at.is_none() {
+            start_at
+        } else {
+            // single shot was already executed
+            return None;
+        };
+
+        match self.end_at {
+            Some(end_at) if next > end_at.saturating_mul(1000) => None,
+            _ => Some(next),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schedule::Schedule;
+    use std::time::Duration;
+
+    #[test]
+    fn single_shot() {
+        let schedule = Schedule::interval(None);
+        assert_eq!(schedule.next_fire(1000, None, 0), Some(0));
+        assert_eq!(schedule.next_fire(1000, Some(2000), 1), None);
+
+        let schedule = Schedule {
+            start_at: Some(10),
+            ..Schedule::interval(None)
+        };
+        assert_eq!(schedule.next_fire(1000, None, 0), Some(10_000));
+    }
+
+    #[test]
+    fn interval() {
+        let schedule = Schedule {
+            end_at: Some(10),
+            max_runs: Some(3),
+            ..Schedule::interval(Some(Duration::from_secs(3)))
+        };
+        assert_eq!(schedule.next_fire(1000, None, 0), Some(0));
+        assert_eq!(schedule.next_fire(1000, Some(2000), 1), Some(5000));
+        assert_eq!(schedule.next_fire(1000, Some(5000), 2), Some(8000));
+        // max runs reached
+        assert_eq!(schedule.next_fire(1000, Some(8000), 3), None);
+        // out of end_at
+        assert_eq!(schedule.next_fire(1000, Some(8000), 2), None);
+    }
+
+    #[test]
+    fn cron() {
+        let schedule = Schedule {
+            cron: Some(Schedule::parse_cron("0 * * * * *").expect("parse cron")),
+            start_at: Some(120),
+            ..<_>::default()
+        };
+        // first fire is at the first minute after start_at
+        assert_eq!(schedule.next_fire(1000, None, 0), Some(120_000));
+        assert_eq!(schedule.next_fire(1000, Some(120_000), 1), Some(180_000));
+        assert_eq!(schedule.next_fire(200_000, None, 0), Some(240_000));
+
+        assert!(Schedule::parse_cron("not a cron").is_err());
+    }
+}
@@ -14,6 +14,10 @@
  * limitations under the License.
  */
 
+use crate::persistence::{
+    load_persisted_scripts, persist_script, remove_persisted_script, PersistedScript,
+};
+use crate::schedule::Schedule;
 use crate::ScriptStorageConfig;
 
 use async_unlock::unlock;
@@ -33,13 +37,19 @@ use std::{
     borrow::Borrow,
     collections::{hash_map::Entry, HashMap},
     convert::identity,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::{Duration, Instant},
+    time::Instant,
 };
 use thiserror::Error;
 
 #[derive(Clone, Hash, Debug, PartialEq, Eq)]
 pub struct ScriptId(Arc<String>);
+impl ScriptId {
+    pub fn new(id: String) -> Self {
+        Self(Arc::new(id))
+    }
+}
 impl Borrow<String> for ScriptId {
     fn borrow(&self) -> &String {
         self.0.borrow()
@@ -50,28 +60,35 @@ impl Borrow<String> for ScriptId {
 pub struct Script {
     pub src: String,
     pub failures: u8,
-    /// Interval at which to execute this script.
-    /// If None, that means the script will be executed only once
-    pub interval: Option<Duration>,
-    pub executed_at: Option<Instant>,
+    /// Defines when and how many times to execute this script
+    pub schedule: Schedule,
+    /// UNIX timestamp in milliseconds at which the script was added or loaded from disk
+    pub added_at: u64,
+    /// UNIX timestamp in milliseconds of the last execution
+    pub executed_at: Option<u64>,
+    /// Number of times this script was executed
+    pub runs: u32,
     pub owner: PeerId,
 }
 
 impl Script {
-    pub fn new(src: String, interval: Option<Duration>, owner: PeerId) -> Self {
+    pub fn new(src: String, schedule: Schedule, owner: PeerId) -> Self {
         Self {
             src,
-            interval,
+            schedule,
             failures: 0,
+            added_at: now_ms() as u64,
             executed_at: None,
+            runs: 0,
             owner,
         }
     }
 
-    pub fn deadline(&self) -> Option<Instant> {
-        let interval = self.interval?;
-        let executed_at = self.executed_at?;
-        Some(executed_at + interval)
+    /// UNIX timestamp in milliseconds at which script should be executed next.
+    /// None if the script shouldn't be executed anymore.
+    pub fn deadline(&self) -> Option<u64> {
+        self.schedule
+            .next_fire(self.added_at, self.executed_at, self.runs)
     }
 }
 
@@ -87,7 +104,7 @@ enum Command {
     AddScript {
         uuid: String,
         script: String,
-        interval: Option<Duration>,
+        schedule: Schedule,
         owner: PeerId,
     },
     RemoveScript {
@@ -138,22 +155,25 @@ impl ScriptStorageBackend {
             let sent_particles = self.sent_particles;
             let pool = self.connection_pool;
             let config = self.config;
-            let max_failures = self.config.max_failures;
+            let max_failures = config.max_failures;
+            let scripts_dir = &config.scripts_dir;
+
+            load_scripts(scripts_dir, &scripts).await;
 
             let mut failed_particles = self.failed_particles.fuse();
             let mut inlet = self.inlet.fuse();
-            let mut timer = async_std::stream::interval(self.config.timer_resolution).fuse();
+            let mut timer = async_std::stream::interval(config.timer_resolution).fuse();
 
             loop {
                 select! {
                     command = inlet.select_next_some() => {
-                        execute_command(command, &scripts).await;
+                        execute_command(command, &scripts, scripts_dir).await;
                     },
                     failed = failed_particles.select_next_some() => {
-                        remove_failed_scripts(failed, &sent_particles, &scripts, max_failures).await;
+                        remove_failed_scripts(failed, &sent_particles, &scripts, max_failures, scripts_dir).await;
                     },
                     _ = timer.select_next_some() => {
-                        execute_scripts(&pool, &scripts, &sent_particles, config).await;
+                        execute_scripts(&pool, &scripts, &sent_particles, &config).await;
                         cleanup(&sent_particles).await;
                     }
                 }
@@ -162,38 +182,83 @@ impl ScriptStorageBackend {
     }
 }
 
+/// Load persisted scripts from disk, so they survive node restarts
+async fn load_scripts(scripts_dir: &Path, scripts: &Mutex<HashMap<ScriptId, Script>>) {
+    let loaded = load_persisted_scripts(scripts_dir)
+        .into_iter()
+        .filter_map(|r| match r.and_then(PersistedScript::into_script) {
+            Ok(script) => script.into(),
+            Err(err) => {
+                log::warn!("Error loading one of persisted scripts: {:?}", err);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    log::info!("Loaded {} persisted scripts", loaded.len());
+    unlock(scripts, |scripts| scripts.extend(loaded)).await;
+}
+
+/// Write script to disk, logging any errors
+fn persist(scripts_dir: &Path, id: &ScriptId, script: &Script) {
+    let persisted = PersistedScript::from_script(id, script);
+    if let Err(err) = persist_script(scripts_dir, persisted) {
+        log::warn!("Error persisting script {:?}: {:?}", id, err);
+    }
+}
+
+/// Remove script from disk, logging any errors
+fn unpersist(scripts_dir: &Path, id: &ScriptId) {
+    let id: &String = id.borrow();
+    if let Err(err) = remove_persisted_script(scripts_dir, id) {
+        log::warn!("Error removing persisted script {}: {:?}", id, err);
+    }
+}
+
 async fn execute_scripts(
     pool: &ConnectionPoolApi,
     scripts: &Mutex<HashMap<ScriptId, Script>>,
     sent_particles: &Mutex<HashMap<ParticleId, SentParticle>>,
-    config: ScriptStorageConfig,
+    config: &ScriptStorageConfig,
 ) {
     let now = Instant::now();
     let now_u64 = now_ms() as u64;
 
-    // Remove all scripts without interval, they will be executing only once
-    let single_shots: Vec<_> = unlock(scripts, |scripts| {
-        scripts.drain_filter(|_, s| s.interval.is_none()).collect()
-    })
-    .await;
-
     // Take and clone all scripts that are ready to be executed
-    let scripts: HashMap<ScriptId, Script> = unlock(scripts, |scripts| {
+    let ready: Vec<(ScriptId, Script)> = unlock(scripts, |scripts| {
         scripts
             .iter_mut()
-            .filter(|(_, script)| script.deadline().map_or(true, |deadline| deadline <= now))
+            .filter(|(_, script)| script.deadline().map_or(false, |d| d <= now_u64))
             .map(|(id, s)| {
                 // mark script as executed at the current timestamp
-                s.executed_at = Some(now);
+                s.executed_at = Some(now_u64);
+                s.runs += 1;
                 (id.clone(), s.clone())
             })
             .collect()
     })
     .await;
-    // concatenate single shots with other scripts
-    let scripts = single_shots.into_iter().chain(scripts);
 
-    for (script_id, script) in scripts {
+    // Remove all scripts that won't be executed anymore: single shots, expired or out of runs
+    let finished: Vec<_> = unlock(scripts, |scripts| {
+        scripts
+            .drain_filter(|_, s| s.deadline().is_none())
+            .collect()
+    })
+    .await;
+    for (id, _) in finished.iter() {
+        unpersist(&config.scripts_dir, id);
+    }
+
+    // Persist last execution time and run counters of the remaining scripts,
+    // so schedule and max_runs are respected after restart
+    for (id, script) in ready.iter() {
+        if !finished.iter().any(|(f, _)| f == id) {
+            persist(&config.scripts_dir, id, script);
+        }
+    }
+
+    for (script_id, script) in ready {
         let particle_id = format!("auto_{}", uuid::Uuid::new_v4());
 
         // Save info about sent particle to account for failures
@@ -207,7 +272,7 @@ async fn execute_scripts(
         .await;
 
         // Send particle to the current node
-        let particle = Particle {
+        let mut particle = Particle {
             id: particle_id,
             init_peer_id: config.peer_id,
             timestamp: now_u64,
@@ -216,21 +281,27 @@ async fn execute_scripts(
             signature: vec![],
             data: vec![],
         };
+        particle.sign(&config.key_pair);
         let contact = Contact::new(config.peer_id, vec![]);
         pool.send(contact, particle).await;
     }
 }
 
-async fn execute_command(command: Command, scripts: &Mutex<HashMap<ScriptId, Script>>) {
+async fn execute_command(
+    command: Command,
+    scripts: &Mutex<HashMap<ScriptId, Script>>,
+    scripts_dir: &Path,
+) {
     match command {
         Command::AddScript {
             uuid,
             script,
-            interval,
+            schedule,
             owner,
         } => {
             let uuid = ScriptId(Arc::new(uuid));
-            let script = Script::new(script, interval, owner);
+            let script = Script::new(script, schedule, owner);
+            persist(scripts_dir, &uuid, &script);
             unlock(scripts, |scripts| scripts.insert(uuid, script)).await;
         }
         Command::RemoveScript {
@@ -240,7 +311,7 @@ async fn execute_command(command: Command, scripts: &Mutex<HashMap<ScriptId, Scr
             force,
         } => {
             let uuid = ScriptId(Arc::new(uuid));
-            let removed = unlock(scripts, |scripts| match scripts.entry(uuid) {
+            let removed = unlock(scripts, |scripts| match scripts.entry(uuid.clone()) {
                 Entry::Vacant(_) => Ok(false),
                 Entry::Occupied(e) if force || e.get().owner == actor => {
                     e.remove();
@@ -249,6 +320,9 @@ async fn execute_command(command: Command, scripts: &Mutex<HashMap<ScriptId, Scr
                 Entry::Occupied(_) => Err(ScriptStorageError::PermissionDenied),
             })
             .await;
+            if let Ok(true) = removed {
+                unpersist(scripts_dir, &uuid);
+            }
             outlet.send(removed).ok();
         }
         Command::ListScripts { outlet } => {
@@ -263,16 +337,20 @@ async fn remove_failed_scripts(
     sent_particles: &Mutex<HashMap<ParticleId, SentParticle>>,
     scripts: &Mutex<HashMap<ScriptId, Script>>,
     max_failures: u8,
+    scripts_dir: &Path,
 ) {
     let sent = unlock(sent_particles, |sent| sent.remove(&particle_id)).await;
     if let Some(SentParticle { script_id, .. }) = sent {
         unlock(scripts, |scripts| {
-            if let Entry::Occupied(entry) = scripts.entry(script_id) {
+            if let Entry::Occupied(entry) = scripts.entry(script_id.clone()) {
                 let failures = entry.get().failures;
                 if failures + 1 < max_failures {
-                    entry.into_mut().failures += 1;
+                    let script = entry.into_mut();
+                    script.failures += 1;
+                    persist(scripts_dir, &script_id, script);
                 } else {
                     entry.remove();
+                    unpersist(scripts_dir, &script_id);
                 }
             }
         })
@@ -303,6 +381,48 @@ pub enum ScriptStorageError {
         "ScriptStorageError::PermissionDenied: only the owner (creator) of a script can remove it"
     )]
     PermissionDenied,
+    #[error("ScriptStorageError::SerializePersistedScript: can't serialize script to TOML: {0}")]
+    SerializePersistedScript(#[source] toml::ser::Error),
+    #[error("ScriptStorageError::WritePersistedScript: error writing script to {path:?}: {err}")]
+    WritePersistedScript {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("ScriptStorageError::RemovePersistedScript: error removing script {path:?}: {err}")]
+    RemovePersistedScript {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("ScriptStorageError::ReadPersistedScript: error reading script from {path:?}: {err}")]
+    ReadPersistedScript {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error(
+        "ScriptStorageError::DeserializePersistedScript: error deserializing script from {path:?}: {err}"
+    )]
+    DeserializePersistedScript {
+        path: PathBuf,
+        #[source]
+        err: toml::de::Error,
+    },
+    #[error(
+        "ScriptStorageError::CreateScriptsDir: error creating directory for persisted scripts {path:?}: {err}"
+    )]
+    CreateScriptsDir {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error(
+        "ScriptStorageError::InvalidPersistedOwner: script {id} has invalid owner peer id '{owner}'"
+    )]
+    InvalidPersistedOwner { id: String, owner: String },
+    #[error("ScriptStorageError::InvalidCron: invalid cron expression '{expression}': {err}")]
+    InvalidCron { expression: String, err: String },
 }
 
 impl ScriptStorageApi {
@@ -315,7 +435,7 @@ impl ScriptStorageApi {
     pub fn add_script(
         &self,
         script: String,
-        interval: Option<Duration>,
+        schedule: Schedule,
         owner: PeerId,
     ) -> Result<String, ScriptStorageError> {
         let uuid = uuid::Uuid::new_v4().to_string();
@@ -323,7 +443,7 @@ impl ScriptStorageApi {
         self.send(Command::AddScript {
             uuid: uuid.clone(),
             script,
-            interval,
+            schedule,
             owner,
         })?;
 